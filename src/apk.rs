@@ -1,5 +1,6 @@
 use crate::command::Command;
 use crate::settings::Settings;
+use crate::utils;
 use std::error::Error;
 
 pub struct Apk {
@@ -64,8 +65,8 @@ impl Apk {
 
         Command::run(
             get_rootfs,
-            None,
-            Some(format!("{cmd} {}", self.remaining_args.join(" "))),
+            Vec::new(),
+            Some(format!("{cmd} {}", self.remaining_args.iter().map(|a| utils::shell_quote(a)).collect::<Vec<_>>().join(" "))),
             true,
             true,
             false,
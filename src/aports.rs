@@ -1,5 +1,6 @@
 use crate::command::Command;
 use crate::parse_key_value;
+use crate::registry::Registry;
 use crate::settings::Settings;
 use crate::utils;
 use crate::utils::_parse_key_value;
@@ -50,6 +51,7 @@ impl Aports {
         }
     }
 
+    #[allow(clippy::unnecessary_lazy_evaluations, clippy::obfuscated_if_else)]
     pub fn run(&self) -> Result<(), Box<dyn Error>> {
         let mut args: VecDeque<_> = self.remaining_args.clone().into();
         if args.is_empty() {
@@ -99,6 +101,14 @@ impl Aports {
                     get_pkg.push(parse_key_value!("aports", "package", arg, args.pop_front().unwrap_or_default())?.unwrap());
                     collect_args!(args, get_pkg);
                 }
+                a if a.starts_with("--env=") => {
+                    let env = parse_key_value!("aports", "name", arg)?.unwrap();
+                    rootfs_dir = Registry::resolve(&env)?;
+                }
+                "--env" => {
+                    let env = parse_key_value!("aports", "name", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                    rootfs_dir = Registry::resolve(&env)?;
+                }
                 a if a.starts_with("--rootfs=") => {
                     rootfs_dir = parse_key_value!("aports", "directory", arg)?.unwrap();
                 }
@@ -126,7 +136,7 @@ impl Aports {
                 git fetch --depth=1 --filter=tree:0
                 git ls-tree -r HEAD --name-only | grep -E \"(community|main|testing)\" > ../aports-database
             ".to_string());
-            Command::run(rootfs_dir.clone(), None, cmd, true, true, false)?;
+            Command::run(rootfs_dir.clone(), Vec::new(), cmd, true, true, false)?;
 
             if search_pkg.is_empty() && get_pkg.is_empty() {
                 return Ok(());
@@ -182,7 +192,7 @@ impl Aports {
                 apkbuild_dirs.join(" ")
             ));
 
-            Command::run(rootfs_dir.clone(), None, cmd, true, true, false)?;
+            Command::run(rootfs_dir.clone(), Vec::new(), cmd, true, true, false)?;
 
             apkbuild_dirs.iter().try_for_each(|dir| {
                 utils::copy_dir_recursive(
@@ -0,0 +1,415 @@
+use crate::utils;
+
+use flate2::read::GzDecoder;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::{fs, io};
+use tar::{Archive, Entry, EntryType};
+
+/// Maximum number of failed entries listed by `ExtractReport::print`.
+const MAX_LISTED_FAILURES: usize = 10;
+
+/// Prefix of the whiteout entries of OCI and Docker image layers.
+const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// Whiteout name (after the prefix) marking a directory as opaque.
+const OPAQUE_WHITEOUT: &str = ".wh..opq";
+
+/// Summary of an archive extraction.
+///
+/// `failures` holds the entries whose content could not be created, and
+/// `warnings` the ones created without their ownership, permissions,
+/// mtime or extended attributes.
+#[derive(Debug, Default)]
+pub struct ExtractReport {
+    pub entries: usize,
+    pub failures: Vec<(PathBuf, String)>,
+    pub warnings: Vec<(PathBuf, String)>,
+}
+
+impl ExtractReport {
+    /// Records the outcome of unpacking an entry.
+    ///
+    /// An error is only a warning when the content of the entry is in place.
+    fn record<R: Read>(&mut self, entry: &Entry<R>, path: PathBuf, destination: &Path, unpacked: io::Result<bool>) {
+        match unpacked {
+            Ok(true) => {}
+            Ok(false) => self
+                .failures
+                .push((path, "path outside of the destination, skipped".to_string())),
+            Err(e) if is_written(entry, &path, destination) => self.warnings.push((path, e.to_string())),
+            Err(e) => self.failures.push((path, e.to_string())),
+        }
+    }
+
+    /// Prints the entries that could not be extracted, if any.
+    ///
+    /// # Example
+    /// ```
+    /// let report = extract_tar_gz(Path::new("rootfs.tar.gz"), Path::new("/tmp/rootfs"))?;
+    /// report.print();
+    /// ```
+    pub fn print(&self) {
+        Self::print_entries(&self.failures, "could not be extracted", self.entries);
+        Self::print_entries(&self.warnings, "were extracted without their metadata", self.entries);
+    }
+
+    /// Prints a warning listing the given entries, if any.
+    fn print_entries(list: &[(PathBuf, String)], what: &str, entries: usize) {
+        if list.is_empty() {
+            return;
+        }
+
+        eprintln!("\x1b[1;33mWarning\x1b[0m: {} of {entries} entries {what}:", list.len());
+        for (path, err) in list.iter().take(MAX_LISTED_FAILURES) {
+            eprintln!("  -> {}: {err}", path.display());
+        }
+        if list.len() > MAX_LISTED_FAILURES {
+            eprintln!("  ... and {} more", list.len() - MAX_LISTED_FAILURES);
+        }
+    }
+}
+
+/// Extracts a `.tar.gz` archive to the specified destination directory.
+///
+/// The gzip stream is decompressed while it is unpacked, and the progress
+/// bar follows the compressed bytes read from the file.
+///
+/// # Arguments
+/// * `file_path` - The path to the `.tar.gz` file to extract.
+/// * `destination` - The directory where the contents will be extracted.
+///
+/// # Returns
+/// * `Ok(ExtractReport)` with the entries that could not be extracted.
+/// * `Err`: An `io::Error` if the archive cannot be read.
+///
+/// # Examples
+/// ```
+/// let report = extract_tar_gz(Path::new("archive.tar.gz"), Path::new("/tmp/output"))?;
+/// report.print();
+/// ```
+pub fn extract_tar_gz(file_path: &Path, destination: &Path) -> io::Result<ExtractReport> {
+    let file = File::open(file_path)?;
+
+    let bar = ProgressBar::new(file.metadata()?.len());
+    bar.set_message("Extracting...");
+    bar.set_style(
+        ProgressStyle::with_template(utils::DOWNLOAD_TEMPLATE)
+            .unwrap()
+            .progress_chars("##-"),
+    );
+
+    let report = unpack(GzDecoder::new(bar.wrap_read(file)), destination);
+    match &report {
+        Ok(_) => bar.finish_with_message("Extracted! "),
+        Err(_) => bar.abandon_with_message("Failed!"),
+    }
+    report
+}
+
+/// Unpacks a tar stream into a directory, entry by entry.
+///
+/// Entries that cannot be created (device nodes, ownership changes a
+/// non-root user cannot make, paths escaping the destination...) are
+/// recorded in the report instead of aborting the extraction. Errors while
+/// reading the stream itself are returned.
+///
+/// # Arguments
+/// * `reader` - The uncompressed tar stream.
+/// * `destination` - The directory where the contents will be extracted.
+///
+/// # Returns
+/// * `Ok(ExtractReport)` with the number of entries and the failures.
+/// * `Err`: An `io::Error` if the stream is corrupted or unreadable.
+pub fn unpack<R: Read>(reader: R, destination: &Path) -> io::Result<ExtractReport> {
+    let mut archive = new_archive(reader);
+    let mut report = ExtractReport::default();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_path_buf();
+        report.entries += 1;
+        let unpacked = unpack_entry(&mut entry, &path, destination);
+        report.record(&entry, path, destination, unpacked);
+    }
+
+    Ok(report)
+}
+
+/// Applies an image layer over a directory, like `unpack`, honouring whiteouts.
+///
+/// A `.wh.<name>` entry removes `<name>` from the lower layers, and a
+/// `.wh..wh..opq` entry removes every entry of its directory that does not
+/// come from this layer. Whiteouts naming `.` or `..` are ignored. An entry
+/// replacing a path of another type (a file over a directory, a symlink over
+/// a file...) removes it first.
+///
+/// # Arguments
+/// * `reader` - The uncompressed tar stream of the layer.
+/// * `destination` - The directory holding the lower layers.
+///
+/// # Returns
+/// * `Ok(ExtractReport)` with the number of entries and the failures.
+/// * `Err`: An `io::Error` if the stream is corrupted or unreadable.
+///
+/// # Examples
+/// ```
+/// let report = unpack_layer(File::open("layer.tar")?, Path::new("/tmp/rootfs"))?;
+/// report.print();
+/// ```
+pub fn unpack_layer<R: Read>(reader: R, destination: &Path) -> io::Result<ExtractReport> {
+    let mut archive = new_archive(reader);
+    let mut report = ExtractReport::default();
+    let mut layer: HashSet<PathBuf> = HashSet::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path: PathBuf = entry.path()?.components().filter(|c| *c != Component::CurDir).collect();
+        report.entries += 1;
+
+        if path.as_os_str().is_empty() {
+            continue;
+        }
+        if path.components().any(|c| !matches!(c, Component::Normal(_))) {
+            report.record(&entry, path, destination, Ok(false));
+            continue;
+        }
+
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let parent = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let unpacked = match name.strip_prefix(WHITEOUT_PREFIX) {
+            Some(OPAQUE_WHITEOUT) => clear_lower_entries(destination, &parent, &layer),
+            // `.wh.`, `.wh..` and `.wh...` would hide the directory itself or its parent.
+            Some("" | "." | "..") => Ok(true),
+            Some(hidden) => remove_lower_entry(destination, &parent.join(hidden)),
+            None => {
+                layer.extend(path.ancestors().map(Path::to_path_buf));
+                resolve_dir(destination, &parent).and_then(|dir| match dir {
+                    Some(dir) => replace_other_type(&entry, &dir.join(path.file_name().unwrap_or_default()))
+                        .and_then(|_| unpack_entry(&mut entry, &path, destination)),
+                    None => Ok(false),
+                })
+            }
+        };
+        report.record(&entry, path, destination, unpacked);
+    }
+
+    Ok(report)
+}
+
+/// Creates an archive reader with the extraction settings of ALPack.
+fn new_archive<R: Read>(reader: R) -> Archive<R> {
+    let mut archive = Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+    archive.set_unpack_xattrs(true);
+    archive.set_overwrite(true);
+    archive.set_preserve_ownerships(utils::is_root());
+    archive
+}
+
+/// Unpacks an entry into the destination.
+///
+/// # Returns
+/// * `Ok(true)` if the entry was created.
+/// * `Ok(false)` if the path points outside of the destination.
+fn unpack_entry<R: Read>(entry: &mut Entry<R>, path: &Path, destination: &Path) -> io::Result<bool> {
+    match entry.header().entry_type() {
+        EntryType::Fifo | EntryType::Char | EntryType::Block => unpack_special(entry, path, destination),
+        _ => entry.unpack_in(destination),
+    }
+}
+
+/// Checks whether the content of an entry is in place, after an error that
+/// may only concern its metadata (ownership, permissions, mtime or xattrs).
+fn is_written<R: Read>(entry: &Entry<R>, path: &Path, destination: &Path) -> bool {
+    let normal: PathBuf = path.components().filter(|c| matches!(c, Component::Normal(_))).collect();
+    let Ok(Some(dir)) = resolve_dir(destination, normal.parent().unwrap_or(Path::new(""))) else {
+        return false;
+    };
+    let target = dir.join(normal.file_name().unwrap_or_default());
+    let Ok(meta) = fs::symlink_metadata(&target) else {
+        return false;
+    };
+
+    let header = entry.header();
+    match header.entry_type() {
+        EntryType::Regular | EntryType::Continuous => meta.is_file() && header.size().is_ok_and(|size| size == meta.len()),
+        EntryType::Directory => meta.is_dir(),
+        EntryType::Symlink => fs::read_link(&target).is_ok_and(|link| entry.link_name().is_ok_and(|name| name.as_deref() == Some(&link))),
+        EntryType::Link => true,
+        EntryType::Fifo => meta.file_type().is_fifo(),
+        EntryType::Char => meta.file_type().is_char_device(),
+        EntryType::Block => meta.file_type().is_block_device(),
+        _ => false,
+    }
+}
+
+/// Removes the path about to be replaced by `entry` when it has another type.
+///
+/// Directories are kept when the entry is a directory too, so the entries
+/// of the lower layers inside them stay visible.
+fn replace_other_type<R: Read>(entry: &Entry<R>, target: &Path) -> io::Result<()> {
+    let Ok(meta) = fs::symlink_metadata(target) else {
+        return Ok(());
+    };
+    match (meta.is_dir(), entry.header().entry_type().is_dir()) {
+        (true, true) => Ok(()),
+        (true, false) => fs::remove_dir_all(target),
+        (false, _) => fs::remove_file(target),
+    }
+}
+
+/// Removes an entry of the lower layers hidden by a `.wh.<name>` whiteout.
+fn remove_lower_entry(destination: &Path, path: &Path) -> io::Result<bool> {
+    let Some(dir) = resolve_dir(destination, path.parent().unwrap_or(Path::new("")))? else {
+        return Ok(false);
+    };
+    let target = dir.join(path.file_name().unwrap_or_default());
+    match fs::symlink_metadata(&target) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(&target)?,
+        Ok(_) => fs::remove_file(&target)?,
+        Err(_) => {}
+    }
+    Ok(true)
+}
+
+/// Removes the entries of a directory hidden by an opaque whiteout, keeping the ones of the current layer.
+fn clear_lower_entries(destination: &Path, dir: &Path, layer: &HashSet<PathBuf>) -> io::Result<bool> {
+    let Some(target) = resolve_dir(destination, dir)? else {
+        return Ok(false);
+    };
+    if !target.is_dir() {
+        return Ok(true);
+    }
+
+    for child in fs::read_dir(target)?.flatten() {
+        if layer.contains(&dir.join(child.file_name())) {
+            continue;
+        }
+        match child.file_type()?.is_dir() {
+            true => fs::remove_dir_all(child.path())?,
+            false => fs::remove_file(child.path())?,
+        }
+    }
+    Ok(true)
+}
+
+/// Resolves a directory of the destination, following its symlinks.
+///
+/// Every existing component is resolved, so a symlink to a path outside of
+/// the destination is caught even when the directories below it do not exist yet.
+///
+/// # Returns
+/// * `Ok(Some(PathBuf))` with the resolved directory, which may not exist.
+/// * `Ok(None)` if a symlink points outside of the destination, or is dangling.
+fn resolve_dir(destination: &Path, dir: &Path) -> io::Result<Option<PathBuf>> {
+    let root = fs::canonicalize(destination)?;
+    let mut target = root.clone();
+    for component in dir.components() {
+        target.push(component);
+        if fs::symlink_metadata(&target).is_ok() {
+            match fs::canonicalize(&target) {
+                Ok(resolved) => target = resolved,
+                Err(_) => return Ok(None),
+            }
+        }
+    }
+    Ok(Some(target).filter(|t| t.starts_with(&root)))
+}
+
+/// Creates a FIFO or device node entry, which `tar` would unpack as a regular file.
+///
+/// Device nodes can only be created by root; for other users the error is
+/// returned and recorded in the extraction report.
+///
+/// # Returns
+/// * `Ok(true)` if the node was created.
+/// * `Ok(false)` if the path, or a symlink of its parent directories, points outside of the destination.
+/// * `Err`: An `io::Error` if the node cannot be created.
+fn unpack_special<R: Read>(entry: &Entry<R>, path: &Path, destination: &Path) -> io::Result<bool> {
+    if path.components().any(|c| !matches!(c, Component::Normal(_) | Component::CurDir)) {
+        return Ok(false);
+    }
+    let Some(parent) = resolve_dir(destination, path.parent().unwrap_or(Path::new("")))? else {
+        return Ok(false);
+    };
+
+    let header = entry.header();
+    let target = parent.join(path.file_name().unwrap_or_default());
+    fs::create_dir_all(&parent)?;
+    if fs::symlink_metadata(&target).is_ok() {
+        fs::remove_file(&target)?;
+    }
+
+    let kind = match header.entry_type() {
+        EntryType::Fifo => libc::S_IFIFO,
+        EntryType::Char => libc::S_IFCHR,
+        _ => libc::S_IFBLK,
+    };
+    let dev = libc::makedev(
+        header.device_major()?.unwrap_or(0),
+        header.device_minor()?.unwrap_or(0),
+    );
+    let mode = header.mode()? & 0o7777;
+    let c_path = CString::new(target.as_os_str().as_bytes()).map_err(io::Error::other)?;
+
+    // SAFETY: `c_path` is a valid C string that outlives the call.
+    if unsafe { libc::mknod(c_path.as_ptr(), kind | mode as libc::mode_t, dev) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    fs::set_permissions(&target, fs::Permissions::from_mode(mode))?;
+    if utils::is_root() {
+        let (uid, gid) = (header.uid()? as libc::uid_t, header.gid()? as libc::gid_t);
+        // SAFETY: `c_path` is a valid C string that outlives the call, and lchown does not follow symlinks.
+        if unsafe { libc::lchown(c_path.as_ptr(), uid, gid) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+    use tar::{Builder, Header};
+
+    /// Appends an entry with the given type and data to a tar builder.
+    fn append(builder: &mut Builder<Vec<u8>>, kind: EntryType, path: &str, data: &[u8]) {
+        let mut header = Header::new_ustar();
+        header.set_entry_type(kind);
+        header.set_mode(0o644);
+        header.set_size(data.len() as u64);
+        header.as_ustar_mut().unwrap().name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_cksum();
+        builder.append(&header, data).unwrap();
+    }
+
+    #[test]
+    fn metadata_errors_are_warnings_and_content_errors_failures() {
+        let dest = env::temp_dir().join(format!("ALPack-archive-test-{}", process::id()));
+        let _ = fs::remove_dir_all(&dest);
+        fs::create_dir_all(&dest).unwrap();
+
+        // `bogus.` is not an xattr namespace, so setting it always fails.
+        let mut builder = Builder::new(Vec::new());
+        append(&mut builder, EntryType::XHeader, "pax", b"24 SCHILY.xattr.bogus.a=1\n");
+        append(&mut builder, EntryType::Regular, "file", b"content");
+        append(&mut builder, EntryType::Regular, "../escaped", b"content");
+        let report = unpack(builder.into_inner().unwrap().as_slice(), &dest).unwrap();
+
+        assert_eq!(fs::read(dest.join("file")).unwrap(), b"content");
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].0, Path::new("file"));
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].0, Path::new("../escaped"));
+        fs::remove_dir_all(&dest).unwrap();
+    }
+}
@@ -0,0 +1,240 @@
+use crate::command::Command;
+use crate::native::Native;
+use crate::qemu;
+use crate::sandbox::{Mount, SandboxSpec, SANDBOX_PATH};
+use crate::utils;
+
+use std::env;
+use std::error::Error;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command as StdCommand, Stdio};
+
+/// Names of the supported backends, as set in the settings and the registry.
+pub const BACKENDS: [&str; 3] = ["proot", "bwrap", "native"];
+
+/// Host files bound read-only by bubblewrap and the native backend for name resolution and time, when they exist.
+pub const HOST_FILES: [&str; 8] = [
+    "/etc/host.conf",
+    "/etc/hosts",
+    "/etc/hosts.equiv",
+    "/etc/netgroup",
+    "/etc/networks",
+    "/etc/nsswitch.conf",
+    "/etc/resolv.conf",
+    "/etc/localtime",
+];
+
+/// Runs the sandboxed sessions of a rootfs.
+pub trait Backend {
+    /// Checks whether the backend mounts a temporary overlay for ephemeral
+    /// sessions (`SandboxSpec::overlay`) instead of running them in a copy.
+    fn supports_tmp_overlay(&self) -> bool {
+        false
+    }
+
+    /// Adds what running a foreign-architecture rootfs needs to the spec.
+    ///
+    /// By default the binfmt_misc handler of the host runs the programs, and
+    /// the qemu binary is bound at the interpreter path when the handler does
+    /// not keep it open.
+    ///
+    /// # Returns
+    /// * `Err` if no qemu binary or binfmt_misc handler is available for `arch`.
+    fn emulate(&self, arch: &str, spec: &mut SandboxSpec) -> Result<(), Box<dyn Error>> {
+        if let Some(interpreter) = qemu::binfmt_interpreter(arch)? {
+            spec.mounts.push(Mount::new(qemu::find_qemu(arch)?, interpreter, true));
+        }
+        Ok(())
+    }
+
+    /// Runs the session described by the spec and waits for it.
+    ///
+    /// # Returns
+    /// * `Ok(i32)` with the exit code of the session.
+    /// * `Err` if the session could not be started.
+    fn run(&self, spec: &SandboxSpec) -> io::Result<i32>;
+}
+
+/// Returns the backend with the given name.
+///
+/// The helper binary of `proot` and `bwrap` is looked up, and downloaded
+/// to `~/.local/bin` when it is missing.
+///
+/// # Example
+/// ```
+/// let backend = backend::from_name("native")?;
+/// let code = backend.run(&spec)?;
+/// ```
+pub fn from_name(name: &str) -> Result<Box<dyn Backend>, Box<dyn Error>> {
+    Ok(match name {
+        "proot" => Box::new(Proot { bin: utils::verify_and_download_rootfs_command(name)? }),
+        "bwrap" => Box::new(Bwrap { bin: utils::verify_and_download_rootfs_command(name)? }),
+        "native" => Box::new(Native),
+        other => return Err(format!("Unsupported rootfs command: {}", other).into()),
+    })
+}
+
+/// Runs a helper binary with inherited standard streams and returns its exit code.
+fn spawn(bin: &Path, args: Vec<OsString>) -> io::Result<i32> {
+    let status = StdCommand::new(bin)
+        .args(&args)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status()?;
+    Ok(status.code().unwrap_or(-1))
+}
+
+/// Returns an option followed by its values.
+fn option<const N: usize>(name: &str, values: [&OsStr; N]) -> Vec<OsString> {
+    let mut args = vec![OsString::from(name)];
+    args.extend(values.iter().map(|v| v.to_os_string()));
+    args
+}
+
+/// The PRoot backend, working without user namespaces through ptrace.
+pub struct Proot {
+    bin: PathBuf,
+}
+
+impl Proot {
+    /// Renders the spec to the arguments of PRoot.
+    ///
+    /// `-R` binds the usual host files and directories (`/dev`, `/proc`,
+    /// `$HOME`, `/etc/resolv.conf`...) on top of the mounts of the spec.
+    ///
+    /// # Example
+    /// ```
+    /// let args = Proot::args(&spec); // ["-R", "/my/rootfs", "--bind=/media:/media", ...]
+    /// ```
+    pub fn args(spec: &SandboxSpec) -> Vec<OsString> {
+        let mut args = vec![OsString::from("-R"), spec.root.clone().into_os_string()];
+
+        for mount in &spec.mounts {
+            args.push(Self::bind(&mount.source, &mount.dest));
+        }
+        if spec.rootfs_users {
+            for file in ["etc/group", "etc/passwd"] {
+                args.push(Self::bind(&spec.root.join(file), &Path::new("/").join(file)));
+            }
+        }
+        args.extend(spec.backend_args.iter().cloned());
+
+        if let Some(cwd) = &spec.cwd {
+            args.extend([OsString::from("-w"), cwd.clone().into_os_string()]);
+        }
+        if spec.root_user {
+            args.push("-0".into());
+        }
+        if let Some(qemu) = &spec.emulator {
+            args.extend([OsString::from("-q"), qemu.clone().into_os_string()]);
+        }
+
+        args.extend(spec.command_args());
+        args
+    }
+
+    /// Returns a PRoot `--bind=<source>:<dest>` option.
+    fn bind(source: &Path, dest: &Path) -> OsString {
+        let mut arg = OsString::from("--bind=");
+        arg.push(source);
+        arg.push(":");
+        arg.push(dest);
+        arg
+    }
+}
+
+impl Backend for Proot {
+    /// PRoot runs every program through qemu-user with `-q`.
+    fn emulate(&self, arch: &str, spec: &mut SandboxSpec) -> Result<(), Box<dyn Error>> {
+        spec.emulator = Some(qemu::find_qemu(arch)?);
+        Ok(())
+    }
+
+    fn run(&self, spec: &SandboxSpec) -> io::Result<i32> {
+        spawn(&self.bin, Self::args(spec))
+    }
+}
+
+/// The bubblewrap backend, working with unprivileged user namespaces.
+pub struct Bwrap {
+    bin: PathBuf,
+}
+
+impl Bwrap {
+    /// Renders the spec to the arguments of bubblewrap.
+    ///
+    /// The sandbox shares the network of the host and binds `/dev`, `/proc`,
+    /// `/sys`, `/tmp`, `/run`, the D-Bus system socket and `$HOME` before the
+    /// mounts of the spec.
+    ///
+    /// # Example
+    /// ```
+    /// let args = Bwrap::args(&spec); // ["--unshare-user", "--share-net", "--bind", "/my/rootfs", "/", ...]
+    /// ```
+    pub fn args(spec: &SandboxSpec) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec!["--unshare-user".into(), "--share-net".into()];
+        if spec.overlay {
+            args.extend(option("--overlay-src", [spec.root.as_os_str()]));
+            args.extend(option("--tmp-overlay", [OsStr::new("/")]));
+        } else {
+            args.extend(option("--bind", [spec.root.as_os_str(), OsStr::new("/")]));
+        }
+        args.push("--die-with-parent".into());
+
+        for file in HOST_FILES {
+            args.extend(option("--ro-bind-try", [file, file].map(OsStr::new)));
+        }
+        args.extend(option("--dev-bind", ["/dev", "/dev"].map(OsStr::new)));
+        args.extend(option("--ro-bind", ["/sys", "/sys"].map(OsStr::new)));
+        for dir in ["/proc", "/tmp", "/run"] {
+            args.extend(option("--bind-try", [dir, dir].map(OsStr::new)));
+        }
+        let dbus = OsStr::new("/var/run/dbus/system_bus_socket");
+        args.extend(option("--ro-bind-try", [dbus, dbus]));
+        if let Some(home) = env::var_os("HOME") {
+            args.extend(option("--bind", [&home, &home]));
+        }
+        if !spec.rootfs_users {
+            for file in ["/etc/passwd", "/etc/group"] {
+                args.extend(option("--ro-bind-try", [file, file].map(OsStr::new)));
+            }
+        }
+
+        for mount in &spec.mounts {
+            let name = if mount.read_only { "--ro-bind" } else { "--bind" };
+            args.extend(option(name, [mount.source.as_os_str(), mount.dest.as_os_str()]));
+        }
+        args.extend(spec.backend_args.iter().cloned());
+
+        args.extend(option("--setenv", ["PATH", SANDBOX_PATH].map(OsStr::new)));
+        if spec.root_user {
+            args.extend(option("--uid", [OsStr::new("0")]));
+            args.extend(option("--gid", [OsStr::new("0")]));
+        }
+        if let Some(cwd) = &spec.cwd {
+            args.extend(option("--chdir", [cwd.as_os_str()]));
+        }
+
+        args.extend(spec.command_args());
+        args
+    }
+}
+
+impl Backend for Bwrap {
+    /// Checks whether bwrap supports temporary overlays (`--tmp-overlay`, bwrap 0.10+).
+    fn supports_tmp_overlay(&self) -> bool {
+        StdCommand::new(&self.bin)
+            .arg("--help")
+            .output()
+            .map(|o| String::from_utf8_lossy(&o.stdout).contains("--tmp-overlay"))
+            .unwrap_or(false)
+    }
+
+    fn run(&self, spec: &SandboxSpec) -> io::Result<i32> {
+        Command::fix_mtab_symlink(&spec.root)?;
+        spawn(&self.bin, Self::args(spec))
+    }
+}
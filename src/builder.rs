@@ -1,7 +1,9 @@
 use crate::command::Command;
+use crate::registry::Registry;
+use crate::sandbox::Mount;
 use crate::settings::Settings;
 use crate::utils::_parse_key_value;
-use crate::{parse_key_value, utils};
+use crate::{parse_key_value, qemu, utils};
 
 use std::collections::VecDeque;
 use std::error::Error;
@@ -35,6 +37,7 @@ impl Builder {
 
         let mut cmd_args = Vec::new();
         let mut apkbuild_file = String::new();
+        let mut clean = false;
 
         let sett = Settings::load_or_create();
         let mut rootfs_dir: String = sett.set_rootfs();
@@ -53,6 +56,17 @@ impl Builder {
                     )?
                     .unwrap();
                 }
+                a if a.starts_with("--env=") => {
+                    let env = parse_key_value!("builder", "name", arg)?.unwrap();
+                    rootfs_dir = Registry::resolve(&env)?;
+                }
+                "--env" => {
+                    let env = parse_key_value!("builder", "name", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                    rootfs_dir = Registry::resolve(&env)?;
+                }
+                "--clean" => {
+                    clean = true;
+                }
                 a if a.starts_with("--apkbuild=") => {
                     apkbuild_file = parse_key_value!("builder", "apkbuild", arg)?.unwrap();
                 }
@@ -86,7 +100,7 @@ impl Builder {
                     let dest_file = build_dir.join("APKBUILD");
                     fs::copy(apkbuild_file.clone(), &dest_file)?;
 
-                    Self::run_abuild(rootfs_dir.clone(), dir_name)?;
+                    Self::run_abuild(rootfs_dir.clone(), dir_name, clean)?;
                 } else {
                     eprintln!(
                         "\x1b[1;33mWarning\x1b[0m: Invalid file: {}, expected 'APKBUILD'",
@@ -159,7 +173,7 @@ impl Builder {
                 utils::copy_dir_recursive(dir_name.as_ref(), dest_path.unwrap())?;
             }
 
-            Self::run_abuild(rootfs_dir.clone(), dir_name)?;
+            Self::run_abuild(rootfs_dir.clone(), dir_name, clean)?;
         }
 
         Ok(())
@@ -194,9 +208,15 @@ impl Builder {
 
     /// Executes the `abuild` command inside the specified root filesystem and directory.
     ///
+    /// With `clean`, the build runs in an ephemeral session: only the built
+    /// packages are kept, in `/build/packages` of the rootfs, and the staged
+    /// sources are removed afterwards. The build tools and the signing key
+    /// are still installed in the rootfs.
+    ///
     /// # Arguments
     /// * `rootfs` - The path to the root filesystem where `abuild` should be executed.
     /// * `dir_name` - The directory containing the PKGBUILD or source to build.
+    /// * `clean` - Discard every change made by the build, except the packages.
     ///
     /// # Returns
     /// * `Ok(())` - If the `abuild` command executes successfully.
@@ -204,10 +224,10 @@ impl Builder {
     ///
     /// # Examples
     /// ```no_run
-    /// run_abuild("/path/to/rootfs".to_string(), "/path/to/srcdir".to_string())?;
+    /// run_abuild("/path/to/rootfs".to_string(), "/path/to/srcdir".to_string(), false)?;
     /// println!("Build completed successfully");
     /// ```
-    fn run_abuild(rootfs: String, dir_name: String) -> Result<(), Box<dyn Error>> {
+    fn run_abuild(rootfs: String, dir_name: String, clean: bool) -> Result<(), Box<dyn Error>> {
         let cmd = format!(
             "
             type abuild > /dev/null || apk add alpine-sdk autoconf automake
@@ -221,17 +241,27 @@ impl Builder {
             u = env::var("USER").unwrap()
         );
 
-        Command::run(rootfs.clone(), None, Some(cmd), false, false, false)?;
+        Command::run(rootfs.clone(), Vec::new(), Some(cmd), false, false, false)?;
 
         let cmd = format!("
             HOME=/build
             cd /build/{dir_name}
             abuild -r -F
             find \"/build/packages/build/{u}\" -name \"$apkbuild_name\"*.apk -exec apk add --allow-untrusted {{}} \\;
-        ", u = utils::get_arch());
+        ", u = qemu::rootfs_arch(&rootfs));
+
+        if !clean {
+            Command::run(rootfs, Vec::new(), Some(cmd), true, true, true)?;
+            return Ok(());
+        }
 
-        Command::run(rootfs, None, Some(cmd), true, true, true)?;
+        let packages = format!("{rootfs}/build/packages");
+        fs::create_dir_all(&packages)?;
+        let bind = Mount::new(&packages, "/build/packages", false);
+        let result = Command::run_ephemeral(rootfs.clone(), vec![bind.into()], Some(cmd), true, true, true);
 
+        let _ = fs::remove_dir_all(format!("{rootfs}/build/{dir_name}"));
+        result?;
         Ok(())
     }
 }
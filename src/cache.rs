@@ -0,0 +1,390 @@
+use crate::settings::Settings;
+use crate::utils::{self, _parse_key_value};
+use crate::parse_key_value;
+
+use indicatif::HumanBytes;
+use regex::Regex;
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Directory of the cache holding the apk package caches, as `apk/<arch>/<release>`.
+pub const APK_CACHE_DIR: &str = "apk";
+
+/// Directory of the cache holding the build source caches, one directory per package.
+pub const SOURCES_CACHE_DIR: &str = "sources";
+
+/// Directories of the cache that are not managed by `cache`, with the subcommand managing them.
+const SKIPPED_DIRS: [(&str, &str); 1] = [("snapshots", "snapshot")];
+
+/// Kinds of entries created by ALPack, the only ones removed by `clean`.
+const CLEANED_KINDS: [&str; 6] = ["rootfs", "sidecar", "partial", "apk", "sources", "keys"];
+
+/// Extensions of the files downloaded next to a minirootfs tarball.
+const SIDECAR_EXTENSIONS: [&str; 3] = ["sha256", "sha512", "asc"];
+
+/// A file or directory of the cache.
+struct Entry {
+    kind: &'static str,
+    name: String,
+    paths: Vec<PathBuf>,
+    release: String,
+    arch: String,
+    version: String,
+    size: u64,
+    modified: u64,
+}
+
+impl Entry {
+    fn new(kind: &'static str, path: PathBuf) -> Self {
+        let modified = Self::modified(&path);
+        Entry {
+            kind,
+            name: path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            size: match path.is_dir() {
+                true => utils::dir_size(&path),
+                false => fs::metadata(&path).map(|m| m.len()).unwrap_or(0),
+            },
+            paths: vec![path],
+            release: String::new(),
+            arch: String::new(),
+            version: String::new(),
+            modified,
+        }
+    }
+
+    /// Returns the modification time of a file, or of the newest file of a directory.
+    fn modified(path: &Path) -> u64 {
+        let own = fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        match fs::read_dir(path) {
+            Ok(entries) => entries.flatten().map(|e| Self::modified(&e.path())).fold(own, u64::max),
+            Err(_) => own,
+        }
+    }
+
+    /// Returns the group in which `--keep-latest` keeps the newest entries.
+    fn group(&self) -> String {
+        format!("{}/{}/{}", self.kind, self.arch, if self.kind == "apk" { &self.release } else { "" })
+    }
+
+    /// Returns the key ordering the entries of a group, oldest first.
+    fn age_key(&self) -> (Vec<u32>, u64) {
+        let version = self.version.split('.').filter_map(|n| n.parse().ok()).collect();
+        (version, self.modified)
+    }
+}
+
+pub struct Cache {
+    name: String,
+    remaining_args: Vec<String>,
+}
+
+impl Cache {
+    pub fn new(name: String, remaining_args: Vec<String>) -> Self {
+        Cache {
+            name,
+            remaining_args,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<_> = self.remaining_args.clone().into();
+        let sett = Settings::load_or_create();
+        let mut cache_dir = sett.set_cache_dir();
+        let (mut older_than, mut keep_latest): (Option<u64>, Option<usize>) = (None, None);
+
+        let action = args.pop_front();
+
+        while let Some(arg) = args.pop_front() {
+            match arg.as_str() {
+                a if a.starts_with("--older-than=") => {
+                    older_than = Some(self.parse_age(parse_key_value!("cache", "age", arg)?.unwrap())?);
+                }
+                "--older-than" => {
+                    older_than = Some(self.parse_age(parse_key_value!("cache", "age", arg, args.pop_front().unwrap_or_default())?.unwrap())?);
+                }
+                a if a.starts_with("--keep-latest=") => {
+                    keep_latest = Some(self.parse_count(parse_key_value!("cache", "N", arg)?.unwrap())?);
+                }
+                "--keep-latest" => {
+                    keep_latest = Some(self.parse_count(parse_key_value!("cache", "N", arg, args.pop_front().unwrap_or_default())?.unwrap())?);
+                }
+                a if a.starts_with("--cache=") => {
+                    cache_dir = parse_key_value!("cache", "directory", arg)?.unwrap();
+                }
+                "--cache" => {
+                    cache_dir = parse_key_value!("cache", "directory", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                }
+                _ => {
+                    return Err(format!("{c}: cache: invalid argument '{arg}'\nUse '{c} --help' to see available options.", c = self.name).into())
+                }
+            }
+        }
+
+        if (older_than.is_some() || keep_latest.is_some()) && action.as_deref() != Some("clean") {
+            return Err(format!("{}: cache: --older-than and --keep-latest can only be used with 'clean'", self.name).into());
+        }
+
+        let cache_dir = Path::new(&cache_dir);
+        match action.as_deref() {
+            Some("list") | Some("ls") => self.list(cache_dir),
+            Some("size") => self.size(cache_dir),
+            Some("clean") => self.clean(cache_dir, older_than, keep_latest),
+            Some(other) => Err(format!(
+                "{c}: cache: invalid argument '{other}'\nUse '{c} --help' to see available options.",
+                c = self.name
+            )
+            .into()),
+            None => Err(format!(
+                "{c}: cache: no parameter specified\nUse '{c} --help' to see available options.",
+                c = self.name
+            )
+            .into()),
+        }
+    }
+
+    /// Prints every cached file with what it is.
+    fn list(&self, cache_dir: &Path) -> Result<(), Box<dyn Error>> {
+        let entries = Self::entries(cache_dir);
+        if entries.is_empty() {
+            println!("The cache {} is empty.", cache_dir.display());
+            return Ok(());
+        }
+
+        let width = entries.iter().map(|e| e.name.len()).max().unwrap_or(0).max(4);
+
+        println!("{}", utils::separator_line());
+        println!(
+            "{:<8}  {:<width$}  {:<13}  {:<11}  {:<8}  {:>10}  MODIFIED",
+            "KIND", "NAME", "RELEASE", "ARCH", "VERSION", "SIZE"
+        );
+        println!("{}", utils::separator_line());
+        for e in &entries {
+            println!(
+                "{:<8}  {:<width$}  {:<13}  {:<11}  {:<8}  {:>10}  {}",
+                e.kind,
+                e.name,
+                Self::or_dash(&e.release),
+                Self::or_dash(&e.arch),
+                Self::or_dash(&e.version),
+                HumanBytes(e.size).to_string(),
+                utils::format_date(e.modified)
+            );
+        }
+        println!("{}", utils::separator_line());
+        println!("Total: {} in {}", HumanBytes(entries.iter().map(|e| e.size).sum()), cache_dir.display());
+        Ok(())
+    }
+
+    /// Prints the size of the cache by kind of file.
+    fn size(&self, cache_dir: &Path) -> Result<(), Box<dyn Error>> {
+        let mut sizes: BTreeMap<&str, (usize, u64)> = BTreeMap::new();
+        for e in Self::entries(cache_dir) {
+            let s = sizes.entry(e.kind).or_default();
+            s.0 += 1;
+            s.1 += e.size;
+        }
+
+        println!("{}", utils::separator_line());
+        println!("{:<10}  {:>7}  {:>10}", "KIND", "ENTRIES", "SIZE");
+        println!("{}", utils::separator_line());
+        for (kind, (count, size)) in &sizes {
+            println!("{kind:<10}  {count:>7}  {:>10}", HumanBytes(*size).to_string());
+        }
+        println!("{}", utils::separator_line());
+        println!(
+            "Total: {} in {}",
+            HumanBytes(sizes.values().map(|s| s.1).sum()),
+            cache_dir.display()
+        );
+
+        for (dir, sub) in SKIPPED_DIRS {
+            let path = cache_dir.join(dir);
+            if path.is_dir() {
+                println!("Not counted: {} in {} (managed by '{} {sub}')", HumanBytes(utils::dir_size(&path)), path.display(), self.name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the cached files, all of them or those matching the filters.
+    ///
+    /// Only the kinds of `CLEANED_KINDS` are removed, files and directories
+    /// not created by ALPack are listed but always kept.
+    ///
+    /// # Arguments
+    /// * `cache_dir` - The cache directory.
+    /// * `older_than` - Only remove the entries not modified for this many seconds.
+    /// * `keep_latest` - Keep the newest entries of each kind, architecture and release.
+    fn clean(&self, cache_dir: &Path, older_than: Option<u64>, keep_latest: Option<usize>) -> Result<(), Box<dyn Error>> {
+        let mut groups: BTreeMap<String, Vec<Entry>> = BTreeMap::new();
+        let mut kept = 0;
+        for e in Self::entries(cache_dir) {
+            match CLEANED_KINDS.contains(&e.kind) {
+                true => groups.entry(e.group()).or_default().push(e),
+                false => kept += 1,
+            }
+        }
+
+        let limit = older_than.map(|age| utils::now().saturating_sub(age));
+        let (mut removed, mut freed) = (0, 0);
+
+        for mut group in groups.into_values() {
+            group.sort_by_key(|e| std::cmp::Reverse(e.age_key()));
+            for e in group.into_iter().skip(keep_latest.unwrap_or(0)) {
+                if limit.is_some_and(|limit| e.modified > limit) {
+                    continue;
+                }
+                for path in &e.paths {
+                    match path.is_dir() {
+                        true => fs::remove_dir_all(path)?,
+                        false => fs::remove_file(path)?,
+                    }
+                }
+                println!("Removed: {} ({})", e.paths[0].display(), HumanBytes(e.size));
+                removed += 1;
+                freed += e.size;
+            }
+        }
+
+        Self::remove_empty_dirs(&cache_dir.join(APK_CACHE_DIR));
+        println!("{removed} cache entries removed, {} freed.", HumanBytes(freed));
+        if kept > 0 {
+            println!("{kept} entries not created by {} were kept, see '{} cache list'.", self.name, self.name);
+        }
+        Ok(())
+    }
+
+    /// Collects the entries of the cache directory.
+    ///
+    /// Minirootfs tarballs are grouped with their checksum and signature
+    /// files, apk caches are listed per architecture and release, and build
+    /// source caches per package.
+    fn entries(cache_dir: &Path) -> Vec<Entry> {
+        let tarball_re = Regex::new(r"^alpine-minirootfs-(\d+\.\d+\.\d+(?:_\w+)?)-(\w+)\.tar\.gz$").unwrap();
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut sidecars: Vec<PathBuf> = Vec::new();
+
+        let Ok(dir) = fs::read_dir(cache_dir) else {
+            return entries;
+        };
+        let mut files: Vec<PathBuf> = dir.flatten().map(|e| e.path()).collect();
+        files.sort();
+
+        for path in files {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            match name.as_str() {
+                n if SKIPPED_DIRS.iter().any(|(dir, _)| *dir == n) => {}
+                APK_CACHE_DIR if path.is_dir() => entries.extend(Self::apk_entries(&path)),
+                SOURCES_CACHE_DIR if path.is_dir() => entries.extend(
+                    Self::subdirs(&path).into_iter().map(|p| Entry::new("sources", p)),
+                ),
+                "keys" if path.is_dir() => entries.push(Entry::new("keys", path)),
+                n if n.ends_with(".part") => entries.push(Entry::new("partial", path)),
+                n if SIDECAR_EXTENSIONS.iter().any(|ext| n.ends_with(&format!(".tar.gz.{ext}"))) => sidecars.push(path),
+                n => {
+                    let mut entry = Entry::new(if path.is_dir() { "other" } else { "file" }, path.clone());
+                    if let Some(caps) = tarball_re.captures(n) {
+                        entry.kind = "rootfs";
+                        entry.version = caps[1].to_string();
+                        entry.arch = caps[2].to_string();
+                        let mut numbers = entry.version.split('.');
+                        entry.release = format!("v{}.{}", numbers.next().unwrap_or_default(), numbers.next().unwrap_or_default());
+                    }
+                    entries.push(entry);
+                }
+            }
+        }
+
+        for sidecar in sidecars {
+            let tarball = sidecar.with_extension("");
+            let size = fs::metadata(&sidecar).map(|m| m.len()).unwrap_or(0);
+            match entries.iter_mut().find(|e| e.paths[0] == tarball) {
+                Some(entry) => {
+                    entry.size += size;
+                    entry.paths.push(sidecar);
+                }
+                None => {
+                    let name = tarball.file_name().unwrap_or_default().to_string_lossy().to_string();
+                    entries.push(Entry::new(if tarball_re.is_match(&name) { "sidecar" } else { "file" }, sidecar));
+                }
+            }
+        }
+        entries
+    }
+
+    /// Lists the apk package caches, stored as `apk/<arch>/<release>`.
+    fn apk_entries(apk_dir: &Path) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        for arch_dir in Self::subdirs(apk_dir) {
+            let arch = arch_dir.file_name().unwrap_or_default().to_string_lossy().to_string();
+            for release_dir in Self::subdirs(&arch_dir) {
+                let mut entry = Entry::new("apk", release_dir);
+                entry.release = entry.name.clone();
+                entry.arch = arch.clone();
+                entry.name = format!("{APK_CACHE_DIR}/{arch}/{}", entry.release);
+                entries.push(entry);
+            }
+        }
+        entries
+    }
+
+    /// Returns the subdirectories of a directory, sorted by name.
+    fn subdirs(dir: &Path) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = fs::read_dir(dir)
+            .map(|entries| entries.flatten().map(|e| e.path()).filter(|p| p.is_dir()).collect())
+            .unwrap_or_default();
+        dirs.sort();
+        dirs
+    }
+
+    /// Removes the architecture directories left empty under the apk cache.
+    fn remove_empty_dirs(apk_dir: &Path) {
+        for arch_dir in Self::subdirs(apk_dir) {
+            let _ = fs::remove_dir(arch_dir);
+        }
+        let _ = fs::remove_dir(apk_dir);
+    }
+
+    fn or_dash(value: &str) -> &str {
+        match value.is_empty() {
+            true => "-",
+            false => value,
+        }
+    }
+
+    /// Parses the `--older-than` value, e.g. `30d`, `12h` or `2w` (days if no unit is given).
+    ///
+    /// # Returns
+    /// * `Ok(u64)` with the age in seconds.
+    fn parse_age(&self, value: String) -> Result<u64, Box<dyn Error>> {
+        let (number, unit) = value.split_at(value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len()));
+        let factor = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            "" | "d" => 86400,
+            "w" => 7 * 86400,
+            _ => 0,
+        };
+        match number.parse::<u64>().ok().and_then(|n| n.checked_mul(factor)) {
+            Some(age) if factor > 0 => Ok(age),
+            _ => Err(format!("{}: cache: invalid age '{value}', expected e.g. '30d', '12h' or '2w'", self.name).into()),
+        }
+    }
+
+    /// Parses the `--keep-latest` value.
+    fn parse_count(&self, value: String) -> Result<usize, Box<dyn Error>> {
+        value
+            .parse::<usize>()
+            .map_err(|_| format!("{}: cache: invalid number '{value}'", self.name).into())
+    }
+}
@@ -1,233 +1,423 @@
+use crate::backend::{self, Backend};
+use crate::cache::APK_CACHE_DIR;
+use crate::qemu;
+use crate::registry::Registry;
+use crate::sandbox::{Bind, Mount, SandboxSpec, SANDBOX_PATH};
 use crate::settings::Settings;
 use crate::utils;
 
+use std::ffi::OsString;
 use std::path::{Path, PathBuf};
-use std::process::{Command as StdCommand, Stdio};
 use std::{env, fs, io};
 
+/// Path where the CA bundle of the settings is bound inside the rootfs.
+const SANDBOX_CA_BUNDLE: &str = "/etc/ssl/alpack-ca.pem";
+
+/// Proxy variables passed to the sandboxed processes, also in upper case.
+const PROXY_VARS: [&str; 5] = ["http_proxy", "https_proxy", "ftp_proxy", "all_proxy", "no_proxy"];
+
 pub struct Command;
 
+/// Does nothing, so ALPack outlives a Ctrl-C sent to the sandboxed session.
+pub extern "C" fn ignore_signal(_: libc::c_int) {}
+
 impl Command {
+    /// Runs a command, or an interactive shell, inside the rootfs.
+    ///
+    /// # Parameters
+    /// - `rootfs`: Path to the root filesystem.
+    /// - `binds`: Extra mounts and raw backend options of the session.
+    /// - `cmd`: Shell command run with `/bin/sh -c`, or `None` for a shell.
+    /// - `use_root`: Run as root inside the rootfs.
+    /// - `ignore_extra_bind`: Skip the optional host binds like fonts and icons.
+    /// - `no_group`: Use the users and groups of the rootfs instead of the host ones.
+    ///
+    /// # Returns
+    /// * `Ok(i32)` with the exit code of the session.
+    ///
+    /// # Example
+    /// ```
+    /// let code = Command::run("/my/rootfs".into(), Vec::new(), Some("apk update".into()), true, false, false)?;
+    /// ```
     pub fn run(
         rootfs: String,
-        args_bind: Option<String>, cmd: Option<String>,
+        binds: Vec<Bind>, cmd: Option<String>,
         use_root: bool, ignore_extra_bind: bool, no_group: bool,
     ) -> Result<i32, Box<dyn std::error::Error>> {
-        let sett = Settings::load_or_create();
+        Self::execute(rootfs, binds, cmd, use_root, ignore_extra_bind, no_group, false)
+    }
+
+    /// Runs a command like `run`, discarding every change made to the rootfs.
+    ///
+    /// With bwrap, a temporary overlay is mounted over the rootfs when
+    /// `--tmp-overlay` is supported. Otherwise, and with the other backends,
+    /// the session runs in a copy of the rootfs that is removed on exit.
+    ///
+    /// # Example
+    /// ```
+    /// let code = Command::run_ephemeral("/my/rootfs".into(), Vec::new(), Some("apk add gcc".into()), true, false, false)?;
+    /// ```
+    pub fn run_ephemeral(
+        rootfs: String,
+        binds: Vec<Bind>, cmd: Option<String>,
+        use_root: bool, ignore_extra_bind: bool, no_group: bool,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        Self::execute(rootfs, binds, cmd, use_root, ignore_extra_bind, no_group, true)
+    }
+
+    fn execute(
+        rootfs: String,
+        binds: Vec<Bind>, cmd: Option<String>,
+        use_root: bool, ignore_extra_bind: bool, no_group: bool,
+        ephemeral: bool,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
         let name = env::current_exe()?.file_name().unwrap().to_str().unwrap().to_string();
         utils::check_rootfs_exists(name, rootfs.clone())?;
 
-        let comm = sett.cmd_rootfs;
-        let rootfs_cmd = utils::verify_and_download_rootfs_command(&comm)?;
+        let backend = backend::from_name(&Self::backend(&rootfs))?;
 
-        let args = match comm.as_str() {
-            "proot" => Self::build_proot_options(rootfs, args_bind.unwrap_or_default(), ignore_extra_bind, no_group),
-            "bwrap" => Self::build_bwrap_options(rootfs, args_bind.unwrap_or_default(), ignore_extra_bind, no_group),
-            other => return Err(format!("Unsupported rootfs command: {}", other).into()),
+        let overlay = ephemeral && backend.supports_tmp_overlay();
+        let staging = match ephemeral && !overlay {
+            true => Some(Self::stage_rootfs(&rootfs)?),
+            false => None,
         };
 
-        let new_cmd = cmd.unwrap_or_default();
-        let mut full_args: Vec<&str> = args.split_whitespace().collect();
+        let mut spec = Self::sandbox_spec(backend.as_ref(), &rootfs, binds, cmd, use_root, ignore_extra_bind, no_group)?;
+        spec.overlay = overlay;
+        if let Some(dir) = &staging {
+            spec.root = dir.clone();
+        }
 
-        let uid = Self::get_uid_from_passwd();
-        let str = match (comm.as_str(), use_root) {
-            ("proot", true) => "PS1=# |USER=root|LOGNAME=root|UID=0|EUID=0".to_string(),
-            ("proot", false) => format!("PS1=$ |UID={uid}|EUID={uid}"),
-            ("bwrap", true) => "PS1=# ".to_string(),
-            ("bwrap", false) => format!("PS1=$ |UID={uid}|EUID={uid}"),
-            _ => format!("PS1=$ |UID={uid}|EUID={uid}"),
-        };
+        // SAFETY: the handler does nothing and is reset to the default one in the child on exec.
+        let previous = staging
+            .is_some()
+            .then(|| unsafe { libc::signal(libc::SIGINT, ignore_signal as *const () as libc::sighandler_t) });
 
-        if comm == "proot" && use_root {
-            full_args.push("-0");
-        }
+        let code = backend.run(&spec);
 
-        if comm == "bwrap" && use_root {
-            full_args.extend([
-                "--uid", "0",
-                "--gid", "0",
-                "--setenv", "USER", "root",
-                "--setenv", "LOGNAME", "root"
-            ]);
+        if let Some(dir) = staging {
+            println!("Discarding the changes made to the rootfs...");
+            if let Err(e) = fs::remove_dir_all(&dir) {
+                eprintln!("\x1b[1;33mWarning\x1b[0m: Failed to remove '{}': {e}", dir.display());
+            }
         }
-
-        full_args.push("env");
-        full_args.extend_from_slice(&str.split('|').collect::<Vec<_>>());
-        full_args.extend([
-            "SHELL=/bin/sh",
-            "PATH=/bin:/sbin:/usr/bin:/usr/sbin:/usr/libexec",
-            "/bin/sh"
-        ]);
-
-        if !new_cmd.is_empty() {
-            full_args.push("-c");
-            full_args.push(&new_cmd);
+        if let Some(handler) = previous {
+            // SAFETY: puts back the handler replaced above, once the staging copy is removed.
+            unsafe {
+                libc::signal(libc::SIGINT, handler);
+            }
         }
 
-        let status = StdCommand::new(&rootfs_cmd)
-            .args(&full_args)
-            .stdin(Stdio::inherit())
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .status()?;
-
-        Ok(status.code().unwrap_or(-1))
+        Ok(code?)
     }
 
-    /// Builds the PRoot command-line options string.
+    /// Describes the session of a rootfs, before it is rendered for a backend.
     ///
-    /// # Parameters
-    /// - `rootfs`: Path to the root filesystem to be used with PRoot.
-    /// - `rootfs_args`: Additional arguments to append to the PRoot command.
-    /// - `no_extra_binds`: If true, skip adding optional binds like fonts and icons.
+    /// Besides the given binds, the spec mounts `/media`, `/mnt`, the local
+    /// repositories, the shared apk cache, the CA bundle, the binds recorded
+    /// in the registry, the `[[mounts]]` of the settings and, unless
+    /// `ignore_extra_bind` is set, the fonts, themes and cursors of the host.
     ///
     /// # Returns
-    /// * `String` - A full string of PRoot options to be passed to the command.
+    /// * `Ok(SandboxSpec)` rooted at `rootfs`.
+    /// * `Err` if a required mount source is missing, or the rootfs needs an emulator that is not available.
     ///
     /// # Example
     /// ```
-    /// let opts = build_proot_options("/my/rootfs".into(), "--cwd=/home/user".into(), false);
-    /// println!("Proot options: {}", opts);
+    /// let spec = sandbox_spec(backend.as_ref(), "/my/rootfs", Vec::new(), None, false, false, false)?;
     /// ```
-    fn build_proot_options(rootfs: String, rootfs_args: String, no_extra_binds: bool, no_group: bool) -> String {
-        let mut proot_options = format!("-R {rootfs} --bind=/media --bind=/mnt {rootfs_args}");
+    fn sandbox_spec(
+        backend: &dyn Backend, rootfs: &str,
+        binds: Vec<Bind>, cmd: Option<String>,
+        use_root: bool, ignore_extra_bind: bool, no_group: bool,
+    ) -> Result<SandboxSpec, Box<dyn std::error::Error>> {
+        let mut spec = SandboxSpec {
+            root: PathBuf::from(rootfs),
+            root_user: use_root,
+            rootfs_users: no_group,
+            ..Default::default()
+        };
 
-        if !no_extra_binds {
-            if Path::new("/etc/asound.conf").exists() {
-                proot_options.push_str(" --bind=/etc/asound.conf");
-            }
-            if Path::new("/etc/fonts").exists() {
-                proot_options.push_str(" --bind=/etc/fonts");
-            }
-            if Path::new("/usr/share/font-config").exists() {
-                proot_options.push_str(" --bind=/usr/share/font-config");
-            }
-            if Path::new("/usr/share/fontconfig").exists() {
-                proot_options.push_str(" --bind=/usr/share/fontconfig");
-            }
-            if Path::new("/usr/share/fonts").exists() {
-                proot_options.push_str(" --bind=/usr/share/fonts");
-            }
-            if Path::new("/usr/share/themes").exists() {
-                proot_options.push_str(" --bind=/usr/share/themes");
+        spec.mounts.extend(["/media", "/mnt"].map(|dir| Mount::new(dir, dir, false)));
+        for bind in binds {
+            match bind {
+                Bind::Mount(mount) => spec.mounts.push(mount),
+                Bind::Args(args) => spec.backend_args.extend(args),
             }
+        }
 
-            if no_group {
-                proot_options.push_str(format!(
-                    " --bind={rootfs}/etc/group:/etc/group \
-                    --bind={rootfs}/etc/passwd:/etc/passwd").as_str()
-                );
-            }
+        for repo in Self::local_repositories(rootfs) {
+            spec.mounts.push(Mount::new(&repo, &repo, true));
+        }
+        if let Some(cache) = Self::apk_cache_dir(rootfs) {
+            spec.mounts.push(Mount::new(cache, "/etc/apk/cache", false));
+        }
+        if let Some(ca) = Self::ca_bundle() {
+            spec.mounts.push(Mount::new(ca, SANDBOX_CA_BUNDLE, true));
+        }
+        for (host, guest) in Self::rootfs_binds(rootfs) {
+            spec.mounts.push(Mount::new(host, guest, false));
+        }
+        for entry in Settings::load_or_create().mounts.iter().filter(|m| m.applies_to(rootfs)) {
+            spec.mounts.extend(entry.to_mount()?);
+        }
+        if !ignore_extra_bind {
+            spec.mounts.extend(Self::host_extra_binds());
+        }
 
-            if let Ok(entries) = fs::read_dir("/usr/share/icons") {
-                for entry in entries.flatten() {
-                    let path = entry.path().join("cursors");
-                    if path.is_dir() {
-                        if let Some(dir_str) = path.to_str() {
-                            proot_options.push_str(" --bind=");
-                            proot_options.push_str(dir_str);
-                        }
-                    }
-                }
+        let arch = qemu::rootfs_arch(rootfs);
+        if qemu::needs_emulation(&arch) {
+            backend.emulate(&arch, &mut spec)?;
+        }
+
+        let env: Vec<String> = match use_root {
+            true => vec!["PS1=# ".into(), "USER=root".into(), "LOGNAME=root".into(), "UID=0".into(), "EUID=0".into()],
+            false => {
+                let uid = Self::get_uid_from_passwd();
+                vec!["PS1=$ ".into(), format!("UID={uid}"), format!("EUID={uid}")]
             }
+        };
+        spec.env.extend(env.into_iter().map(OsString::from));
+        spec.env.extend(Self::network_env().into_iter().map(OsString::from));
+        spec.env.extend(Self::rootfs_env(rootfs).into_iter().map(OsString::from));
+        spec.env.extend(["SHELL=/bin/sh".into(), format!("PATH={SANDBOX_PATH}").into()]);
+
+        spec.argv.push("/bin/sh".into());
+        if let Some(cmd) = cmd.filter(|c| !c.is_empty()) {
+            spec.argv.extend(["-c".into(), cmd.into()]);
         }
+        Ok(spec)
+    }
 
-        proot_options
+    /// Lists the optional host paths bound read-only into the rootfs: sound
+    /// configuration, fonts, themes and cursors.
+    fn host_extra_binds() -> Vec<Mount> {
+        let mut paths: Vec<PathBuf> = [
+            "/etc/asound.conf",
+            "/etc/fonts",
+            "/usr/share/font-config",
+            "/usr/share/fontconfig",
+            "/usr/share/fonts",
+            "/usr/share/themes",
+        ]
+        .into_iter()
+        .map(PathBuf::from)
+        .filter(|p| p.exists())
+        .collect();
+
+        if let Ok(entries) = fs::read_dir("/usr/share/icons") {
+            paths.extend(entries.flatten().map(|e| e.path().join("cursors")).filter(|p| p.is_dir()));
+        }
+        paths.into_iter().map(|p| Mount::new(&p, &p, true)).collect()
     }
 
-    /// Builds the command-line options for running a program inside Bubblewrap.
+    /// Copies the rootfs to a staging directory next to it for an ephemeral session.
     ///
-    /// This function generates a set of `--bind` and `--ro-bind` options for `bwrap`,
-    /// based on the provided root filesystem, extra bind arguments, and whether
-    /// to include additional system paths.
+    /// # Returns
+    /// * `Ok(PathBuf)` with the path of the copy.
+    /// * `Err` if the copy failed; the partial copy is removed.
+    fn stage_rootfs(rootfs: &str) -> io::Result<PathBuf> {
+        let rootfs = Path::new(rootfs.trim_end_matches('/'));
+        let name = rootfs.file_name().unwrap_or_default().to_string_lossy();
+        let staging = rootfs.with_file_name(format!("{name}.ephemeral-{}", std::process::id()));
+
+        println!("Copying the rootfs to {} for an ephemeral session...", staging.display());
+        if let Err(e) = utils::copy_tree(rootfs, &staging) {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e);
+        }
+        Ok(staging)
+    }
+
+    /// Returns the rootfs command used to run a rootfs.
+    ///
+    /// The backend recorded in the registry is used first, then the one
+    /// set in the configuration.
+    ///
+    /// # Example
+    /// ```
+    /// let comm = Command::backend("/my/rootfs"); // "proot", "bwrap" or "native"
+    /// ```
+    pub fn backend(rootfs: &str) -> String {
+        Registry::load()
+            .find_by_path(rootfs)
+            .map(|e| e.backend.clone())
+            .filter(|b| !b.is_empty())
+            .unwrap_or_else(|| Settings::load_or_create().cmd_rootfs)
+    }
+
+    /// Lists the repositories of the rootfs that live on the host filesystem.
+    ///
+    /// Local repositories (absolute paths or `file://` URLs in
+    /// `etc/apk/repositories`) must be bound into the sandbox for apk to reach them.
     ///
     /// # Parameters
     /// - `rootfs`: Path to the root filesystem.
-    /// - `rootfs_args`: Additional bind arguments passed as a string.
-    /// - `ignore_extra_binds`: If `true`, skip adding extra system binds.
     ///
     /// # Returns
-    /// A `String` containing the constructed Bubblewrap options.
+    /// A `Vec<String>` with the host paths of the local repositories.
     ///
     /// # Example
     /// ```
-    /// let opts = build_bwrap_options("/path/to/rootfs".to_string(), "".to_string(), false);
-    /// println!("bwrap options: {}", opts);
+    /// let repos = local_repositories("/my/rootfs");
     /// ```
-    fn build_bwrap_options(rootfs: String, rootfs_args: String, ignore_extra_binds: bool, no_group: bool) -> String {
-
-        let mut bwrap_options = format!(
-            "--unshare-user \
-             --share-net \
-             --bind {rootfs} / \
-             --die-with-parent \
-             --ro-bind-try /etc/host.conf /etc/host.conf \
-             --ro-bind-try /etc/hosts /etc/hosts \
-             --ro-bind-try /etc/hosts.equiv /etc/hosts.equiv \
-             --ro-bind-try /etc/netgroup /etc/netgroup \
-             --ro-bind-try /etc/networks /etc/networks \
-             --ro-bind-try /etc/nsswitch.conf /etc/nsswitch.conf \
-             --ro-bind-try /etc/resolv.conf /etc/resolv.conf \
-             --ro-bind-try /etc/localtime /etc/localtime \
-             --dev-bind /dev /dev \
-             --ro-bind /sys /sys \
-             --bind-try /proc /proc \
-             --bind-try /tmp /tmp \
-             --bind-try /run /run \
-             --ro-bind /var/run/dbus/system_bus_socket /var/run/dbus/system_bus_socket \
-             --bind {a} {a} \
-             --bind /media /media \
-             --bind /mnt /mnt \
-             {rootfs_args} \
-             --setenv PATH \"/bin:/sbin:/usr/bin:/usr/sbin:/usr/libexec\"", a = env::var("HOME").unwrap());
-
-        if !no_group {
-            bwrap_options.push_str(
-                " --ro-bind-try /etc/passwd /etc/passwd \
-                --ro-bind-try /etc/group /etc/group"
-            );
+    fn local_repositories(rootfs: &str) -> Vec<String> {
+        let content = fs::read_to_string(Path::new(rootfs).join("etc/apk/repositories")).unwrap_or_default();
+
+        content
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(|l| match l.starts_with('@') {
+                true => l.split_whitespace().nth(1).unwrap_or_default(),
+                false => l,
+            })
+            .filter_map(utils::local_path)
+            .filter(|p| Path::new(p).is_dir())
+            .map(|p| p.to_string())
+            .collect()
+    }
+
+    /// Returns the host directory bound as `/etc/apk/cache` when the shared apk cache is enabled.
+    ///
+    /// The cache is shared by every rootfs of the same architecture and
+    /// release, in `{cache_dir}/apk/<arch>/<release>`. The release is the one
+    /// recorded in the registry, or the branch of `etc/alpine-release`.
+    ///
+    /// # Parameters
+    /// - `rootfs`: Path to the root filesystem.
+    ///
+    /// # Returns
+    /// `Some(String)` with the cache directory, or `None` if the shared cache is disabled or unusable.
+    ///
+    /// # Example
+    /// ```
+    /// let cache = apk_cache_dir("/my/rootfs"); // Some("/home/user/.cache/ALPack/apk/x86_64/v3.20")
+    /// ```
+    fn apk_cache_dir(rootfs: &str) -> Option<String> {
+        let sett = Settings::load_or_create();
+        if !sett.apk_cache {
+            return None;
         }
 
-        Self::fix_mtab_symlink(Path::new(&rootfs.clone())).unwrap();
+        let release = Registry::load()
+            .find_by_path(rootfs)
+            .map(|e| e.release.clone())
+            .filter(|r| r.starts_with('v') || r == "edge")
+            .or_else(|| {
+                let version = fs::read_to_string(Path::new(rootfs).join("etc/alpine-release")).ok()?;
+                let version = version.trim();
+                match version.contains('_') {
+                    true => Some("edge".to_string()),
+                    false => version.rsplit_once('.').map(|(branch, _)| format!("v{branch}")),
+                }
+            })?;
+
+        let dir = Path::new(&sett.set_cache_dir())
+            .join(APK_CACHE_DIR)
+            .join(qemu::rootfs_arch(rootfs))
+            .join(release);
+        let dir = dir.display().to_string();
+
+        let mount = Path::new(rootfs).join("etc/apk/cache");
+        if let Err(e) = fs::create_dir_all(&dir).and_then(|_| match fs::symlink_metadata(&mount) {
+            Ok(_) => Ok(()),
+            Err(_) => fs::create_dir_all(&mount),
+        }) {
+            eprintln!("\x1b[1;33mWarning\x1b[0m: The shared apk cache is disabled: {e}");
+            return None;
+        }
+        Some(dir)
+    }
 
-        if !ignore_extra_binds {
-            if Path::new("/etc/asound.conf").exists() {
-                bwrap_options.push_str(" --ro-bind /etc/asound.conf /etc/asound.conf");
-            }
-            if Path::new("/etc/fonts").exists() {
-                bwrap_options.push_str(" --ro-bind /etc/fonts /etc/fonts");
-            }
-            if Path::new("/usr/share/font-config").exists() {
-                bwrap_options.push_str(" --ro-bind /usr/share/font-config /usr/share/font-config");
-            }
-            if Path::new("/usr/share/fontconfig").exists() {
-                bwrap_options.push_str(" --ro-bind /usr/share/fontconfig /usr/share/fontconfig");
-            }
-            if Path::new("/usr/share/fonts").exists() {
-                bwrap_options.push_str(" --ro-bind /usr/share/fonts /usr/share/fonts");
-            }
-            if Path::new("/usr/share/themes").exists() {
-                bwrap_options.push_str(" --ro-bind /usr/share/themes /usr/share/themes");
-            }
+    /// Returns the binds recorded for the rootfs in the registry.
+    ///
+    /// # Parameters
+    /// - `rootfs`: Path to the root filesystem.
+    ///
+    /// # Returns
+    /// A `Vec<(String, String)>` of host and guest paths; missing host paths are skipped with a warning.
+    ///
+    /// # Example
+    /// ```
+    /// let binds = rootfs_binds("/my/rootfs"); // [("/home/user/src", "/src")]
+    /// ```
+    fn rootfs_binds(rootfs: &str) -> Vec<(String, String)> {
+        let Some(entry) = Registry::load().find_by_path(rootfs).cloned() else {
+            return Vec::new();
+        };
 
-            if let Ok(entries) = fs::read_dir("/usr/share/icons") {
-                for entry in entries.flatten() {
-                    let path = entry.path().join("cursors");
-                    if path.is_dir() {
-                        if let Some(dir_str) = path.to_str() {
-                            bwrap_options.push_str(" --ro-bind ");
-                            bwrap_options.push_str(dir_str);
-                            bwrap_options.push(' ');
-                            bwrap_options.push_str(dir_str);
-                        }
-                    }
+        entry
+            .binds
+            .iter()
+            .filter_map(|b| b.split_once(':'))
+            .filter(|(host, _)| {
+                let found = Path::new(host).exists();
+                if !found {
+                    eprintln!("\x1b[1;33mWarning\x1b[0m: Bind source '{host}' does not exist, skipping it.");
                 }
-            }
-        }
+                found
+            })
+            .map(|(host, guest)| (host.to_string(), guest.to_string()))
+            .collect()
+    }
 
-        bwrap_options
+    /// Returns the variables recorded for the rootfs in the registry, as `NAME=value` assignments.
+    fn rootfs_env(rootfs: &str) -> Vec<String> {
+        Registry::load()
+            .find_by_path(rootfs)
+            .map(|e| e.env.iter().map(|(k, v)| format!("{k}={v}")).collect())
+            .unwrap_or_default()
     }
 
+    /// Returns the CA bundle of the settings, if one is set and usable.
+    fn ca_bundle() -> Option<String> {
+        let ca = Settings::load_or_create().ca_bundle;
+        if ca.is_empty() {
+            return None;
+        }
+        if !Path::new(&ca).is_file() {
+            eprintln!("\x1b[1;33mWarning\x1b[0m: The CA bundle '{ca}' is not bound into the rootfs, it is missing.");
+            return None;
+        }
+        Some(ca)
+    }
+
+    /// Builds the proxy and CA variables of the sandboxed processes.
+    ///
+    /// The proxy of the settings is used for the `http`, `https` and `ftp`
+    /// proxies, otherwise the proxy variables of the host are passed on, so
+    /// `apk` and other tools inside the rootfs use the same proxy as ALPack.
+    ///
+    /// # Returns
+    /// A `Vec<String>` of `NAME=value` assignments for `env`.
+    ///
+    /// # Example
+    /// ```
+    /// let vars = network_env(); // ["http_proxy=http://proxy:3128", "HTTP_PROXY=http://proxy:3128", ...]
+    /// ```
+    fn network_env() -> Vec<String> {
+        let sett = Settings::load_or_create();
+        let mut vars = Vec::new();
+
+        for name in PROXY_VARS {
+            let value = match name {
+                "no_proxy" if !sett.no_proxy.is_empty() => sett.no_proxy.clone(),
+                "http_proxy" | "https_proxy" | "ftp_proxy" if !sett.proxy.is_empty() => sett.proxy.clone(),
+                _ => match env::var(name).or_else(|_| env::var(name.to_uppercase())) {
+                    Ok(value) => value,
+                    Err(_) => continue,
+                },
+            };
+            vars.push(format!("{name}={value}"));
+            vars.push(format!("{}={value}", name.to_uppercase()));
+        }
+
+        if Self::ca_bundle().is_some() {
+            vars.push(format!("SSL_CERT_FILE={SANDBOX_CA_BUNDLE}"));
+        }
+        vars
+    }
 
     /// Attempts to retrieve the current user's UID by parsing `/etc/passwd`.
     ///
@@ -263,6 +453,7 @@ impl Command {
     /// ```
     /// fix_mtab_symlink("/my/rootfs".to_string());
     /// ```
+    #[allow(clippy::collapsible_if, clippy::single_match)]
     pub fn fix_mtab_symlink(rootfs: &Path) -> io::Result<()> {
         use std::os::unix::fs::symlink;
 
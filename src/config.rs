@@ -1,9 +1,11 @@
-use crate::parse_key_value;
+use crate::mirror::Mirror;
 use crate::settings::Settings;
 use crate::utils::_parse_key_value;
+use crate::{download, parse_key_value};
 
 use std::collections::VecDeque;
 use std::error::Error;
+use std::fs;
 
 pub struct Config {
     name: String,
@@ -30,11 +32,38 @@ impl Config {
                 "--use-bwrap" => {
                     sett.cmd_rootfs = "bwrap".to_string();
                 },
+                "--use-native" => {
+                    sett.cmd_rootfs = "native".to_string();
+                },
                 "--use-latest-stable" => {
                     sett.release = "latest-stable".to_string();
+                    sett.version = String::new();
                 },
                 "--use-edge" => {
                     sett.release = "edge".to_string();
+                    sett.version = String::new();
+                },
+                "--apk-cache" => {
+                    sett.apk_cache = true;
+                },
+                "--no-apk-cache" => {
+                    sett.apk_cache = false;
+                },
+                a if a.starts_with("--release=") => {
+                    let release = parse_key_value!("config", "release", arg)?.unwrap();
+                    self.set_release(&mut sett, release)?;
+                }
+                "--release" => {
+                    let release = parse_key_value!("config", "release", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                    self.set_release(&mut sett, release)?;
+                },
+                a if a.starts_with("--version=") => {
+                    let version = parse_key_value!("config", "version", arg)?.unwrap();
+                    self.set_version(&mut sett, version)?;
+                }
+                "--version" => {
+                    let version = parse_key_value!("config", "version", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                    self.set_version(&mut sett, version)?;
                 },
                 a if a.starts_with("--cache-dir=") => {
                     sett.cache_dir = parse_key_value!("config", "directory", arg)?.unwrap();
@@ -48,12 +77,50 @@ impl Config {
                 "--rootfs-dir" => {
                     sett.rootfs_dir = parse_key_value!("config", "directory", arg, args.pop_front().unwrap_or_default())?.unwrap();
                 },
+                a if a.starts_with("--envs-dir=") => {
+                    sett.envs_dir = parse_key_value!("config", "directory", arg)?.unwrap();
+                }
+                "--envs-dir" => {
+                    sett.envs_dir = parse_key_value!("config", "directory", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                },
                 a if a.starts_with("--output-dir=") => {
                     sett.rootfs_dir = parse_key_value!("config", "directory", arg)?.unwrap();
                 }
                 "--output-dir" => {
                     sett.rootfs_dir = parse_key_value!("config", "directory", arg, args.pop_front().unwrap_or_default())?.unwrap();
                 },
+                a if a.starts_with("--default-preset=") => {
+                    let preset = parse_key_value!("config", "preset", arg)?.unwrap();
+                    self.set_default_preset(&mut sett, preset)?;
+                }
+                "--default-preset" => {
+                    let preset = parse_key_value!("config", "preset", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                    self.set_default_preset(&mut sett, preset)?;
+                },
+                a if a.starts_with("--proxy=") => {
+                    let proxy = parse_key_value!("config", "url", arg)?.unwrap();
+                    self.set_proxy(&mut sett, proxy)?;
+                }
+                "--proxy" => {
+                    let proxy = parse_key_value!("config", "url", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                    self.set_proxy(&mut sett, proxy)?;
+                },
+                a if a.starts_with("--no-proxy=") => {
+                    let hosts = parse_key_value!("config", "hosts", arg)?.unwrap();
+                    sett.no_proxy = if hosts == "none" { String::new() } else { hosts };
+                }
+                "--no-proxy" => {
+                    let hosts = parse_key_value!("config", "hosts", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                    sett.no_proxy = if hosts == "none" { String::new() } else { hosts };
+                },
+                a if a.starts_with("--ca-bundle=") => {
+                    let file = parse_key_value!("config", "file", arg)?.unwrap();
+                    self.set_ca_bundle(&mut sett, file)?;
+                }
+                "--ca-bundle" => {
+                    let file = parse_key_value!("config", "file", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                    self.set_ca_bundle(&mut sett, file)?;
+                },
                 a if a.starts_with("--default-mirror=") => {
                     sett.default_mirror = parse_key_value!("config", "mirror", arg)?.unwrap();
                 }
@@ -72,4 +139,108 @@ impl Config {
         }
         Ok(())
     }
+
+    /// Sets the release branch, dropping a pinned version from another branch.
+    ///
+    /// # Parameters
+    /// - `sett`: The settings being modified.
+    /// - `release`: The release branch (`latest-stable`, `edge` or `vX.Y`).
+    ///
+    /// # Returns
+    /// - `Ok(())` on success.
+    /// - `Err` if the release name is invalid.
+    fn set_release(&self, sett: &mut Settings, release: String) -> Result<(), Box<dyn Error>> {
+        if !Mirror::is_valid_release(&release) {
+            return Err(format!("{}: config: invalid release '{release}', expected 'latest-stable', 'edge' or 'vX.Y'", self.name).into());
+        }
+        Mirror::check_release(&sett.default_mirror, &release)
+            .map_err(|e| format!("{}: config: {e}", self.name))?;
+        if Mirror::release_from_version(&sett.version).as_ref() != Some(&release) {
+            sett.version = String::new();
+        }
+        sett.release = release;
+        Ok(())
+    }
+
+    /// Pins the Alpine point version and the release branch it belongs to.
+    ///
+    /// # Parameters
+    /// - `sett`: The settings being modified.
+    /// - `version`: The point version (`X.Y.Z`), or `latest` to remove the pin.
+    ///
+    /// # Returns
+    /// - `Ok(())` on success.
+    /// - `Err` if the version is invalid.
+    fn set_version(&self, sett: &mut Settings, version: String) -> Result<(), Box<dyn Error>> {
+        if version == "latest" {
+            sett.version = String::new();
+            return Ok(());
+        }
+        let release = Mirror::release_from_version(&version).ok_or_else(|| {
+            format!("{}: config: invalid version '{version}', expected 'X.Y.Z'", self.name)
+        })?;
+        sett.release = release;
+        sett.version = version;
+        Ok(())
+    }
+
+    /// Sets the proxy used for downloads and inside the rootfs.
+    ///
+    /// # Parameters
+    /// - `sett`: The settings being modified.
+    /// - `proxy`: The proxy URL, or `none` to use the `*_proxy` environment variables.
+    ///
+    /// # Returns
+    /// - `Ok(())` on success.
+    /// - `Err` if the proxy URL is invalid.
+    fn set_proxy(&self, sett: &mut Settings, proxy: String) -> Result<(), Box<dyn Error>> {
+        if proxy == "none" {
+            sett.proxy = String::new();
+            return Ok(());
+        }
+        download::proxy(&proxy, "").map_err(|e| format!("{}: config: invalid proxy '{proxy}': {e}", self.name))?;
+        sett.proxy = proxy;
+        Ok(())
+    }
+
+    /// Sets the CA bundle trusted by downloads and inside the rootfs.
+    ///
+    /// # Parameters
+    /// - `sett`: The settings being modified.
+    /// - `file`: A PEM file with the trusted certificates, or `none` for the built-in ones.
+    ///
+    /// # Returns
+    /// - `Ok(())` on success.
+    /// - `Err` if the file does not exist.
+    fn set_ca_bundle(&self, sett: &mut Settings, file: String) -> Result<(), Box<dyn Error>> {
+        if file == "none" {
+            sett.ca_bundle = String::new();
+            return Ok(());
+        }
+        let path = fs::canonicalize(&file).map_err(|e| format!("{}: config: invalid CA bundle '{file}': {e}", self.name))?;
+        sett.ca_bundle = path.display().to_string();
+        Ok(())
+    }
+
+    /// Sets the package preset installed by `setup`.
+    ///
+    /// # Parameters
+    /// - `sett`: The settings being modified.
+    /// - `preset`: The name of a built-in or configured preset.
+    ///
+    /// # Returns
+    /// - `Ok(())` on success.
+    /// - `Err` if the preset does not exist.
+    fn set_default_preset(&self, sett: &mut Settings, preset: String) -> Result<(), Box<dyn Error>> {
+        if sett.preset(&preset).is_none() {
+            return Err(format!(
+                "{}: config: unknown preset '{preset}'\nAvailable presets: {}",
+                self.name,
+                sett.preset_names().join(", ")
+            )
+            .into());
+        }
+        sett.default_preset = preset;
+        Ok(())
+    }
 }
@@ -0,0 +1,557 @@
+use crate::backend;
+use crate::mirror::Mirror;
+use crate::registry::Registry;
+use crate::settings::Settings;
+use crate::utils::{self, _parse_key_value};
+use crate::{parse_key_value, qemu};
+
+use regex::Regex;
+use std::collections::{HashSet, VecDeque};
+use std::error::Error;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use which::which;
+
+/// Socket bound unconditionally into every bwrap sandbox.
+const DBUS_SOCKET: &str = "/var/run/dbus/system_bus_socket";
+
+/// Collects the results of the checks and prints them as they come.
+#[derive(Default)]
+struct Report {
+    warnings: usize,
+    failures: usize,
+}
+
+impl Report {
+    fn section(&self, title: &str) {
+        println!("\n\x1b[1m{title}\x1b[0m");
+    }
+
+    fn ok(&mut self, msg: &str) {
+        println!("  \x1b[1;32m[ ok ]\x1b[0m {msg}");
+    }
+
+    fn warn(&mut self, msg: &str, fix: &str) {
+        self.warnings += 1;
+        println!("  \x1b[1;33m[warn]\x1b[0m {msg}");
+        Self::fix(fix);
+    }
+
+    fn fail(&mut self, msg: &str, fix: &str) {
+        self.failures += 1;
+        println!("  \x1b[1;31m[fail]\x1b[0m {msg}");
+        Self::fix(fix);
+    }
+
+    fn fix(fix: &str) {
+        for line in fix.lines() {
+            println!("         fix: {line}");
+        }
+    }
+}
+
+/// A package of the apk database (`lib/apk/db/installed`).
+#[derive(Default)]
+struct Package {
+    name: String,
+    provides: Vec<String>,
+    files: Vec<String>,
+}
+
+pub struct Doctor {
+    name: String,
+    remaining_args: Vec<String>,
+}
+
+impl Doctor {
+    pub fn new(name: String, remaining_args: Vec<String>) -> Self {
+        Doctor {
+            name,
+            remaining_args,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<_> = self.remaining_args.clone().into();
+        let mut rootfs_dir: Option<String> = None;
+
+        while let Some(arg) = args.pop_front() {
+            match arg.as_str() {
+                a if a.starts_with("--env=") => {
+                    let env = parse_key_value!("doctor", "name", arg)?.unwrap();
+                    rootfs_dir = Some(Registry::resolve(&env)?);
+                }
+                "--env" => {
+                    let env = parse_key_value!("doctor", "name", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                    rootfs_dir = Some(Registry::resolve(&env)?);
+                }
+                a if a.starts_with("--rootfs=") => {
+                    rootfs_dir = parse_key_value!("doctor", "directory", arg)?;
+                }
+                "-R" | "--rootfs" => {
+                    rootfs_dir = parse_key_value!("doctor", "directory", arg, args.pop_front().unwrap_or_default())?;
+                }
+                _ => {
+                    return Err(format!("{c}: doctor: invalid argument '{arg}'\nUse '{c} --help' to see available options.", c = self.name).into())
+                }
+            }
+        }
+
+        // The configuration is checked before it is loaded, as an invalid
+        // one would be replaced by the default settings.
+        let mut report = Report::default();
+        report.section("Configuration");
+        let sett = self.check_config(&mut report).unwrap_or_default();
+
+        let rootfs_dir = rootfs_dir.unwrap_or_else(|| sett.set_rootfs());
+        let backend = Registry::load()
+            .find_by_path(&rootfs_dir)
+            .map(|e| e.backend.clone())
+            .filter(|b| !b.is_empty())
+            .unwrap_or(sett.cmd_rootfs.clone());
+
+        report.section("Host");
+        self.check_backend(&mut report, &backend);
+        if backend != "proot" {
+            self.check_user_namespaces(&mut report, &backend);
+        }
+        if backend == "bwrap" {
+            self.check_dbus(&mut report);
+        }
+
+        report.section(&format!("Rootfs ({rootfs_dir})"));
+        if self.check_rootfs_dir(&mut report, &rootfs_dir) {
+            self.check_emulation(&mut report, &rootfs_dir, &backend);
+            self.check_repositories(&mut report, &rootfs_dir);
+            self.check_mtab(&mut report, &rootfs_dir, &backend);
+            self.check_apk_db(&mut report, &rootfs_dir);
+        }
+
+        println!("\n{}", utils::separator_line());
+        println!("  {} problem(s), {} warning(s) found.", report.failures, report.warnings);
+        println!("{}", utils::separator_line());
+
+        if report.failures > 0 {
+            return Err(format!("{}: doctor: {} problem(s) found", self.name, report.failures).into());
+        }
+        Ok(())
+    }
+
+    /// Checks that the rootfs command is installed, or can be downloaded.
+    fn check_backend(&self, report: &mut Report, backend: &str) {
+        if !backend::BACKENDS.contains(&backend) {
+            report.fail(
+                &format!("Unsupported rootfs command '{backend}'"),
+                &format!("{} config --use-proot", self.name),
+            );
+            return;
+        }
+        if backend == "native" {
+            report.ok("native: built into ALPack, no helper binary needed");
+            return;
+        }
+
+        match utils::find_rootfs_command(backend) {
+            Some(path) => report.ok(&format!("{backend}: {}", path.display())),
+            None if utils::is_x86_64() => report.warn(
+                &format!("{backend} is not installed, a static binary will be downloaded to ~/.local/bin on first use"),
+                &format!("install the '{backend}' package of your distribution to use it offline"),
+            ),
+            None => report.fail(
+                &format!("{backend} is not installed and no static binary is available for {}", std::env::consts::ARCH),
+                &format!("install the '{backend}' package of your distribution (e.g. 'sudo apt install {}')", match backend {
+                    "bwrap" => "bubblewrap",
+                    other => other,
+                }),
+            ),
+        }
+    }
+
+    /// Checks that bwrap or the native backend can create unprivileged user namespaces.
+    fn check_user_namespaces(&self, report: &mut Report, backend: &str) {
+        let setuid = backend == "bwrap" && utils::find_rootfs_command("bwrap")
+            .and_then(|p| fs::metadata(p).ok())
+            .is_some_and(|m| m.permissions().mode() & 0o4000 != 0);
+        if setuid {
+            report.ok("bwrap is setuid root, user namespaces are not required");
+            return;
+        }
+
+        let sysctl = |path: &str| fs::read_to_string(path).ok().map(|v| v.trim().to_string());
+        let mut found = false;
+
+        if sysctl("/proc/sys/kernel/unprivileged_userns_clone").as_deref() == Some("0") {
+            found = true;
+            report.fail(
+                "Unprivileged user namespaces are disabled (kernel.unprivileged_userns_clone = 0)",
+                "sudo sysctl -w kernel.unprivileged_userns_clone=1",
+            );
+        }
+        if sysctl("/proc/sys/user/max_user_namespaces").as_deref() == Some("0") {
+            found = true;
+            report.fail(
+                "User namespaces are disabled (user.max_user_namespaces = 0)",
+                "sudo sysctl -w user.max_user_namespaces=15000",
+            );
+        }
+        if sysctl("/proc/sys/kernel/apparmor_restrict_unprivileged_userns").as_deref() == Some("1") {
+            found = true;
+            report.fail(
+                "AppArmor restricts unprivileged user namespaces (kernel.apparmor_restrict_unprivileged_userns = 1)",
+                &format!(
+                    "add an AppArmor profile allowing 'userns' for {}, or run 'sudo sysctl -w kernel.apparmor_restrict_unprivileged_userns=0'\n\
+                     or switch to proot: '{} config --use-proot'",
+                    if backend == "bwrap" { "bwrap" } else { "ALPack" },
+                    self.name
+                ),
+            );
+        }
+        if !found {
+            report.ok("Unprivileged user namespaces are enabled");
+        }
+    }
+
+    /// Checks the D-Bus socket that bwrap binds into the sandbox.
+    fn check_dbus(&self, report: &mut Report) {
+        match Path::new(DBUS_SOCKET).exists() {
+            true => report.ok(&format!("D-Bus system socket: {DBUS_SOCKET}")),
+            false => report.fail(
+                &format!("{DBUS_SOCKET} does not exist, bwrap fails to bind it"),
+                &format!("start the system D-Bus daemon (e.g. 'sudo systemctl start dbus'), or use proot: '{} config --use-proot'", self.name),
+            ),
+        }
+    }
+
+    /// Checks that the configuration and the registry parse and hold valid values.
+    ///
+    /// # Returns
+    /// * `Some(Settings)` with the configuration if it could be parsed.
+    fn check_config(&self, report: &mut Report) -> Option<Settings> {
+        let path = Settings::config_dir().join("config.toml");
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(_) if !path.exists() => {
+                report.warn(
+                    &format!("{} does not exist, the default settings are used", path.display()),
+                    &format!("run '{} config' to create it", self.name),
+                );
+                return None;
+            }
+            Err(e) => {
+                report.fail(
+                    &format!("{} cannot be read: {e}", path.display()),
+                    &format!("check the permissions of {}", path.display()),
+                );
+                return None;
+            }
+        };
+
+        let sett: Settings = match toml::from_str(&content) {
+            Ok(sett) => sett,
+            Err(e) => {
+                report.fail(
+                    &format!("{} is invalid and will be replaced by the default settings: {}", path.display(), e.message().trim()),
+                    &format!("fix or remove {}", path.display()),
+                );
+                return None;
+            }
+        };
+        report.ok(&format!("{} is valid", path.display()));
+
+        if !backend::BACKENDS.contains(&sett.cmd_rootfs.as_str()) {
+            report.fail(
+                &format!("cmd_rootfs = '{}' is not one of: {}", sett.cmd_rootfs, backend::BACKENDS.join(", ")),
+                &format!("{} config --use-proot", self.name),
+            );
+        }
+        if !Mirror::is_valid_release(&sett.release) {
+            report.fail(
+                &format!("release = '{}' is not 'latest-stable', 'edge' or 'vX.Y'", sett.release),
+                &format!("{} config --release latest-stable", self.name),
+            );
+        }
+        if sett.preset(&sett.default_preset).is_none() {
+            report.fail(
+                &format!("default_preset = '{}' is not a known preset", sett.default_preset),
+                &format!("{} config --default-preset sdk", self.name),
+            );
+        }
+        for entry in &sett.mounts {
+            if let Err(e) = entry.to_mount() {
+                report.fail(
+                    &format!("Mount '{}': {e}", entry.source),
+                    &format!("fix the [[mounts]] entry in {}, or set 'optional = true'", path.display()),
+                );
+            }
+        }
+        for step in sett.provision.iter().filter(|s| !s.script.is_empty()) {
+            if !Path::new(&step.script).is_file() {
+                report.fail(
+                    &format!("Provisioning script '{}' not found", step.script),
+                    &format!("fix or remove the [[provision]] entry in {}", path.display()),
+                );
+            }
+        }
+
+        let registry = Registry::path();
+        if let Ok(content) = fs::read_to_string(&registry) {
+            match toml::from_str::<Registry>(&content) {
+                Ok(_) => report.ok(&format!("{} is valid", registry.display())),
+                Err(e) => report.fail(
+                    &format!("{} is invalid, known environments are ignored: {}", registry.display(), e.message()),
+                    &format!("fix or remove {}", registry.display()),
+                ),
+            }
+        }
+        Some(sett)
+    }
+
+    /// Checks that the rootfs exists and looks like an Alpine rootfs.
+    ///
+    /// # Returns
+    /// * `true` if the remaining rootfs checks can run.
+    fn check_rootfs_dir(&self, report: &mut Report, rootfs: &str) -> bool {
+        let root = Path::new(rootfs);
+        if !root.is_dir() {
+            report.fail("The rootfs directory does not exist", &format!("{} setup -R {rootfs}", self.name));
+            return false;
+        }
+        if fs::symlink_metadata(root.join("bin/sh")).is_err() || !root.join("etc/apk").is_dir() {
+            report.fail(
+                "The rootfs is incomplete (missing /bin/sh or /etc/apk)",
+                &format!("{} setup -R {rootfs} --reinstall", self.name),
+            );
+            return false;
+        }
+
+        let version = fs::read_to_string(root.join("etc/alpine-release")).unwrap_or_default();
+        report.ok(&format!("Alpine Linux {}", version.trim()));
+        true
+    }
+
+    /// Checks that a foreign-architecture rootfs can be run.
+    fn check_emulation(&self, report: &mut Report, rootfs: &str, backend: &str) {
+        let arch = qemu::rootfs_arch(rootfs);
+        if !qemu::needs_emulation(&arch) {
+            return;
+        }
+
+        if backend != "proot" {
+            match qemu::binfmt_interpreter(&arch) {
+                Ok(_) => report.ok(&format!("binfmt_misc handler registered for {arch}")),
+                Err(e) => report.fail(&e.to_string().replace('\n', " "), "install qemu-user-static and binfmt support on the host"),
+            }
+            return;
+        }
+
+        let Some(qemu) = qemu::qemu_arch(&arch) else {
+            report.fail(&format!("Unsupported architecture '{arch}'"), &format!("{} setup -R {rootfs} --reinstall", self.name));
+            return;
+        };
+        let found = std::env::var("ALPACK_QEMU")
+            .ok()
+            .filter(|p| Path::new(p).is_file())
+            .map(Into::into)
+            .or_else(|| which(format!("qemu-{qemu}-static")).ok())
+            .or_else(|| utils::find_rootfs_command(&format!("qemu-{qemu}")));
+        match found {
+            Some(path) => report.ok(&format!("{arch} binaries run through {}", path.display())),
+            None => report.fail(
+                &format!("qemu-{qemu} is required to run {arch} binaries"),
+                "install qemu-user-static on the host, or set ALPACK_QEMU",
+            ),
+        }
+    }
+
+    /// Checks that `etc/apk/repositories` lists valid repositories of one branch.
+    fn check_repositories(&self, report: &mut Report, rootfs: &str) {
+        let file = Path::new(rootfs).join("etc/apk/repositories");
+        let content = fs::read_to_string(&file).unwrap_or_default();
+        let lines: Vec<(usize, &str)> = content
+            .lines()
+            .map(|l| l.trim())
+            .enumerate()
+            .filter(|(_, l)| !l.is_empty() && !l.starts_with('#'))
+            .collect();
+        if lines.is_empty() {
+            report.fail(
+                "/etc/apk/repositories is missing or empty",
+                &format!("{} setup -R {rootfs} --reinstall", self.name),
+            );
+            return;
+        }
+
+        let branch_re = Regex::new(r"/(v\d+\.\d+|edge|latest-stable)/[^/]+$").unwrap();
+        let mut branches: Vec<String> = Vec::new();
+        let mut valid = true;
+
+        for (n, line) in &lines {
+            let (tagged, url) = match line.strip_prefix('@') {
+                Some(rest) => (true, rest.split_whitespace().nth(1).unwrap_or_default()),
+                None => (false, *line),
+            };
+
+            let remote = ["http://", "https://", "ftp://"].iter().any(|s| url.starts_with(s));
+            match utils::local_path(url) {
+                Some(path) if !Path::new(path).is_dir() => {
+                    valid = false;
+                    report.warn(
+                        &format!("Local repository '{path}' at line {} does not exist", n + 1),
+                        &format!("create it, or remove the line from {}", file.display()),
+                    );
+                }
+                Some(_) => {}
+                None if !remote => {
+                    valid = false;
+                    report.fail(
+                        &format!("Invalid repository '{line}' at line {}", n + 1),
+                        &format!("fix or remove the line in {}", file.display()),
+                    );
+                }
+                None => {}
+            }
+
+            if !tagged
+                && let Some(caps) = branch_re.captures(url)
+                && !branches.contains(&caps[1].to_string())
+            {
+                branches.push(caps[1].to_string());
+            }
+        }
+
+        if branches.len() > 1 {
+            valid = false;
+            report.warn(
+                &format!("/etc/apk/repositories mixes release branches: {}", branches.join(", ")),
+                &format!("{} upgrade-release -R {rootfs} --to <BRANCH>", self.name),
+            );
+        }
+
+        let release = Registry::load().find_by_path(rootfs).map(|e| e.release.clone()).unwrap_or_default();
+        if let [branch] = branches.as_slice()
+            && !release.is_empty()
+            && release != *branch
+        {
+            valid = false;
+            report.warn(
+                &format!("The rootfs was set up for {release} but its repositories use {branch}"),
+                &format!("{} upgrade-release -R {rootfs} --to {branch}", self.name),
+            );
+        }
+
+        if valid {
+            report.ok(&format!("/etc/apk/repositories: {} repositories", lines.len()));
+        }
+    }
+
+    /// Checks that `etc/mtab` points to the mount table of the process.
+    ///
+    /// bwrap recreates the symlink on every run, so a wrong one is only a
+    /// warning there.
+    fn check_mtab(&self, report: &mut Report, rootfs: &str, backend: &str) {
+        let mtab = Path::new(rootfs).join("etc/mtab");
+        let fix = format!("ln -sf /proc/self/mounts {}", mtab.display());
+
+        let problem = match fs::read_link(&mtab) {
+            Ok(target) if ["/proc/self/mounts", "/proc/mounts", "../proc/self/mounts", "../proc/mounts"]
+                .iter()
+                .any(|t| target == Path::new(t)) =>
+            {
+                report.ok(&format!("/etc/mtab -> {}", target.display()));
+                return;
+            }
+            Ok(target) => format!("/etc/mtab points to {} instead of /proc/self/mounts", target.display()),
+            Err(_) if mtab.exists() => "/etc/mtab is a regular file instead of a symlink to /proc/self/mounts".to_string(),
+            Err(_) => "/etc/mtab is missing".to_string(),
+        };
+
+        match backend {
+            "bwrap" | "native" => report.warn(&format!("{problem} (fixed on the next run)"), &fix),
+            _ => report.fail(&problem, &fix),
+        }
+    }
+
+    /// Checks that the apk database matches the world file and the installed files.
+    fn check_apk_db(&self, report: &mut Report, rootfs: &str) {
+        let root = Path::new(rootfs);
+        let apk_fix = format!("{} apk -R {rootfs} fix", self.name);
+
+        let Ok(installed) = fs::read_to_string(root.join("lib/apk/db/installed")) else {
+            report.fail("The apk database (/lib/apk/db/installed) is missing", &format!("{} setup -R {rootfs} --reinstall", self.name));
+            return;
+        };
+        let packages = Self::parse_installed(&installed);
+
+        let mut seen: HashSet<&str> = HashSet::new();
+        let duplicates: Vec<&str> = packages.iter().map(|p| p.name.as_str()).filter(|n| !seen.insert(n)).collect();
+        if !duplicates.is_empty() {
+            report.fail(&format!("Packages recorded more than once: {}", duplicates.join(", ")), &apk_fix);
+        }
+
+        let provided: HashSet<&str> = packages
+            .iter()
+            .flat_map(|p| std::iter::once(p.name.as_str()).chain(p.provides.iter().map(|s| s.as_str())))
+            .collect();
+        let world = fs::read_to_string(root.join("etc/apk/world")).unwrap_or_default();
+        let missing: Vec<&str> = world
+            .split_whitespace()
+            .filter(|w| !w.starts_with('!'))
+            .map(|w| w.split(['<', '>', '=', '~', '@']).next().unwrap_or_default())
+            .filter(|w| !w.is_empty() && !provided.contains(w))
+            .collect();
+        if !missing.is_empty() {
+            report.fail(
+                &format!("/etc/apk/world requires packages that are not installed: {}", missing.join(", ")),
+                &format!("{} apk -R {rootfs} add {}", self.name, missing.join(" ")),
+            );
+        }
+
+        let mut broken: Vec<String> = Vec::new();
+        let mut lost = 0;
+        for pkg in &packages {
+            let count = pkg.files.iter().filter(|f| fs::symlink_metadata(root.join(f)).is_err()).count();
+            if count > 0 {
+                lost += count;
+                broken.push(pkg.name.clone());
+            }
+        }
+        if !broken.is_empty() {
+            broken.truncate(5);
+            report.fail(
+                &format!("{lost} installed file(s) are missing (packages: {})", broken.join(", ")),
+                &format!("{} apk -R {rootfs} fix --reinstall {}", self.name, broken.join(" ")),
+            );
+        }
+
+        if duplicates.is_empty() && missing.is_empty() && lost == 0 {
+            report.ok(&format!("apk database: {} packages installed", packages.len()));
+        }
+    }
+
+    /// Parses the apk database into its packages.
+    fn parse_installed(content: &str) -> Vec<Package> {
+        let mut packages = Vec::new();
+
+        for block in content.split("\n\n").filter(|b| !b.trim().is_empty()) {
+            let mut pkg = Package::default();
+            let mut dir = String::new();
+
+            for line in block.lines() {
+                let Some((key, value)) = line.split_once(':') else { continue };
+                match key {
+                    "P" => pkg.name = value.to_string(),
+                    "p" => pkg.provides.extend(value.split_whitespace().map(|p| p.split('=').next().unwrap_or_default().to_string())),
+                    "F" => dir = value.to_string(),
+                    "R" => pkg.files.push(match dir.is_empty() {
+                        true => value.to_string(),
+                        false => format!("{dir}/{value}"),
+                    }),
+                    _ => {}
+                }
+            }
+            packages.push(pkg);
+        }
+        packages
+    }
+}
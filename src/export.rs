@@ -0,0 +1,470 @@
+use crate::registry::Registry;
+use crate::settings::Settings;
+use crate::snapshot::Snapshot;
+use crate::utils::{self, _parse_key_value};
+use crate::{parse_key_value, qemu, verify};
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use tar::{Builder, EntryType, Header, HeaderMode};
+
+/// Media types of the OCI image specification.
+const OCI_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+const OCI_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+const OCI_CONFIG: &str = "application/vnd.oci.image.config.v1+json";
+const OCI_LAYER: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+
+/// Content of the `oci-layout` file.
+const OCI_LAYOUT: &str = r#"{"imageLayoutVersion":"1.0.0"}"#;
+
+/// `PATH` of the image, the default one of Alpine Linux.
+const IMAGE_PATH: &str = "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+pub struct Export {
+    name: String,
+    remaining_args: Vec<String>,
+}
+
+/// A blob of the image, identified by its digest.
+struct Blob {
+    digest: String,
+    size: u64,
+}
+
+/// A writer computing the SHA-256 digest and size of the bytes written through it.
+struct DigestWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<W: Write> DigestWriter<W> {
+    fn new(inner: W) -> Self {
+        DigestWriter { inner, hasher: Sha256::new(), size: 0 }
+    }
+
+    /// Returns the inner writer and the blob of the written bytes.
+    fn finish(self) -> (W, Blob) {
+        let digest = format!("sha256:{}", verify::hex(&self.hasher.finalize()));
+        (self.inner, Blob { digest, size: self.size })
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Export {
+    pub fn new(name: String, remaining_args: Vec<String>) -> Self {
+        Export {
+            name,
+            remaining_args,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<_> = self.remaining_args.clone().into();
+        let sett = Settings::load_or_create();
+        let mut rootfs_dir: String = sett.set_rootfs();
+        let (mut format, mut output, mut tag): (Option<String>, Option<String>, Option<String>) = (None, None, None);
+
+        while let Some(arg) = args.pop_front() {
+            match arg.as_str() {
+                a if a.starts_with("--format=") => {
+                    format = parse_key_value!("export", "format", arg)?;
+                }
+                "--format" => {
+                    format = parse_key_value!("export", "format", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with("--output=") => {
+                    output = parse_key_value!("export", "file", arg)?;
+                }
+                "-o" | "--output" => {
+                    output = parse_key_value!("export", "file", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with("--tag=") => {
+                    tag = parse_key_value!("export", "name", arg)?;
+                }
+                "--tag" => {
+                    tag = parse_key_value!("export", "name", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with("--env=") => {
+                    let env = parse_key_value!("export", "name", arg)?.unwrap();
+                    rootfs_dir = Registry::resolve(&env)?;
+                }
+                "--env" => {
+                    let env = parse_key_value!("export", "name", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                    rootfs_dir = Registry::resolve(&env)?;
+                }
+                a if a.starts_with("--rootfs=") => {
+                    rootfs_dir = parse_key_value!("export", "directory", arg)?.unwrap();
+                }
+                "-R" | "--rootfs" => {
+                    rootfs_dir = parse_key_value!("export", "directory", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                }
+                _ => {
+                    return Err(format!("{c}: export: invalid argument '{arg}'\nUse '{c} --help' to see available options.", c = self.name).into())
+                }
+            }
+        }
+
+        match format.as_deref() {
+            Some("oci") | None => {}
+            Some(other) => return Err(format!("{}: export: invalid format '{other}', expected 'oci'", self.name).into()),
+        }
+
+        utils::check_rootfs_exists(self.name.clone(), rootfs_dir.clone())?;
+        let entry = Registry::load().find_by_path(&rootfs_dir).cloned();
+        let env_name = entry.as_ref().map(|e| e.name.clone()).unwrap_or_else(|| "alpine".to_string());
+
+        let tag = tag.unwrap_or_else(|| format!("{env_name}:latest"));
+        if tag.is_empty() || tag.contains(char::is_whitespace) {
+            return Err(format!("{}: export: invalid tag '{tag}'", self.name).into());
+        }
+        let output = match output {
+            Some(o) => PathBuf::from(o),
+            None => Path::new(&Settings::set_output_dir()?).join(format!("{env_name}-oci.tar")),
+        };
+
+        let env: Vec<String> = entry.map(|e| e.env.iter().map(|(k, v)| format!("{k}={v}")).collect()).unwrap_or_default();
+        Self::oci_archive(&rootfs_dir, &output, &tag, &env)?;
+        println!("Image '{tag}' exported to: {}", output.display());
+        Ok(())
+    }
+
+    /// Writes the rootfs as an OCI image layout packed in a tar archive.
+    ///
+    /// The image has a single gzip layer holding the whole rootfs, with the
+    /// files of the current user owned by root, a config with the platform
+    /// of the rootfs and its variables, and an index tagging the manifest
+    /// with `tag` (`org.opencontainers.image.ref.name`).
+    ///
+    /// # Arguments
+    /// * `rootfs` - The rootfs directory.
+    /// * `output` - The archive to write.
+    /// * `tag` - The reference name of the image.
+    /// * `env` - The `NAME=value` variables of the image, besides `PATH`.
+    ///
+    /// # Returns
+    /// * `Ok(())` if the archive was written.
+    /// * `Err` if the rootfs cannot be read or the archive cannot be written.
+    ///
+    /// # Example
+    /// ```
+    /// Export::oci_archive("/home/user/.ALPack", Path::new("image.tar"), "alpine:latest", &[])?;
+    /// ```
+    pub fn oci_archive(rootfs: &str, output: &Path, tag: &str, env: &[String]) -> Result<(), Box<dyn Error>> {
+        let part = PathBuf::from(format!("{}.part", output.display()));
+        let layer_part = PathBuf::from(format!("{}.layer.part", output.display()));
+
+        let result = (|| -> Result<(), Box<dyn Error>> {
+            let (layer, diff_id) = Self::write_layer(rootfs, &layer_part)?;
+
+            let arch = qemu::rootfs_arch(rootfs);
+            let (architecture, variant) = Self::oci_platform(&arch);
+            let mut platform = json!({ "architecture": architecture, "os": "linux" });
+            if let Some(v) = variant {
+                platform["variant"] = json!(v);
+            }
+
+            let now = utils::now();
+            let created = format!("{}:{:02}Z", utils::format_date(now).replace(' ', "T"), now % 60);
+            let mut image_env = env.to_vec();
+            if !env.iter().any(|v| v.starts_with("PATH=")) {
+                image_env.insert(0, IMAGE_PATH.to_string());
+            }
+
+            let mut config = platform.clone();
+            config["created"] = json!(created);
+            config["config"] = json!({ "Env": image_env, "Cmd": ["/bin/sh"], "WorkingDir": "/" });
+            config["rootfs"] = json!({ "type": "layers", "diff_ids": [diff_id.digest] });
+            config["history"] = json!([{ "created": created, "created_by": "ALPack export" }]);
+            let config = serde_json::to_vec(&config)?;
+            let config_blob = Self::blob(&config);
+
+            let manifest = serde_json::to_vec(&json!({
+                "schemaVersion": 2,
+                "mediaType": OCI_MANIFEST,
+                "config": { "mediaType": OCI_CONFIG, "digest": config_blob.digest, "size": config_blob.size },
+                "layers": [{ "mediaType": OCI_LAYER, "digest": layer.digest, "size": layer.size }],
+            }))?;
+            let manifest_blob = Self::blob(&manifest);
+
+            let index = serde_json::to_vec(&json!({
+                "schemaVersion": 2,
+                "mediaType": OCI_INDEX,
+                "manifests": [{
+                    "mediaType": OCI_MANIFEST,
+                    "digest": manifest_blob.digest,
+                    "size": manifest_blob.size,
+                    "platform": platform,
+                    "annotations": { "org.opencontainers.image.ref.name": tag },
+                }],
+            }))?;
+
+            let mut builder = Builder::new(BufWriter::new(File::create(&part)?));
+            Self::append_file(&mut builder, "oci-layout", OCI_LAYOUT.as_bytes(), OCI_LAYOUT.len() as u64)?;
+            Self::append_file(&mut builder, "index.json", index.as_slice(), index.len() as u64)?;
+            Self::append_file(&mut builder, &Self::blob_path(&manifest_blob), manifest.as_slice(), manifest_blob.size)?;
+            Self::append_file(&mut builder, &Self::blob_path(&config_blob), config.as_slice(), config_blob.size)?;
+            Self::append_file(&mut builder, &Self::blob_path(&layer), File::open(&layer_part)?, layer.size)?;
+            builder.into_inner()?.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+            fs::rename(&part, output)?;
+            println!("Layer: {} ({})", layer.digest, HumanBytes(layer.size));
+            Ok(())
+        })();
+
+        let _ = fs::remove_file(&layer_part);
+        if let Err(e) = result {
+            let _ = fs::remove_file(&part);
+            return Err(format!("Failed to export '{rootfs}' to '{}': {e}", output.display()).into());
+        }
+        Ok(())
+    }
+
+    /// Writes the rootfs as a gzip tar layer.
+    ///
+    /// # Returns
+    /// * `Ok((Blob, Blob))` with the compressed layer and its uncompressed `diff_id`.
+    fn write_layer(rootfs: &str, dest: &Path) -> io::Result<(Blob, Blob)> {
+        let bar = ProgressBar::new(utils::dir_size(Path::new(rootfs)));
+        bar.set_message("Exporting rootfs...");
+        bar.set_style(
+            ProgressStyle::with_template(utils::DOWNLOAD_TEMPLATE)
+                .unwrap()
+                .progress_chars("##-"),
+        );
+
+        let compressed = DigestWriter::new(BufWriter::new(File::create(dest)?));
+        let mut builder = Builder::new(DigestWriter::new(GzEncoder::new(compressed, Compression::default())));
+        builder.follow_symlinks(false);
+
+        let mut entries: Vec<_> = fs::read_dir(rootfs)?.flatten().collect();
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            Self::append_tree(&mut builder, &entry.path(), Path::new(&entry.file_name()), &bar)?;
+        }
+
+        let (encoder, diff_id) = builder.into_inner()?.finish();
+        let (file, layer) = encoder.finish()?.finish();
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+        bar.finish_and_clear();
+        Ok((layer, diff_id))
+    }
+
+    /// Recursively appends a directory tree to the layer.
+    ///
+    /// Like `Snapshot::create`, but the files of the current user are stored
+    /// as owned by root, as they are seen inside the rootfs.
+    fn append_tree<W: Write>(builder: &mut Builder<W>, path: &Path, name: &Path, bar: &ProgressBar) -> io::Result<()> {
+        let meta = fs::symlink_metadata(path)?;
+        let file_type = meta.file_type();
+
+        if file_type.is_socket() {
+            return Ok(());
+        }
+
+        Snapshot::append_xattrs(builder, path)?;
+
+        let mut header = Header::new_gnu();
+        header.set_metadata_in_mode(&meta, HeaderMode::Complete);
+        // SAFETY: getuid and getgid cannot fail.
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        if meta.uid() == uid {
+            header.set_uid(0);
+        }
+        if meta.gid() == gid {
+            header.set_gid(0);
+        }
+
+        match () {
+            _ if file_type.is_symlink() => {
+                builder.append_link(&mut header, name, fs::read_link(path)?)?;
+            }
+            _ if file_type.is_file() => {
+                builder.append_data(&mut header, name, File::open(path)?)?;
+                bar.inc(meta.len());
+            }
+            _ if file_type.is_dir() => {
+                header.set_size(0);
+                builder.append_data(&mut header, name, io::empty())?;
+            }
+            _ => {
+                header.set_entry_type(match () {
+                    _ if file_type.is_fifo() => EntryType::Fifo,
+                    _ if file_type.is_char_device() => EntryType::Char,
+                    _ => EntryType::Block,
+                });
+                let dev = meta.rdev();
+                header.set_device_major(libc::major(dev))?;
+                header.set_device_minor(libc::minor(dev))?;
+                header.set_size(0);
+                builder.append_data(&mut header, name, io::empty())?;
+            }
+        }
+
+        if file_type.is_dir() {
+            let mut entries: Vec<_> = fs::read_dir(path)?.flatten().collect();
+            entries.sort_by_key(|e| e.file_name());
+            for entry in entries {
+                Self::append_tree(builder, &entry.path(), &name.join(entry.file_name()), bar)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends a regular file of the image layout to the archive.
+    fn append_file<W: Write, R: io::Read>(builder: &mut Builder<W>, name: &str, data: R, size: u64) -> io::Result<()> {
+        let mut header = Header::new_ustar();
+        header.set_entry_type(EntryType::Regular);
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(utils::now());
+        header.set_size(size);
+        builder.append_data(&mut header, name, data)
+    }
+
+    /// Returns the blob of an in-memory content.
+    fn blob(data: &[u8]) -> Blob {
+        Blob {
+            digest: format!("sha256:{}", verify::hex(&Sha256::digest(data))),
+            size: data.len() as u64,
+        }
+    }
+
+    /// Returns the path of a blob in the image layout.
+    fn blob_path(blob: &Blob) -> String {
+        format!("blobs/{}", blob.digest.replace(':', "/"))
+    }
+
+    /// Maps an Alpine architecture to the OCI architecture and variant.
+    ///
+    /// # Example
+    /// ```
+    /// assert_eq!(Export::oci_platform("armv7"), ("arm", Some("v7")));
+    /// ```
+    pub fn oci_platform(arch: &str) -> (&str, Option<&str>) {
+        match arch {
+            "x86_64" => ("amd64", None),
+            "x86" => ("386", None),
+            "aarch64" => ("arm64", Some("v8")),
+            "armhf" => ("arm", Some("v6")),
+            "armv7" => ("arm", Some("v7")),
+            "loongarch64" => ("loong64", None),
+            other => (other, None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use serde_json::Value;
+    use std::collections::HashMap;
+    use std::io::Read;
+    use std::os::unix::fs::symlink;
+    use std::{env, process};
+
+    /// Reads every regular file of a tar archive by path.
+    fn read_archive(path: &Path) -> HashMap<String, Vec<u8>> {
+        let mut archive = tar::Archive::new(File::open(path).unwrap());
+        let mut files = HashMap::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_string_lossy().to_string();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            files.insert(name, data);
+        }
+        files
+    }
+
+    /// Returns the content of the blob referenced by a descriptor, checking its digest and size.
+    fn blob<'a>(files: &'a HashMap<String, Vec<u8>>, descriptor: &Value) -> &'a [u8] {
+        let digest = descriptor["digest"].as_str().unwrap();
+        let data = files
+            .get(&format!("blobs/{}", digest.replace(':', "/")))
+            .unwrap_or_else(|| panic!("missing blob {digest}"));
+        assert_eq!(digest, format!("sha256:{}", verify::hex(&Sha256::digest(data))));
+        assert_eq!(descriptor["size"].as_u64(), Some(data.len() as u64));
+        data
+    }
+
+    #[test]
+    fn oci_archive_writes_a_valid_layout() {
+        let dir = env::temp_dir().join(format!("ALPack-export-test-{}", process::id()));
+        let rootfs = dir.join("rootfs");
+        fs::create_dir_all(rootfs.join("etc/apk")).unwrap();
+        fs::write(rootfs.join("etc/apk/arch"), "aarch64\n").unwrap();
+        fs::write(rootfs.join("etc/motd"), "hello\n").unwrap();
+        symlink("motd", rootfs.join("etc/issue")).unwrap();
+        let output = dir.join("image.tar");
+
+        Export::oci_archive(rootfs.to_str().unwrap(), &output, "test:latest", &[]).unwrap();
+        let files = read_archive(&output);
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(files["oci-layout"], OCI_LAYOUT.as_bytes());
+
+        let index: Value = serde_json::from_slice(&files["index.json"]).unwrap();
+        let descriptor = &index["manifests"][0];
+        assert_eq!(descriptor["mediaType"], OCI_MANIFEST);
+        assert_eq!(descriptor["annotations"]["org.opencontainers.image.ref.name"], "test:latest");
+        assert_eq!(descriptor["platform"]["architecture"], "arm64");
+        assert_eq!(descriptor["platform"]["variant"], "v8");
+
+        let manifest: Value = serde_json::from_slice(blob(&files, descriptor)).unwrap();
+        assert_eq!(manifest["config"]["mediaType"], OCI_CONFIG);
+        let config: Value = serde_json::from_slice(blob(&files, &manifest["config"])).unwrap();
+        assert_eq!(config["architecture"], "arm64");
+        assert_eq!(config["os"], "linux");
+
+        let layer_descriptor = &manifest["layers"][0];
+        assert_eq!(layer_descriptor["mediaType"], OCI_LAYER);
+        let mut layer = Vec::new();
+        GzDecoder::new(blob(&files, layer_descriptor)).read_to_end(&mut layer).unwrap();
+        let diff_id = format!("sha256:{}", verify::hex(&Sha256::digest(&layer)));
+        assert_eq!(config["rootfs"]["diff_ids"][0], diff_id);
+
+        let names: Vec<String> = tar::Archive::new(layer.as_slice())
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert!(names.iter().any(|n| n == "etc/motd"));
+        assert!(names.iter().any(|n| n == "etc/issue"));
+    }
+
+    #[test]
+    fn oci_platform_maps_alpine_architectures() {
+        assert_eq!(Export::oci_platform("x86_64"), ("amd64", None));
+        assert_eq!(Export::oci_platform("x86"), ("386", None));
+        assert_eq!(Export::oci_platform("aarch64"), ("arm64", Some("v8")));
+        assert_eq!(Export::oci_platform("armhf"), ("arm", Some("v6")));
+        assert_eq!(Export::oci_platform("armv7"), ("arm", Some("v7")));
+        assert_eq!(Export::oci_platform("loongarch64"), ("loong64", None));
+        assert_eq!(Export::oci_platform("riscv64"), ("riscv64", None));
+    }
+}
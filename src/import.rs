@@ -0,0 +1,505 @@
+use crate::archive;
+use crate::backend;
+use crate::export::Export;
+use crate::mirror::Mirror;
+use crate::registry::{Registry, RootfsEntry};
+use crate::settings::Settings;
+use crate::utils::{self, _parse_key_value};
+use crate::{parse_key_value, qemu};
+
+use flate2::read::GzDecoder;
+use indicatif::{ProgressBar, ProgressStyle};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+
+/// Magic bytes of the gzip and zstd streams.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Media type of the OCI image indexes, which may be nested in `index.json`.
+const OCI_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+
+pub struct Import {
+    name: String,
+    remaining_args: Vec<String>,
+}
+
+/// The image selected in an archive.
+struct Image {
+    /// The image config, holding the platform and the variables.
+    config: Value,
+    /// The layer files, lowest first.
+    layers: Vec<PathBuf>,
+}
+
+impl Import {
+    pub fn new(name: String, remaining_args: Vec<String>) -> Self {
+        Import {
+            name,
+            remaining_args,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<_> = self.remaining_args.clone().into();
+        let sett = Settings::load_or_create();
+        let (mut env_name, mut tag, mut rootfs_dir): (Option<String>, Option<String>, Option<String>) = (None, None, None);
+        let mut backend: Option<String> = None;
+        let mut archive: Option<String> = None;
+
+        while let Some(arg) = args.pop_front() {
+            match arg.as_str() {
+                a if a.starts_with("--name=") => {
+                    env_name = parse_key_value!("import", "name", arg)?;
+                }
+                "--name" => {
+                    env_name = parse_key_value!("import", "name", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with("--tag=") => {
+                    tag = parse_key_value!("import", "name", arg)?;
+                }
+                "--tag" => {
+                    tag = parse_key_value!("import", "name", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with("--backend=") => {
+                    backend = parse_key_value!("import", "backend", arg)?;
+                }
+                "--backend" => {
+                    backend = parse_key_value!("import", "backend", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with("--rootfs=") => {
+                    rootfs_dir = parse_key_value!("import", "directory", arg)?;
+                }
+                "-R" | "--rootfs" => {
+                    rootfs_dir = parse_key_value!("import", "directory", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with('-') || archive.is_some() => {
+                    return Err(format!("{c}: import: invalid argument '{arg}'\nUse '{c} --help' to see available options.", c = self.name).into())
+                }
+                _ => archive = Some(arg),
+            }
+        }
+
+        let archive = archive.ok_or_else(|| {
+            format!("{c}: import: requires an <ARCHIVE> as argument.\nUsage: {c} import <ARCHIVE> --name <NAME>", c = self.name)
+        })?;
+        if !Path::new(&archive).is_file() {
+            return Err(format!("{}: import: archive '{archive}' not found", self.name).into());
+        }
+        if let Some(b) = &backend
+            && !backend::BACKENDS.contains(&b.as_str())
+        {
+            return Err(format!("{}: import: invalid backend '{b}', expected one of: {}", self.name, backend::BACKENDS.join(", ")).into());
+        }
+
+        let env_name = env_name.unwrap_or_else(|| Self::default_name(&archive, tag.as_deref()));
+        if let Err(e) = Registry::check_name(&env_name) {
+            return Err(format!("{}: import: {e}\nUse '--name' to choose another one.", self.name).into());
+        }
+        if Registry::load().find(&env_name).is_some() {
+            return Err(format!("{}: import: environment '{env_name}' already exists", self.name).into());
+        }
+
+        let dest = Registry::normalize(&rootfs_dir.unwrap_or_else(|| format!("{}/{env_name}", sett.envs_dir)));
+        if Path::new(&dest).exists() {
+            return Err(format!("{}: import: directory '{dest}' already exists", self.name).into());
+        }
+
+        let staging = PathBuf::from(format!("{dest}.import-{}", process::id()));
+        let result = self.import(Path::new(&archive), &staging, Path::new(&dest), tag.as_deref());
+        let _ = fs::remove_dir_all(&staging);
+        let config = match result {
+            Ok(config) => config,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&dest);
+                return Err(e);
+            }
+        };
+
+        let mut entry = RootfsEntry::new(env_name.clone(), dest.clone());
+        entry.backend = backend.unwrap_or_default();
+        entry.version = fs::read_to_string(Path::new(&dest).join("etc/alpine-release"))
+            .map(|v| v.trim().to_string())
+            .unwrap_or_default();
+        entry.release = Mirror::release_from_version(&entry.version).unwrap_or_default();
+        entry.arch = Self::image_arch(&config)
+            .or_else(|| fs::read_to_string(Path::new(&dest).join("etc/apk/arch")).ok().map(|a| a.trim().to_string()))
+            .unwrap_or_else(utils::get_arch);
+        entry.env = config["config"]["Env"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|v| v.as_str()?.split_once('='))
+            .filter(|(k, _)| *k != "PATH")
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        if entry.version.is_empty() {
+            eprintln!("\x1b[1;33mWarning\x1b[0m: The image is not based on Alpine Linux, 'apk' and 'setup' options may not work in it.");
+        }
+        if !Path::new(&dest).join("bin/sh").exists() {
+            eprintln!("\x1b[1;33mWarning\x1b[0m: The image has no /bin/sh, pass a command to '{} run' to enter it.", self.name);
+        }
+
+        let mut registry = Registry::load();
+        registry.upsert(entry);
+        registry.save()?;
+
+        println!("Image imported to {dest}.\nUse '{} run --env {env_name}' to enter it.", self.name);
+        Ok(())
+    }
+
+    /// Unpacks the archive in a staging directory and applies the layers of the image to `dest`.
+    ///
+    /// # Returns
+    /// * `Ok(Value)` with the image config.
+    /// * `Err` if the archive is not an OCI layout or `docker save` archive, or a layer cannot be read.
+    fn import(&self, archive: &Path, staging: &Path, dest: &Path, tag: Option<&str>) -> Result<Value, Box<dyn Error>> {
+        println!("Unpacking {}...", archive.display());
+        fs::create_dir_all(staging)?;
+        let report = archive::unpack(Self::open(archive)?, staging)?;
+        if let Some((path, e)) = report.failures.first() {
+            return Err(format!("{}: import: cannot unpack '{}': {e}", self.name, path.display()).into());
+        }
+
+        let image = match (staging.join("manifest.json").is_file(), staging.join("index.json").is_file()) {
+            (true, _) => self.docker_image(staging, tag)?,
+            (false, true) => self.oci_image(staging, tag)?,
+            (false, false) => {
+                return Err(format!("{}: import: '{}' is not an OCI layout or a 'docker save' archive", self.name, archive.display()).into());
+            }
+        };
+
+        fs::create_dir_all(dest)?;
+        let total = image.layers.len();
+        for (i, layer) in image.layers.iter().enumerate() {
+            let file = File::open(layer).map_err(|e| format!("{}: import: cannot read layer '{}': {e}", self.name, layer.display()))?;
+            let bar = ProgressBar::new(file.metadata()?.len());
+            bar.set_message(format!("Applying layer {}/{total}...", i + 1));
+            bar.set_style(
+                ProgressStyle::with_template(utils::DOWNLOAD_TEMPLATE)
+                    .unwrap()
+                    .progress_chars("##-"),
+            );
+
+            let report = archive::unpack_layer(Self::decompress(bar.wrap_read(file))?, dest);
+            match &report {
+                Ok(_) => bar.finish_and_clear(),
+                Err(_) => bar.abandon_with_message("Failed!"),
+            }
+            report?.print();
+        }
+        println!("Applied {total} layer(s).");
+        Ok(image.config)
+    }
+
+    /// Selects the image of a `docker save` archive, described in `manifest.json`.
+    fn docker_image(&self, dir: &Path, tag: Option<&str>) -> Result<Image, Box<dyn Error>> {
+        let manifest: Value = serde_json::from_slice(&fs::read(dir.join("manifest.json"))?)?;
+        let images = manifest.as_array().cloned().unwrap_or_default();
+
+        let image = match tag {
+            Some(t) => images.iter().find(|i| {
+                i["RepoTags"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .any(|r| Self::tag_matches(r, t))
+            }),
+            None => images.first(),
+        }
+        .ok_or_else(|| self.no_image(tag))?;
+
+        let config = image["Config"]
+            .as_str()
+            .and_then(|c| Self::inside(dir, c))
+            .ok_or_else(|| self.invalid("manifest.json"))?;
+        let layers = image["Layers"]
+            .as_array()
+            .ok_or_else(|| self.invalid("manifest.json"))?
+            .iter()
+            .map(|l| l.as_str().and_then(|l| Self::inside(dir, l)).ok_or_else(|| self.invalid("manifest.json")))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Image {
+            config: serde_json::from_slice(&fs::read(config)?)?,
+            layers,
+        })
+    }
+
+    /// Selects the image of an OCI layout, described in `index.json`.
+    ///
+    /// Nested indexes are followed. Without a tag, the manifest for the
+    /// architecture of the host is preferred.
+    fn oci_image(&self, dir: &Path, tag: Option<&str>) -> Result<Image, Box<dyn Error>> {
+        let index: Value = serde_json::from_slice(&fs::read(dir.join("index.json"))?)?;
+
+        let mut manifests = Vec::new();
+        let mut pending = vec![(index, None::<String>)];
+        while let Some((index, ref_name)) = pending.pop() {
+            for m in index["manifests"].as_array().into_iter().flatten() {
+                let name = m["annotations"]["org.opencontainers.image.ref.name"].as_str().map(str::to_string).or(ref_name.clone());
+                match m["mediaType"].as_str() == Some(OCI_INDEX) {
+                    true => pending.push((self.blob(dir, m)?, name)),
+                    false => manifests.push((m.clone(), name)),
+                }
+            }
+        }
+
+        let arch = utils::get_arch();
+        let (host, _) = Export::oci_platform(&arch);
+        let selected = match tag {
+            Some(t) => manifests.iter().find(|(_, name)| name.as_deref().is_some_and(|n| Self::tag_matches(n, t))),
+            None => manifests
+                .iter()
+                .find(|(m, _)| m["platform"]["architecture"].as_str() == Some(host))
+                .or(manifests.first()),
+        };
+        let (descriptor, _) = selected.ok_or_else(|| self.no_image(tag))?;
+
+        let manifest = self.blob(dir, descriptor)?;
+        let layers = manifest["layers"]
+            .as_array()
+            .ok_or_else(|| self.invalid("image manifest"))?
+            .iter()
+            .map(|l| Self::blob_path(dir, l).ok_or_else(|| self.invalid("image manifest")))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Image {
+            config: self.blob(dir, &manifest["config"])?,
+            layers,
+        })
+    }
+
+    /// Reads a JSON blob of an OCI layout from its descriptor.
+    fn blob(&self, dir: &Path, descriptor: &Value) -> Result<Value, Box<dyn Error>> {
+        let path = Self::blob_path(dir, descriptor).ok_or_else(|| self.invalid("descriptor"))?;
+        let data = fs::read(&path).map_err(|e| format!("{}: import: cannot read blob '{}': {e}", self.name, path.display()))?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    /// Returns the path of the blob of a descriptor (`blobs/<algorithm>/<hex>`).
+    fn blob_path(dir: &Path, descriptor: &Value) -> Option<PathBuf> {
+        let (algorithm, hex) = descriptor["digest"].as_str()?.split_once(':')?;
+        Self::inside(dir, &format!("blobs/{algorithm}/{hex}"))
+    }
+
+    /// Joins a path of the archive to the staging directory, rejecting paths escaping it.
+    fn inside(dir: &Path, path: &str) -> Option<PathBuf> {
+        let path = Path::new(path);
+        match path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+            true => Some(dir.join(path)),
+            false => None,
+        }
+    }
+
+    /// Opens a possibly gzip-compressed archive.
+    fn open(path: &Path) -> io::Result<Box<dyn Read>> {
+        Self::decompress(File::open(path)?)
+    }
+
+    /// Wraps a tar stream in a gzip decoder when it is compressed.
+    fn decompress<R: Read + 'static>(reader: R) -> io::Result<Box<dyn Read>> {
+        let mut reader = BufReader::new(reader);
+        let magic = io::BufRead::fill_buf(&mut reader)?;
+        if magic.starts_with(&ZSTD_MAGIC) {
+            return Err(io::Error::other("zstd-compressed layers are not supported"));
+        }
+        match magic.starts_with(&GZIP_MAGIC) {
+            true => Ok(Box::new(GzDecoder::new(reader))),
+            false => Ok(Box::new(reader)),
+        }
+    }
+
+    /// Maps the platform of an image config to an Alpine architecture.
+    fn image_arch(config: &Value) -> Option<String> {
+        let architecture = config["architecture"].as_str()?;
+        let variant = config["variant"].as_str();
+        qemu::ALPINE_ARCHES
+            .iter()
+            .find(|a| {
+                let (oci, v) = Export::oci_platform(a);
+                oci == architecture && (variant.is_none() || v.is_none() || v == variant)
+            })
+            .map(|a| a.to_string())
+    }
+
+    /// Checks whether a reference of the archive matches the requested tag.
+    ///
+    /// The full `repository:tag` references are compared, with `:latest`
+    /// implied and the `docker.io/` and `library/` prefixes of Docker Hub
+    /// images ignored.
+    ///
+    /// # Example
+    /// ```
+    /// assert!(Import::tag_matches("docker.io/library/alpine:3.20", "alpine:3.20"));
+    /// assert!(!Import::tag_matches("nginx:3.20", "alpine:3.20"));
+    /// ```
+    fn tag_matches(reference: &str, tag: &str) -> bool {
+        Self::full_reference(reference) == Self::full_reference(tag)
+    }
+
+    /// Normalizes an image reference to `repository:tag`, as Docker does for Docker Hub names.
+    fn full_reference(reference: &str) -> String {
+        let reference = reference.strip_prefix("docker.io/").unwrap_or(reference);
+        let reference = reference.strip_prefix("library/").unwrap_or(reference);
+        match reference.rsplit('/').next().is_some_and(|name| name.contains(':')) {
+            true => reference.to_string(),
+            false => format!("{reference}:latest"),
+        }
+    }
+
+    /// Returns the environment name derived from the tag or the archive name.
+    ///
+    /// # Example
+    /// ```
+    /// assert_eq!(Import::default_name("/tmp/web-oci.tar", None), "web-oci");
+    /// ```
+    fn default_name(archive: &str, tag: Option<&str>) -> String {
+        let base = match tag {
+            Some(t) => t.rsplit('/').next().unwrap_or(t).split(':').next().unwrap_or(t).to_string(),
+            None => Path::new(archive)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        };
+        base.trim_end_matches(".gz").trim_end_matches(".tar").to_string()
+    }
+
+    /// Returns the error reported when no image matches the tag.
+    fn no_image(&self, tag: Option<&str>) -> String {
+        match tag {
+            Some(t) => format!("{}: import: no image tagged '{t}' in the archive", self.name),
+            None => format!("{}: import: no image in the archive", self.name),
+        }
+    }
+
+    /// Returns the error reported for a malformed file of the archive.
+    fn invalid(&self, what: &str) -> String {
+        format!("{}: import: invalid {what} in the archive", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify;
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
+    use std::env;
+    use tar::{Builder, EntryType, Header};
+
+    /// Builds a tar archive; entries ending with `/` are directories.
+    fn tar_of(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for (path, data) in entries {
+            let mut header = Header::new_gnu();
+            match path.ends_with('/') {
+                true => header.set_entry_type(EntryType::Directory),
+                false => header.set_entry_type(EntryType::Regular),
+            }
+            header.set_mode(0o755);
+            header.set_size(data.len() as u64);
+            builder.append_data(&mut header, path, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// The two layers of the fixtures: the second one hides `etc/a` with a
+    /// whiteout, makes `opt` opaque and holds a `.wh..` whiteout to ignore.
+    fn layers() -> [Vec<u8>; 2] {
+        [
+            tar_of(&[("etc/", b""), ("etc/a", b"a"), ("etc/b", b"b"), ("opt/", b""), ("opt/x", b"x"), ("keep/", b""), ("keep/y", b"y")]),
+            tar_of(&[("etc/.wh.a", b""), ("opt/", b""), ("opt/.wh..wh..opq", b""), ("opt/z", b"z"), ("keep/.wh..", b"")]),
+        ]
+    }
+
+    /// Returns the config of one of the two images of the fixtures.
+    fn config(image: &str) -> Vec<u8> {
+        serde_json::to_vec(&json!({ "architecture": "amd64", "config": { "Env": [format!("IMAGE={image}")] } })).unwrap()
+    }
+
+    /// Imports an archive into a fresh directory and checks the resulting rootfs.
+    fn check_import(archive: Vec<u8>, kind: &str) {
+        let dir = env::temp_dir().join(format!("ALPack-import-test-{kind}-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("image.tar");
+        fs::write(&file, archive).unwrap();
+
+        let import = Import::new("ALPack".to_string(), Vec::new());
+        let dest = dir.join("rootfs");
+        let config = import.import(&file, &dir.join("staging"), &dest, Some("example/web:1.0")).unwrap();
+
+        assert_eq!(config["config"]["Env"][0], "IMAGE=web", "{kind}");
+        assert!(!dest.join("etc/a").exists(), "{kind}");
+        assert_eq!(fs::read(dest.join("etc/b")).unwrap(), b"b", "{kind}");
+        assert!(!dest.join("opt/x").exists(), "{kind}");
+        assert_eq!(fs::read(dest.join("opt/z")).unwrap(), b"z", "{kind}");
+        assert_eq!(fs::read(dest.join("keep/y")).unwrap(), b"y", "{kind}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn imports_a_docker_save_archive() {
+        let [lower, upper] = layers();
+        let manifest = json!([
+            { "Config": "other.json", "RepoTags": ["other/web:1.0"], "Layers": ["lower/layer.tar", "upper/layer.tar"] },
+            { "Config": "web.json", "RepoTags": ["example/web:1.0"], "Layers": ["lower/layer.tar", "upper/layer.tar"] },
+        ]);
+        let manifest = serde_json::to_vec(&manifest).unwrap();
+        let (other, web) = (config("other"), config("web"));
+
+        check_import(
+            tar_of(&[
+                ("manifest.json", &manifest),
+                ("other.json", &other),
+                ("web.json", &web),
+                ("lower/layer.tar", &lower),
+                ("upper/layer.tar", &upper),
+            ]),
+            "docker",
+        );
+    }
+
+    #[test]
+    fn imports_an_oci_layout() {
+        let mut blobs: Vec<(String, Vec<u8>)> = Vec::new();
+        let mut descriptor = |data: Vec<u8>| {
+            let digest = format!("sha256:{}", verify::hex(&Sha256::digest(&data)));
+            let value = json!({ "digest": digest, "size": data.len() });
+            blobs.push((format!("blobs/{}", digest.replace(':', "/")), data));
+            value
+        };
+
+        let [lower, upper] = layers();
+        let layers = [descriptor(lower), descriptor(upper)];
+        let mut manifests = Vec::new();
+        for (image, reference) in [("other", "other/web:1.0"), ("web", "example/web:1.0")] {
+            let manifest = json!({ "config": descriptor(config(image)), "layers": layers });
+            let mut entry = descriptor(serde_json::to_vec(&manifest).unwrap());
+            entry["annotations"] = json!({ "org.opencontainers.image.ref.name": reference });
+            manifests.push(entry);
+        }
+        let index = serde_json::to_vec(&json!({ "schemaVersion": 2, "manifests": manifests })).unwrap();
+
+        let mut entries: Vec<(&str, &[u8])> = vec![("index.json", &index)];
+        entries.extend(blobs.iter().map(|(path, data)| (path.as_str(), data.as_slice())));
+        check_import(tar_of(&entries), "oci");
+    }
+
+    #[test]
+    fn tags_match_the_full_reference() {
+        assert!(Import::tag_matches("docker.io/library/alpine:3.20", "alpine:3.20"));
+        assert!(Import::tag_matches("alpine", "alpine:latest"));
+        assert!(!Import::tag_matches("nginx:3.20", "alpine:3.20"));
+        assert!(!Import::tag_matches("other/web:1.0", "example/web:1.0"));
+        assert!(!Import::tag_matches("alpine:3.20", "3.20"));
+    }
+}
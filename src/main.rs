@@ -1,20 +1,50 @@
 mod apk;
 mod aports;
+mod archive;
+mod backend;
 mod builder;
+mod cache;
 mod command;
 mod config;
+mod doctor;
+mod download;
+mod export;
+mod import;
 mod mirror;
+mod mirrors;
+mod native;
+mod profile;
+mod provision;
+mod qemu;
+mod recipe;
+mod registry;
+mod repo;
 mod run;
+mod sandbox;
 mod settings;
 mod setup;
+mod snapshot;
+mod upgrade;
 mod utils;
+mod verify;
 
 use crate::apk::Apk;
 use crate::aports::Aports;
 use crate::builder::Builder;
+use crate::cache::Cache;
 use crate::config::Config;
+use crate::doctor::Doctor;
+use crate::export::Export;
+use crate::import::Import;
+use crate::mirrors::Mirrors;
+use crate::profile::Profile;
+use crate::recipe::Recipe;
+use crate::registry::Registry;
+use crate::repo::Repo;
 use crate::run::Run;
 use crate::setup::Setup;
+use crate::snapshot::Snapshot;
+use crate::upgrade::Upgrade;
 use pico_args::Arguments;
 use std::env;
 use std::error::Error;
@@ -34,6 +64,16 @@ Available parameters:
         setup                   Initialize or configure the rootfs environment
         run                     Execute command inside the rootfs
         config                  Display or modify global configuration
+        env                     Manage named rootfs environments
+        build-env               Create or update a rootfs from a recipe file (ALPack.toml)
+        snapshot                Save and restore snapshots of the rootfs
+        upgrade-release         Upgrade the rootfs to another Alpine release branch
+        export                  Export the rootfs as an OCI image archive
+        import <ARCHIVE>        Import an OCI or 'docker save' archive as a rootfs
+        cache                   List, measure and clean the cache directory
+        doctor                  Check the host and the rootfs for common problems
+        mirror                  List, rank and select Alpine mirrors
+        repo                    Manage extra apk repositories of the rootfs
         aports                  Manage local aports repositories
         builder                 Build utility for packages and images
         apk                     Run the Alpine package manager (apk)
@@ -47,17 +87,150 @@ Options for 'setup':
         --no-cache              Disable caching during the operation
     -r, --reinstall             Reinstall packages without forcing
         --edge                  Use the edge (testing) repository
+        --release <BRANCH>      Use the specified release branch (e.g., v3.19)
+        --release=<BRANCH>      Use the specified release branch (inline)
+        --version <X.Y.Z>       Install the minirootfs of an Alpine point version (e.g., 3.19.4)
+        --version=<X.Y.Z>       Install the minirootfs of an Alpine point version (inline)
+                                (only the branch is pinned, apk then follows its latest packages)
         --minimal               Install only the minimal set of packages
+        --preset <NAME>         Install a package preset (base, c, go, rust, gui, sdk or from config)
+        --preset=<NAME>         Install a package preset (inline)
+        --preset-file <FILE>    Load package presets from a TOML file
+        --preset-file=<FILE>    Load package presets from a TOML file (inline)
+        --packages <PKGS>       Install these packages (added to --preset if given)
+        --packages=<PKGS>       Install these packages (inline)
+        --provision <FILE>      Run a host script inside the new rootfs (can be repeated)
+        --provision=<FILE>      Run a host script inside the new rootfs (inline)
+        --provision-cmd <CMD>   Run a command inside the new rootfs (can be repeated)
+        --provision-cmd=<CMD>   Run a command inside the new rootfs (inline)
+        --provision-user        Run the provisioning given on the command line as the user
+        --no-provision          Skip the provisioning steps listed in the config file
+        --from-tarball <FILE>   Install from a local minirootfs tarball (offline)
+        --from-tarball=<FILE>   Install from a local minirootfs tarball (inline)
+        --from-url <URL>        Install from a minirootfs tarball URL (also file://)
+        --from-url=<URL>        Install from a minirootfs tarball URL (inline)
+        --skip-verify           Do not verify the rootfs checksum and signature
+        --arch <ARCH>           Install a rootfs of another architecture (run through qemu-user)
+        --arch=<ARCH>           Install a rootfs of another architecture (inline)
+        --backend <CMD>         Record 'proot', 'bwrap' or 'native' as the rootfs handler
+        --backend=<CMD>         Record 'proot', 'bwrap' or 'native' as the rootfs handler (inline)
+        --mirror <URL>          Use the specified mirror instead of the default one (also a local path)
+        --mirror=<URL>          Use the specified mirror instead of the default one (inline)
+        --cache <DIR>           Specify cache directory
+        --cache=<DIR>           Specify cache directory (inline)
+    -R, --rootfs <DIR>          Specify rootfs directory
+        --rootfs=<DIR>          Specify rootfs directory (inline)
+        --env <NAME>            Install into a named environment
+        --env=<NAME>            Install into a named environment (inline)
+
+Options for 'env':
+        list | ls               List environments with size and creation date
+        create <NAME> [OPTS]    Create an environment (accepts the 'setup' options)
+        rm | remove <NAME>      Remove an environment, its rootfs and snapshots, after a confirmation
+    -y, --yes                   Do not ask for a confirmation before deleting the rootfs
+        --keep-files            Only unregister the environment, keeping its rootfs and snapshots
+        --force                 Delete a rootfs outside 'envs_dir' and 'rootfs_dir'
+        clone <SRC> <DST>       Copy an environment into a new one
+
+Options for 'build-env':
+    -f, --file <FILE>           Read the recipe from FILE (default: ./ALPack.toml)
+        --file=<FILE>           Read the recipe from FILE (inline)
+    -R, --rootfs <DIR>          Build this rootfs instead of the one named in the recipe
+        --rootfs=<DIR>          Build this rootfs instead of the one named in the recipe (inline)
+        --env <NAME>            Build this named environment instead of the one in the recipe
+        --env=<NAME>            Build this named environment instead of the one in the recipe (inline)
+
+Options for 'snapshot':
+        create [NAME]           Save a snapshot of the rootfs (default name: current date)
+        list | ls               List the snapshots of the rootfs
+        restore <NAME>          Replace the rootfs with a snapshot
+        rm | remove <NAME>      Remove a snapshot
+    -R, --rootfs <DIR>          Specify rootfs directory
+        --rootfs=<DIR>          Specify rootfs directory (inline)
+        --env <NAME>            Use the rootfs of a named environment
+        --env=<NAME>            Use the rootfs of a named environment (inline)
+
+Options for 'upgrade-release':
+        --to <BRANCH>           The release branch to upgrade to (e.g., v3.20)
+        --to=<BRANCH>           The release branch to upgrade to (inline)
         --mirror <URL>          Use the specified mirror instead of the default one
         --mirror=<URL>          Use the specified mirror instead of the default one (inline)
+    -R, --rootfs <DIR>          Specify rootfs directory
+        --rootfs=<DIR>          Specify rootfs directory (inline)
+        --env <NAME>            Use the rootfs of a named environment
+        --env=<NAME>            Use the rootfs of a named environment (inline)
+
+Options for 'export':
+        --format <FORMAT>       Archive format, only 'oci' is supported (default)
+        --format=<FORMAT>       Archive format (inline)
+    -o, --output <FILE>         Write the image to FILE (default: ./<NAME>-oci.tar)
+        --output=<FILE>         Write the image to FILE (inline)
+        --tag <NAME[:TAG]>      Reference name of the image (default: <NAME>:latest)
+        --tag=<NAME[:TAG]>      Reference name of the image (inline)
+    -R, --rootfs <DIR>          Specify rootfs directory
+        --rootfs=<DIR>          Specify rootfs directory (inline)
+        --env <NAME>            Use the rootfs of a named environment
+        --env=<NAME>            Use the rootfs of a named environment (inline)
+
+Options for 'import':
+        --name <NAME>           Name of the new environment (default: from the tag or the archive)
+        --name=<NAME>           Name of the new environment (inline)
+        --tag <NAME[:TAG]>      Import this image when the archive holds several
+        --tag=<NAME[:TAG]>      Import this image when the archive holds several (inline)
+        --backend <CMD>         Record 'proot', 'bwrap' or 'native' as the rootfs handler
+        --backend=<CMD>         Record 'proot', 'bwrap' or 'native' as the rootfs handler (inline)
+    -R, --rootfs <DIR>          Import into this directory instead of the environments directory
+        --rootfs=<DIR>          Import into this directory instead of the environments directory (inline)
+
+Options for 'cache':
+        list | ls               List the cached files (kind, release, arch and version)
+        size                    Show the size of the cache by kind of file
+        clean                   Remove the cached files created by ALPack (all of them without filters)
+        --older-than <AGE>      Only remove files not modified for AGE (e.g. 30d, 12h, 2w)
+        --older-than=<AGE>      Only remove files not modified for AGE (inline)
+        --keep-latest <N>       Keep the N newest files of each kind, arch and release
+        --keep-latest=<N>       Keep the N newest files of each kind, arch and release (inline)
         --cache <DIR>           Specify cache directory
         --cache=<DIR>           Specify cache directory (inline)
+
+Options for 'doctor':
+    -R, --rootfs <DIR>          Specify rootfs directory
+        --rootfs=<DIR>          Specify rootfs directory (inline)
+        --env <NAME>            Check the rootfs of a named environment
+        --env=<NAME>            Check the rootfs of a named environment (inline)
+
+Options for 'mirror':
+        list | ls               List the mirrors of MIRRORS.txt (marks the configured ones)
+        rank                    Probe the mirrors and sort them by response time
+        use <URL> [FALLBACK...] Set the default mirror and the fallback mirrors
+        --source <FILE|URL>     Read the mirror list from a local copy or another URL
+        --source=<FILE|URL>     Read the mirror list from a local copy or another URL (inline)
+        --release <BRANCH>      Probe this release branch (default from config)
+        --release=<BRANCH>      Probe this release branch (inline)
+        --arch <ARCH>           Probe this architecture (default host or ALPACK_ARCH)
+        --arch=<ARCH>           Probe this architecture (inline)
+        --timeout <SECS>        Time allowed for each probe (default 5)
+        --timeout=<SECS>        Time allowed for each probe (inline)
+        --save                  Save the fastest mirror as default and the next ones as fallbacks
+
+Options for 'repo':
+        list | ls               List the repositories of the rootfs
+        add <URL|DIR>           Add a repository (kept on 'setup --reinstall')
+        rm | remove <URL|@TAG>  Remove a repository added with 'repo add'
+        --tag <NAME>            Add the repository as a tagged one (@NAME)
+        --tag=<NAME>            Add the repository as a tagged one (inline)
+        --key <FILE|URL>        Install the signing key of the repository
+        --key=<FILE|URL>        Install the signing key of the repository (inline)
     -R, --rootfs <DIR>          Specify rootfs directory
         --rootfs=<DIR>          Specify rootfs directory (inline)
+        --env <NAME>            Use the rootfs of a named environment
+        --env=<NAME>            Use the rootfs of a named environment (inline)
 
 Options for 'apk':
     -R, --rootfs <DIR>          Specify rootfs directory
         --rootfs=<DIR>          Specify rootfs directory (inline)
+        --env <NAME>            Use the rootfs of a named environment
+        --env=<NAME>            Use the rootfs of a named environment (inline)
 
 Options for 'aports':
     -u, --update                Update the local aports repository to the latest version
@@ -65,34 +238,62 @@ Options for 'aports':
     -g, --get=<PKG>             Download the APKBUILD in the Alpine aports
     -R, --rootfs <DIR>          Specify rootfs directory
         --rootfs=<DIR>          Specify rootfs directory (inline)
+        --env <NAME>            Use the rootfs of a named environment
+        --env=<NAME>            Use the rootfs of a named environment (inline)
 
 Options for 'builder':
     -a, --apkbuild <APKBUILD>   Use a specific APKBUILD file as input
         --apkbuild=<APKBUILD>   Use a specific APKBUILD file as input (inline)
+        --clean                 Build in an ephemeral session, keeping only the packages
     -R, --rootfs <DIR>          Specify rootfs directory
         --rootfs=<DIR>          Specify rootfs directory (inline)
+        --env <NAME>            Use the rootfs of a named environment
+        --env=<NAME>            Use the rootfs of a named environment (inline)
 
 Options for 'run':
     -0, --root                  Run with root privileges inside rootfs
     -i, --ignore-extra-binds    Ignore additional bind mounts
-    -b, --bind-args <ARGS>      Additional bind arguments (can be inline or next argument)
-        --bind-args=<ARGS>      Additional bind arguments (inline)
+    -e, --ephemeral             Discard every change made to the rootfs on exit
+    -m, --mount <SRC:DST[:ro]>  Mount a host path in the rootfs, read-only with 'ro' (can be repeated)
+        --mount=<SRC:DST[:ro]>  Mount a host path in the rootfs (inline)
+    -b, --bind-args <ARGS>      Additional bind arguments, quoted like in a shell (can be inline or next argument)
+        --bind-args=<ARGS>      Additional bind arguments, quoted like in a shell (inline)
     -c, --command <CMD>         Command to execute inside rootfs (can be repeated)
         --command=<CMD>         Command to execute (inline)
     -R, --rootfs <DIR>          Specify rootfs directory
         --rootfs=<DIR>          Specify rootfs directory (inline)
+        --env <NAME>            Use the rootfs of a named environment
+        --env=<NAME>            Use the rootfs of a named environment (inline)
 
 Options for 'config':
         --use-proot             Use 'proot' as rootfs handler (default)
         --use-bwrap             Use 'bwrap' as rootfs handler
+        --use-native            Use the built-in namespace sandbox as rootfs handler
+        --apk-cache             Share an apk package cache per arch and release between rootfs
+        --no-apk-cache          Do not share the apk package cache (default)
         --use-latest-stable     Use 'latest-stable' release (default)
         --use-edge              Use 'edge' release
+        --release <BRANCH>      Set the release branch (e.g., v3.19)
+        --release=<BRANCH>      Set the release branch (inline)
+        --version <X.Y.Z>       Set the Alpine point version of the minirootfs ('latest' to unset)
+        --version=<X.Y.Z>       Set the Alpine point version of the minirootfs (inline)
+                                (only the branch is pinned, apk then follows its latest packages)
         --cache-dir <DIR>       Set cache directory
         --cache-dir=<DIR>       Set cache directory (inline)
         --output-dir <DIR>      Set output directory (default current directory)
         --output-dir=<DIR>      Set output directory (inline)
         --rootfs-dir <DIR>      Set rootfs directory
         --rootfs-dir=<DIR>      Set rootfs directory (inline)
+        --envs-dir <DIR>        Set the directory of named environments
+        --envs-dir=<DIR>        Set the directory of named environments (inline)
+        --default-preset <NAME> Set the package preset installed by 'setup' (default sdk)
+        --default-preset=<NAME> Set the package preset installed by 'setup' (inline)
+        --proxy <URL>           Set the proxy for downloads and the rootfs ('none' for the environment)
+        --proxy=<URL>           Set the proxy for downloads and the rootfs (inline)
+        --no-proxy <HOSTS>      Set the comma separated hosts reached without the proxy
+        --no-proxy=<HOSTS>      Set the comma separated hosts reached without the proxy (inline)
+        --ca-bundle <FILE>      Trust the certificates of a PEM bundle ('none' for the built-in ones)
+        --ca-bundle=<FILE>      Trust the certificates of a PEM bundle (inline)
         --default-mirror <URL>  Set default Alpine mirror
         --default-mirror=<URL>  Set default Alpine mirror (inline)
 
@@ -104,17 +305,35 @@ Environment variables:
     ALPACK_ARCH       Define the target architecture for rootfs (e.g., x86_64, aarch64)
     ALPACK_ROOTFS     Specify the path to the root filesystem used by ALPack
     ALPACK_CACHE      Specify the path to the cache directory used by ALPack
+    ALPACK_QEMU       Specify the qemu-user binary used to run foreign-architecture rootfs
+                      (qemu-user-static must be installed on the host, it is not downloaded)
 
 Examples:
     {cmd} setup --rootfs=/mnt/alpine --minimal --edge
     {cmd} apk --rootfs=/mnt/alpine install curl
     {cmd} run -R /mnt/alpine -0 -- fdisk -l
+    {cmd} env create web --release v3.20
+    {cmd} env create arm --arch aarch64
+    {cmd} build-env -f ALPack.toml
+    {cmd} setup --preset rust --packages \"htop vim\"
+    {cmd} run --env web
+    {cmd} run --ephemeral -0 -- apk add gcc
+    {cmd} mirror rank --save
+    {cmd} repo add https://dl-cdn.alpinelinux.org/alpine/edge/testing --tag testing
+    {cmd} snapshot create before-upgrade
+    {cmd} snapshot restore before-upgrade
+    {cmd} upgrade-release --to v3.20 --env web
+    {cmd} export --format oci -o image.tar --env web
+    {cmd} import image.tar --name debug
+    {cmd} doctor --env web
+    {cmd} cache clean --older-than 30d --keep-latest 1
 "
     );
     Ok(())
 }
 
 /// alpack is the main logic function of the program, returning a Result for error handling
+#[allow(clippy::unit_arg)]
 fn alpack() -> Result<(), Box<dyn Error>> {
     let cmd = env::current_exe()
         .unwrap()
@@ -146,6 +365,10 @@ fn alpack() -> Result<(), Box<dyn Error>> {
                     rootfs = args.next();
                 } else if arg.starts_with("--rootfs=") {
                     rootfs = Some(arg.trim_start_matches("--rootfs=").to_string());
+                } else if arg == "--env" {
+                    rootfs = Some(Registry::resolve(&args.next().unwrap_or_default())?);
+                } else if arg.starts_with("--env=") {
+                    rootfs = Some(Registry::resolve(arg.trim_start_matches("--env="))?);
                 } else if subcommand.is_none() {
                     subcommand = Some(arg);
                 } else {
@@ -160,10 +383,20 @@ fn alpack() -> Result<(), Box<dyn Error>> {
             Apk::new(cmd, command, remaining_args, None).run()
         }
         Some("aports") => Aports::new(cmd, remaining_args).run(),
+        Some("build-env") => Recipe::new(cmd, remaining_args).run(),
         Some("builder") => Builder::new(cmd, remaining_args).run(),
+        Some("cache") => Cache::new(cmd, remaining_args).run(),
         Some("config") => Config::new(cmd, remaining_args).run(),
+        Some("doctor") => Doctor::new(cmd, remaining_args).run(),
+        Some("env") => Profile::new(cmd, remaining_args).run(),
+        Some("export") => Export::new(cmd, remaining_args).run(),
+        Some("import") => Import::new(cmd, remaining_args).run(),
+        Some("mirror") => Mirrors::new(cmd, remaining_args).run(),
+        Some("repo") => Repo::new(cmd, remaining_args).run(),
         Some("run") => Run::new(cmd, remaining_args).run(),
         Some("setup") => Setup::new(cmd, remaining_args).run(),
+        Some("snapshot") => Snapshot::new(cmd, remaining_args).run(),
+        Some("upgrade-release") => Upgrade::new(cmd, remaining_args).run(),
         Some("-h") | Some("--help") => print_help(&cmd),
         Some("-V") | Some("--version") => {
             let version = env!("CARGO_PKG_VERSION");
@@ -1,26 +1,47 @@
 use crate::settings::Settings;
-use crate::utils;
+use crate::{download, utils};
+use regex::Regex;
+use scraper::{Html, Selector};
+use std::collections::HashSet;
 use std::error::Error;
+use std::fs;
 
 pub struct Mirror {
     mirror: Option<String>,
     release: Option<String>,
+    version: Option<String>,
+    arch: Option<String>,
+    fallbacks: Vec<String>,
+    extra: Vec<String>,
 }
 
 impl Mirror {
     pub fn new(mirror: Option<String>, release: Option<String>) -> Self {
-        Mirror { mirror, release }
+        Mirror {
+            mirror,
+            release,
+            version: None,
+            arch: None,
+            fallbacks: Vec::new(),
+            extra: Vec::new(),
+        }
     }
 
     pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
         let sett = Settings::load_or_create();
 
         if self.mirror.clone().unwrap_or_default().is_empty() {
-            self.mirror = Some(sett.default_mirror);
+            self.mirror = Some(sett.default_mirror.clone());
         }
+        let current = Self::normalize(self.mirror.as_ref().unwrap());
+        self.fallbacks = Self::fallback_list(&current, &sett.fallback_mirrors, &sett.default_mirror);
+        self.mirror = Some(current);
         if self.release.clone().unwrap_or_default().is_empty() {
             self.release = Some(sett.release);
         }
+        if self.arch.clone().unwrap_or_default().is_empty() {
+            self.arch = Some(utils::get_arch());
+        }
         Ok(())
     }
 
@@ -29,22 +50,316 @@ impl Mirror {
             "{}{}/releases/{}/",
             self.mirror.as_ref().unwrap(),
             self.release.as_ref().unwrap(),
-            utils::get_arch()
+            self.arch.as_ref().unwrap()
         )
     }
 
+    /// Returns the base URL of the mirror in use.
+    pub fn get_base(&self) -> String {
+        self.mirror.clone().unwrap_or_default()
+    }
+
+    /// Switches to the next fallback mirror.
+    ///
+    /// The fallbacks are the `fallback_mirrors` of the configuration,
+    /// followed by the default mirror when another one was requested.
+    ///
+    /// # Returns
+    /// * `Some(String)` with the new mirror URL.
+    /// * `None` if every mirror was already tried.
+    ///
+    /// # Example
+    /// ```
+    /// if let Some(next) = mirror.next_mirror() {
+    ///     println!("Trying {next}...");
+    /// }
+    /// ```
+    pub fn next_mirror(&mut self) -> Option<String> {
+        if self.fallbacks.is_empty() {
+            return None;
+        }
+        let next = self.fallbacks.remove(0);
+        self.mirror = Some(next.clone());
+        Some(next)
+    }
+
+    /// Returns the mirrors tried after `current`, in order and without duplicates.
+    ///
+    /// # Example
+    /// ```
+    /// let fallbacks = Mirror::fallback_list("https://a/", &sett.fallback_mirrors, &sett.default_mirror);
+    /// ```
+    fn fallback_list(current: &str, configured: &[String], default: &str) -> Vec<String> {
+        let mut seen = HashSet::from([current.to_string()]);
+        configured
+            .iter()
+            .map(String::as_str)
+            .chain([default])
+            .map(Self::normalize)
+            .filter(|m| seen.insert(m.clone()))
+            .collect()
+    }
+
+    /// Normalizes a mirror URL, turning local paths into `file://` URLs.
+    ///
+    /// # Example
+    /// ```
+    /// assert_eq!(Mirror::normalize("/srv/alpine"), "file:///srv/alpine/");
+    /// ```
+    pub fn normalize(mirror: &str) -> String {
+        let mut m = mirror.trim().to_string();
+        if m.starts_with('/') {
+            m.insert_str(0, "file://");
+        }
+        if !m.ends_with('/') {
+            m.push('/');
+        }
+        m
+    }
+
+    /// Returns the release branch used by this mirror.
+    pub fn get_release(&self) -> String {
+        self.release.clone().unwrap_or_default()
+    }
+
+    /// Returns the architecture of the rootfs downloaded from this mirror.
+    pub fn get_arch(&self) -> String {
+        self.arch.clone().unwrap_or_default()
+    }
+
+    /// Sets the architecture of the rootfs, overriding `ALPACK_ARCH`.
+    ///
+    /// # Example
+    /// ```
+    /// mirror.set_arch(Some("aarch64".to_string()));
+    /// ```
+    pub fn set_arch(&mut self, arch: Option<String>) {
+        self.arch = arch;
+    }
+
+    /// Records the exact Alpine version installed from this mirror.
+    ///
+    /// The version is written as a header of the generated repositories file.
+    ///
+    /// # Example
+    /// ```
+    /// mirror.set_version(Some("3.19.4".to_string()));
+    /// ```
+    pub fn set_version(&mut self, version: Option<String>) {
+        self.version = version;
+    }
+
+    /// Sets the extra repository lines appended after the Alpine ones.
+    ///
+    /// # Example
+    /// ```
+    /// mirror.set_extra_repositories(vec!["@internal https://pkgs.example.org/alpine".to_string()]);
+    /// ```
+    pub fn set_extra_repositories(&mut self, extra: Vec<String>) {
+        self.extra = extra;
+    }
+
+    /// Returns the content of `/etc/apk/repositories` for the mirror and release.
+    ///
+    /// Alpine mirrors only serve the latest packages of a branch, so the
+    /// point version set with `set_version` is written as an informational
+    /// header and does not pin the packages apk installs.
     pub fn get_repository(&mut self) -> String {
-        if self.release == Some("edge".to_string()) {
-            return format!(
-                "{a}{b}/main\n{a}{b}/community\n{a}{b}/testing",
-                a = self.mirror.as_ref().unwrap(),
+        let header = match &self.version {
+            Some(v) => format!(
+                "# Alpine Linux {v} ({})\n",
+                self.release.as_ref().unwrap()
+            ),
+            None => String::new(),
+        };
+
+        let mirror = self.mirror.as_ref().unwrap();
+        let base = utils::local_path(mirror).unwrap_or(mirror);
+
+        let mut repos = if self.release == Some("edge".to_string()) {
+            format!(
+                "{header}{a}{b}/main\n{a}{b}/community\n{a}{b}/testing",
+                a = base,
+                b = self.release.as_ref().unwrap()
+            )
+        } else {
+            format!(
+                "{header}{a}{b}/main\n{a}{b}/community",
+                a = base,
                 b = self.release.as_ref().unwrap()
-            );
+            )
+        };
+
+        for extra in &self.extra {
+            repos.push('\n');
+            repos.push_str(extra);
         }
-        format!(
-            "{a}{b}/main\n{a}{b}/community",
-            a = self.mirror.as_ref().unwrap(),
-            b = self.release.as_ref().unwrap()
+        repos
+    }
+
+    /// Lists the entries of a mirror directory.
+    ///
+    /// Remote mirrors are read from their HTML index page, while local
+    /// mirrors (`file://` URLs) are read directly from the filesystem.
+    ///
+    /// # Arguments
+    /// * `url` - The URL of the directory.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<String>)` with the entry names (directories end with `/`).
+    /// * `Err` if the directory cannot be read.
+    ///
+    /// # Example
+    /// ```
+    /// let entries = Mirror::list_index("https://dl-cdn.alpinelinux.org/alpine/")?;
+    /// ```
+    pub fn list_index(url: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        if let Some(path) = utils::local_path(url) {
+            let mut entries = Vec::new();
+            for entry in fs::read_dir(path)?.flatten() {
+                let mut name = entry.file_name().to_string_lossy().to_string();
+                if entry.path().is_dir() {
+                    name.push('/');
+                }
+                entries.push(name);
+            }
+            return Ok(entries);
+        }
+
+        let res = download::fetch_text(url.to_string())?;
+        let document = Html::parse_document(res.as_str());
+        let selector = Selector::parse("a").unwrap();
+
+        Ok(document
+            .select(&selector)
+            .filter_map(|e| e.value().attr("href"))
+            .map(|href| href.to_string())
+            .collect())
+    }
+
+    /// Checks whether a string is a valid Alpine release branch name.
+    ///
+    /// # Returns
+    /// * `true` for `latest-stable`, `edge` and `vX.Y` branches.
+    ///
+    /// # Example
+    /// ```
+    /// assert!(Mirror::is_valid_release("v3.19"));
+    /// ```
+    pub fn is_valid_release(release: &str) -> bool {
+        release == "latest-stable"
+            || release == "edge"
+            || Regex::new(r"^v\d+\.\d+$").unwrap().is_match(release)
+    }
+
+    /// Checks that a release branch is published on a mirror.
+    ///
+    /// The branch is looked up in the directory listing of the mirror. If
+    /// the listing cannot be fetched, a warning is printed and the branch is
+    /// assumed to exist, so an unreachable mirror is reported by the request
+    /// that actually needs it.
+    ///
+    /// # Arguments
+    /// * `mirror` - The base URL of the mirror.
+    /// * `release` - The release branch (`latest-stable`, `edge` or `vX.Y`).
+    ///
+    /// # Returns
+    /// * `Ok(())` if the branch exists or the mirror could not be listed.
+    /// * `Err` with the available branches if it does not exist.
+    ///
+    /// # Example
+    /// ```
+    /// Mirror::check_release("https://dl-cdn.alpinelinux.org/alpine/", "v3.18")?;
+    /// ```
+    pub fn check_release(mirror: &str, release: &str) -> Result<(), Box<dyn Error>> {
+        let mirror = Self::normalize(mirror);
+        let entries = match Self::list_index(&mirror) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("\x1b[1;33mWarning\x1b[0m: Could not list the branches of '{mirror}': {e}");
+                return Ok(());
+            }
+        };
+
+        let branches: Vec<String> = entries
+            .iter()
+            .map(|e| e.trim_end_matches('/').rsplit('/').next().unwrap_or_default().to_string())
+            .filter(|e| Self::is_valid_release(e))
+            .collect();
+        if branches.iter().any(|b| b == release) {
+            return Ok(());
+        }
+
+        let mut versions: Vec<(u32, u32, &String)> = branches
+            .iter()
+            .filter_map(|b| {
+                let (major, minor) = b.strip_prefix('v')?.split_once('.')?;
+                Some((major.parse().ok()?, minor.parse().ok()?, b))
+            })
+            .collect();
+        versions.sort();
+        let available: Vec<&str> = versions.iter().map(|(_, _, b)| b.as_str()).collect();
+
+        Err(format!(
+            "Release '{release}' not found on {mirror}\nAvailable branches: {}",
+            available.join(", ")
         )
+        .into())
+    }
+
+    /// Returns the release branch a point version belongs to.
+    ///
+    /// # Returns
+    /// * `Some(String)` with the branch name (e.g. `v3.19` for `3.19.4`).
+    /// * `None` if the version is not in the `X.Y.Z` format.
+    ///
+    /// # Example
+    /// ```
+    /// assert_eq!(Mirror::release_from_version("3.19.4"), Some("v3.19".to_string()));
+    /// ```
+    pub fn release_from_version(version: &str) -> Option<String> {
+        let caps = Regex::new(r"^(\d+)\.(\d+)\.\d+").unwrap().captures(version)?;
+        Some(format!("v{}.{}", &caps[1], &caps[2]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::tests::serve;
+
+    #[test]
+    fn fallback_list_keeps_the_order_without_duplicates() {
+        let configured = ["https://b".to_string(), "https://c/".to_string(), "https://a/".to_string(), "https://b/".to_string()];
+        assert_eq!(
+            Mirror::fallback_list("https://a/", &configured, "https://c"),
+            ["https://b/", "https://c/"]
+        );
+    }
+
+    #[test]
+    fn next_mirror_fails_over_to_a_working_mirror() {
+        let broken = serve(|_| (404, String::new()));
+        let working = serve(|path| match path {
+            "/v3.22/releases/x86_64/" => (200, r#"<a href="alpine-minirootfs-3.22.1-x86_64.tar.gz">"#.to_string()),
+            _ => (404, String::new()),
+        });
+
+        let mut mirror = Mirror::new(Some(broken.clone()), Some("v3.22".to_string()));
+        mirror.set_arch(Some("x86_64".to_string()));
+        mirror.fallbacks = Mirror::fallback_list(&broken, &[broken.clone(), working.clone(), working.clone()], &broken);
+
+        let mut tried = vec![mirror.get_base()];
+        let entries = loop {
+            match Mirror::list_index(&mirror.get_mirror()) {
+                Ok(entries) => break entries,
+                Err(_) => tried.push(mirror.next_mirror().expect("no mirror left")),
+            }
+        };
+
+        assert_eq!(tried, [broken, working.clone()]);
+        assert_eq!(entries, ["alpine-minirootfs-3.22.1-x86_64.tar.gz"]);
+        assert_eq!(mirror.get_base(), working);
+        assert_eq!(mirror.next_mirror(), None);
     }
 }
@@ -0,0 +1,306 @@
+use crate::mirror::Mirror;
+use crate::settings::Settings;
+use crate::utils::{self, _parse_key_value};
+use crate::{download, parse_key_value};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::{HashSet, VecDeque};
+use std::error::Error;
+use std::thread;
+use std::time::Duration;
+
+/// List of the official Alpine Linux mirrors.
+const MIRRORS_URL: &str = "https://dl-cdn.alpinelinux.org/alpine/MIRRORS.txt";
+
+/// Number of mirrors probed at the same time by `mirror rank`.
+const PROBE_THREADS: usize = 16;
+
+/// Number of fallback mirrors saved by `mirror rank --save`.
+const SAVED_FALLBACKS: usize = 3;
+
+pub struct Mirrors {
+    name: String,
+    remaining_args: Vec<String>,
+}
+
+impl Mirrors {
+    pub fn new(name: String, remaining_args: Vec<String>) -> Self {
+        Mirrors {
+            name,
+            remaining_args,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<_> = self.remaining_args.clone().into();
+        let sett = Settings::load_or_create();
+
+        let action = args.pop_front();
+        let (mut source, mut release, mut arch): (String, String, String) =
+            (MIRRORS_URL.to_string(), sett.release.clone(), utils::get_arch());
+        let mut timeout: u64 = 5;
+        let mut save = false;
+        let mut urls: Vec<String> = Vec::new();
+
+        while let Some(arg) = args.pop_front() {
+            match arg.as_str() {
+                a if a.starts_with("--source=") => {
+                    source = parse_key_value!("mirror", "file", arg)?.unwrap();
+                }
+                "--source" => {
+                    source = parse_key_value!("mirror", "file", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                }
+                a if a.starts_with("--release=") => {
+                    release = parse_key_value!("mirror", "release", arg)?.unwrap();
+                }
+                "--release" => {
+                    release = parse_key_value!("mirror", "release", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                }
+                a if a.starts_with("--arch=") => {
+                    arch = parse_key_value!("mirror", "arch", arg)?.unwrap();
+                }
+                "--arch" => {
+                    arch = parse_key_value!("mirror", "arch", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                }
+                a if a.starts_with("--timeout=") => {
+                    timeout = self.parse_timeout(parse_key_value!("mirror", "seconds", arg)?.unwrap())?;
+                }
+                "--timeout" => {
+                    timeout = self.parse_timeout(parse_key_value!("mirror", "seconds", arg, args.pop_front().unwrap_or_default())?.unwrap())?;
+                }
+                "--save" => {
+                    save = true;
+                }
+                a if a.starts_with('-') => {
+                    return Err(format!("{c}: mirror: invalid argument '{arg}'\nUse '{c} --help' to see available options.", c = self.name).into())
+                }
+                _ => urls.push(arg),
+            }
+        }
+
+        if !urls.is_empty() && action.as_deref() != Some("use") {
+            return Err(format!("{c}: mirror: invalid argument '{}'\nUse '{c} --help' to see available options.", urls[0], c = self.name).into());
+        }
+
+        match action.as_deref() {
+            Some("list") | Some("ls") => self.list(&source, &sett),
+            Some("rank") => {
+                if !Mirror::is_valid_release(&release) {
+                    return Err(format!("{}: mirror: invalid release '{release}', expected 'latest-stable', 'edge' or 'vX.Y'", self.name).into());
+                }
+                let ranked = self.rank(&source, &release, &arch, Duration::from_secs(timeout))?;
+                if save {
+                    self.save(ranked, SAVED_FALLBACKS)?;
+                }
+                Ok(())
+            }
+            Some("use") => {
+                if urls.is_empty() {
+                    return Err(format!(
+                        "{c}: mirror: use requires a <URL> as argument.\nUsage: {c} mirror use <URL> [FALLBACK...]",
+                        c = self.name
+                    )
+                    .into());
+                }
+                self.save(urls, usize::MAX)
+            }
+            Some(other) => Err(format!(
+                "{c}: mirror: invalid argument '{other}'\nUse '{c} --help' to see available options.",
+                c = self.name
+            )
+            .into()),
+            None => Err(format!(
+                "{c}: mirror: no parameter specified\nUse '{c} --help' to see available options.",
+                c = self.name
+            )
+            .into()),
+        }
+    }
+
+    /// Reads the mirror list from `MIRRORS.txt` or a local copy of it.
+    ///
+    /// # Arguments
+    /// * `source` - The URL or path of the list.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<String>)` with the normalized mirror URLs.
+    /// * `Err` if the list cannot be read.
+    ///
+    /// # Example
+    /// ```
+    /// let mirrors = Mirrors::fetch_list("https://dl-cdn.alpinelinux.org/alpine/MIRRORS.txt")?;
+    /// ```
+    pub fn fetch_list(source: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let content = match source.starts_with('/') {
+            true => std::fs::read_to_string(source)?,
+            false => download::fetch_text(source.to_string())?,
+        };
+
+        let mut seen = HashSet::new();
+        let mirrors: Vec<String> = content
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(Mirror::normalize)
+            .filter(|m| seen.insert(m.clone()))
+            .collect();
+
+        if mirrors.is_empty() {
+            return Err(format!("No mirror found in '{source}'").into());
+        }
+        Ok(mirrors)
+    }
+
+    /// Prints the available mirrors, marking the configured ones.
+    fn list(&self, source: &str, sett: &Settings) -> Result<(), Box<dyn Error>> {
+        let default = Mirror::normalize(&sett.default_mirror);
+        let fallbacks: Vec<String> = sett.fallback_mirrors.iter().map(|m| Mirror::normalize(m)).collect();
+
+        for m in Self::fetch_list(source)? {
+            let mark = match () {
+                _ if m == default => "*",
+                _ if fallbacks.contains(&m) => "+",
+                _ => " ",
+            };
+            println!("{mark} {m}");
+        }
+        println!("\n(*) default mirror   (+) fallback mirror");
+        Ok(())
+    }
+
+    /// Probes every mirror for a release and prints them by response time.
+    ///
+    /// A mirror is probed by requesting the `main` index of the release for
+    /// the architecture, so mirrors missing it are reported as unreachable.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<String>)` with the reachable mirrors, fastest first.
+    /// * `Err` if the mirror list cannot be read or no mirror answered.
+    fn rank(&self, source: &str, release: &str, arch: &str, timeout: Duration) -> Result<Vec<String>, Box<dyn Error>> {
+        let mirrors = Self::fetch_list(source)?;
+
+        let bar = ProgressBar::new(mirrors.len() as u64);
+        bar.set_message("Probing mirrors...");
+        bar.set_style(
+            ProgressStyle::with_template(utils::DOWNLOAD_TEMPLATE.replace("{bytes}/{total_bytes}", "{pos}/{len}").as_str())
+                .unwrap()
+                .progress_chars("##-"),
+        );
+
+        let results = Self::probe_all(&mirrors, release, arch, timeout, &bar);
+        bar.finish_and_clear();
+
+        println!("{}", utils::separator_line());
+        println!("{:>4}  {:>8}  MIRROR ({release}, {arch})", "RANK", "TIME");
+        println!("{}", utils::separator_line());
+        for (n, (m, time)) in results.iter().enumerate() {
+            match time {
+                Some(t) => println!("{:>4}  {:>6}ms  {m}", n + 1, t.as_millis()),
+                None => println!("{:>4}  {:>8}  {m}", "-", "failed"),
+            }
+        }
+        println!("{}", utils::separator_line());
+
+        let ranked: Vec<String> = results
+            .into_iter()
+            .filter(|(_, time)| time.is_some())
+            .map(|(m, _)| m)
+            .collect();
+        if ranked.is_empty() {
+            return Err(format!("{}: mirror: no mirror answered for {release} ({arch})", self.name).into());
+        }
+        Ok(ranked)
+    }
+
+    /// Probes the `main` index of a release on every mirror.
+    ///
+    /// # Returns
+    /// * `Vec<(String, Option<Duration>)>` with the response time of every
+    ///   mirror, fastest first and the unreachable ones (`None`) last.
+    fn probe_all(
+        mirrors: &[String],
+        release: &str,
+        arch: &str,
+        timeout: Duration,
+        bar: &ProgressBar,
+    ) -> Vec<(String, Option<Duration>)> {
+        let mut results: Vec<(String, Option<Duration>)> = Vec::new();
+        for chunk in mirrors.chunks(PROBE_THREADS) {
+            thread::scope(|scope| {
+                let handles: Vec<_> = chunk
+                    .iter()
+                    .map(|m| {
+                        let url = format!("{m}{release}/main/{arch}/APKINDEX.tar.gz");
+                        scope.spawn(move || {
+                            let time = download::probe(&url, timeout).ok();
+                            bar.inc(1);
+                            (m.clone(), time)
+                        })
+                    })
+                    .collect();
+                results.extend(handles.into_iter().filter_map(|h| h.join().ok()));
+            });
+        }
+
+        results.sort_by_key(|(_, time)| time.unwrap_or(Duration::MAX));
+        results
+    }
+
+    /// Saves the first mirror as the default one and up to `fallbacks` of the next as fallbacks.
+    fn save(&self, mirrors: Vec<String>, fallbacks: usize) -> Result<(), Box<dyn Error>> {
+        let mut sett = Settings::load_or_create();
+        let mut mirrors = mirrors.iter().map(|m| Mirror::normalize(m));
+
+        sett.default_mirror = mirrors.next().unwrap_or_default();
+        sett.fallback_mirrors = mirrors.take(fallbacks).collect();
+        sett.show_config_changes();
+        sett.save()?;
+        Ok(())
+    }
+
+    /// Parses the `--timeout` value, in seconds.
+    fn parse_timeout(&self, value: String) -> Result<u64, Box<dyn Error>> {
+        match value.parse::<u64>() {
+            Ok(secs) if secs > 0 => Ok(secs),
+            _ => Err(format!("{}: mirror: invalid timeout '{value}'", self.name).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::tests::serve;
+
+    #[test]
+    fn fetch_list_keeps_the_order_without_duplicates() {
+        let source = serve(|_| (200, "https://b/\nhttps://a\n# comment\n\nhttps://b\nhttps://c/\nhttps://a/\n".to_string()));
+        assert_eq!(
+            Mirrors::fetch_list(&format!("{source}MIRRORS.txt")).unwrap(),
+            ["https://b/", "https://a/", "https://c/"]
+        );
+    }
+
+    #[test]
+    fn probe_all_ranks_mirrors_by_response_time() {
+        let index = |path: &str| match path {
+            "/v3.22/main/x86_64/APKINDEX.tar.gz" => (200, String::new()),
+            _ => (404, String::new()),
+        };
+        let slow = serve(move |path| {
+            thread::sleep(Duration::from_millis(300));
+            index(path)
+        });
+        let fast = serve(index);
+        let missing = serve(|_| (404, String::new()));
+
+        let mirrors = [missing.clone(), slow.clone(), fast.clone()];
+        let results = Mirrors::probe_all(&mirrors, "v3.22", "x86_64", Duration::from_secs(5), &ProgressBar::hidden());
+
+        let ranked: Vec<&String> = results.iter().map(|(m, _)| m).collect();
+        assert_eq!(ranked, [&fast, &slow, &missing]);
+        assert!(results[0].1.is_some() && results[1].1.is_some());
+        assert_eq!(results[2].1, None);
+        assert!(results[1].1 >= Some(Duration::from_millis(300)));
+    }
+}
@@ -0,0 +1,397 @@
+use crate::backend::{Backend, HOST_FILES};
+use crate::command::{self, Command};
+use crate::sandbox::{SandboxSpec, SANDBOX_PATH};
+
+use std::ffi::{CString, OsStr, OsString};
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use std::{env, ptr};
+
+/// Maximum number of symlinks followed when resolving a path inside the rootfs.
+const MAX_SYMLINKS: usize = 40;
+
+/// The native backend, setting up the sandbox without any helper binary.
+///
+/// ALPack creates the user, mount and PID namespaces of the session itself:
+/// the rootfs is bound on itself, the host directories and the mounts of the
+/// spec are bound into it, and `pivot_root` makes it the root of the session.
+/// Like bubblewrap, it needs unprivileged user namespaces.
+pub struct Native;
+
+impl Backend for Native {
+    fn run(&self, spec: &SandboxSpec) -> io::Result<i32> {
+        if !spec.backend_args.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the native backend does not take bind arguments"));
+        }
+        Command::fix_mtab_symlink(&spec.root)?;
+
+        let session = Session::new(spec)?;
+        if !is_single_threaded() {
+            return Err(io::Error::other("the native backend must be started before any other thread"));
+        }
+
+        // SAFETY: the handler does nothing and is reset to the default one in the session on exec.
+        let previous = unsafe { libc::signal(libc::SIGINT, command::ignore_signal as *const () as libc::sighandler_t) };
+
+        // SAFETY: ALPack was checked above to run a single thread, so no lock of
+        // the allocator or of the std library is held in the child, which can use them.
+        let code = match unsafe { libc::fork() } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => {
+                let code = session.enter();
+                // SAFETY: exits the child without running the destructors of the parent.
+                unsafe { libc::_exit(code) }
+            }
+            pid => wait(pid),
+        };
+
+        // SAFETY: puts back the handler returned by the call above.
+        unsafe {
+            libc::signal(libc::SIGINT, previous);
+        }
+        code
+    }
+}
+
+/// A bind mount done before `pivot_root`.
+struct BindMount {
+    source: PathBuf,
+    dest: PathBuf,
+    read_only: bool,
+    /// Skip it when the source is missing or cannot be mounted, instead of failing.
+    optional: bool,
+}
+
+/// Everything the children need to start the session, prepared before forking.
+struct Session {
+    root: PathBuf,
+    binds: Vec<BindMount>,
+    cwd: PathBuf,
+    argv: Vec<CString>,
+    envp: Vec<CString>,
+    uid_map: String,
+    gid_map: String,
+}
+
+impl Session {
+    fn new(spec: &SandboxSpec) -> io::Result<Self> {
+        let mut binds = Vec::new();
+        let mut host = |path: &OsStr, read_only: bool| binds.push(BindMount {
+            source: path.into(),
+            dest: path.into(),
+            read_only,
+            optional: true,
+        });
+
+        host("/dev".as_ref(), false);
+        host("/sys".as_ref(), true);
+        for dir in ["/tmp", "/run"] {
+            host(dir.as_ref(), false);
+        }
+        for file in HOST_FILES {
+            host(file.as_ref(), true);
+        }
+        if let Some(home) = env::var_os("HOME") {
+            host(&home, false);
+        }
+        if !spec.rootfs_users {
+            for file in ["/etc/passwd", "/etc/group"] {
+                host(file.as_ref(), true);
+            }
+        }
+        binds.extend(spec.mounts.iter().map(|m| BindMount {
+            source: m.source.clone(),
+            dest: m.dest.clone(),
+            read_only: m.read_only,
+            optional: false,
+        }));
+
+        let mut envp: Vec<OsString> = env::vars_os()
+            .filter(|(name, _)| name != "PATH")
+            .map(|(name, value)| {
+                let mut var = name;
+                var.push("=");
+                var.push(value);
+                var
+            })
+            .collect();
+        envp.push(format!("PATH={SANDBOX_PATH}").into());
+
+        // SAFETY: getuid and getgid cannot fail.
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let (inner_uid, inner_gid) = match spec.root_user {
+            true => (0, 0),
+            false => (uid, gid),
+        };
+
+        Ok(Session {
+            root: spec.root.clone(),
+            binds,
+            cwd: spec.cwd.clone().or_else(|| env::current_dir().ok()).unwrap_or_else(|| "/".into()),
+            argv: spec.command_args().iter().map(|a| cstring(a)).collect::<io::Result<_>>()?,
+            envp: envp.iter().map(|v| cstring(v)).collect::<io::Result<_>>()?,
+            uid_map: format!("{inner_uid} {uid} 1"),
+            gid_map: format!("{inner_gid} {gid} 1"),
+        })
+    }
+
+    /// Creates the namespaces and waits for the first process of the session.
+    ///
+    /// Runs in the child of ALPack. The PID namespace only applies to the
+    /// children of the process creating it, so the session is started in a
+    /// second child, which is PID 1 of the sandbox.
+    ///
+    /// # Returns
+    /// The exit code of the session, or `1` if the sandbox could not be set up.
+    fn enter(&self) -> i32 {
+        let result = (|| {
+            // SAFETY: plain system calls with valid arguments.
+            unsafe {
+                check("prctl", libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL))?;
+                check("unshare", libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNS | libc::CLONE_NEWPID))?;
+            }
+            context("/proc/self/setgroups", fs::write("/proc/self/setgroups", "deny"))?;
+            context("/proc/self/uid_map", fs::write("/proc/self/uid_map", &self.uid_map))?;
+            context("/proc/self/gid_map", fs::write("/proc/self/gid_map", &self.gid_map))?;
+
+            // SAFETY: this process runs no other thread.
+            match unsafe { libc::fork() } {
+                -1 => Err(io::Error::last_os_error()),
+                0 => {
+                    let e = match self.setup() {
+                        Ok(()) => self.exec(),
+                        Err(e) => e,
+                    };
+                    eprintln!("\x1b[1;31mError\x1b[0m: native sandbox: {e}");
+                    // SAFETY: exits the child without running the destructors of the parent.
+                    unsafe { libc::_exit(1) }
+                }
+                pid => wait(pid),
+            }
+        })();
+
+        result.unwrap_or_else(|e| {
+            eprintln!("\x1b[1;31mError\x1b[0m: native sandbox: {e}");
+            1
+        })
+    }
+
+    /// Mounts the rootfs and the binds, then makes the rootfs the root of the process.
+    fn setup(&self) -> io::Result<()> {
+        // SAFETY: a plain system call with valid arguments.
+        unsafe {
+            check("prctl", libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL))?;
+        }
+        context("making the mounts private", mount(None, Path::new("/"), None, libc::MS_REC | libc::MS_PRIVATE))?;
+        context("binding the rootfs", mount(Some(self.root.as_os_str()), &self.root, None, libc::MS_BIND | libc::MS_REC))?;
+
+        for bind in &self.binds {
+            match self.bind(bind) {
+                Ok(()) => {}
+                Err(_) if bind.optional => {}
+                Err(e) => return Err(io::Error::new(e.kind(), format!("binding '{}': {e}", bind.source.display()))),
+            }
+        }
+
+        let proc = self.mount_point(Path::new("/proc"), true)?;
+        let flags = libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC;
+        if mount(Some("proc".as_ref()), &proc, Some("proc"), flags).is_err() {
+            // A fresh procfs is refused when parts of the one of the host are hidden, as in containers.
+            context("binding /proc", mount(Some("/proc".as_ref()), &proc, None, libc::MS_BIND | libc::MS_REC))?;
+        }
+
+        let root = cstring(self.root.as_os_str())?;
+        let dot = cstring(".".as_ref())?;
+        // SAFETY: plain system calls with valid C strings.
+        unsafe {
+            check("chdir", libc::chdir(root.as_ptr()))?;
+            if libc::syscall(libc::SYS_pivot_root, dot.as_ptr(), dot.as_ptr()) == -1 {
+                return context("pivot_root", Err(io::Error::last_os_error()));
+            }
+            check("umount", libc::umount2(dot.as_ptr(), libc::MNT_DETACH))?;
+        }
+
+        if env::set_current_dir(&self.cwd).is_err() {
+            env::set_current_dir("/")?;
+        }
+        Ok(())
+    }
+
+    /// Binds a host path into the rootfs, read-only if requested.
+    fn bind(&self, bind: &BindMount) -> io::Result<()> {
+        let is_dir = fs::metadata(&bind.source)?.is_dir();
+        let target = self.mount_point(&bind.dest, is_dir)?;
+        mount(Some(bind.source.as_os_str()), &target, None, libc::MS_BIND | libc::MS_REC)?;
+        if bind.read_only {
+            remount_read_only(&target)?;
+        }
+        Ok(())
+    }
+
+    /// Resolves a path of the sandbox to the host path inside the rootfs and
+    /// creates it as an empty directory or file if it is missing.
+    ///
+    /// Symlinks are followed as if the rootfs was `/`, so an absolute link
+    /// of the rootfs never leads to a mount over a path of the host.
+    fn mount_point(&self, dest: &Path, is_dir: bool) -> io::Result<PathBuf> {
+        let target = resolve_in_root(&self.root, dest);
+        match is_dir {
+            true => fs::create_dir_all(&target)?,
+            false => {
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                OpenOptions::new().write(true).create(true).truncate(false).open(&target)?;
+            }
+        }
+        Ok(target)
+    }
+
+    /// Runs the command of the session, looking it up in `SANDBOX_PATH`.
+    ///
+    /// # Returns
+    /// The error of the last attempt; on success it does not return.
+    fn exec(&self) -> io::Error {
+        let mut argv: Vec<*const libc::c_char> = self.argv.iter().map(|a| a.as_ptr()).collect();
+        let mut envp: Vec<*const libc::c_char> = self.envp.iter().map(|v| v.as_ptr()).collect();
+        argv.push(ptr::null());
+        envp.push(ptr::null());
+
+        let program = self.argv[0].to_string_lossy();
+        let mut error = io::Error::from(io::ErrorKind::NotFound);
+        for dir in SANDBOX_PATH.split(':') {
+            let Ok(path) = CString::new(format!("{dir}/{program}")) else {
+                break;
+            };
+            // SAFETY: argv and envp are arrays of valid C strings ended by a null pointer.
+            unsafe {
+                libc::execve(path.as_ptr(), argv.as_ptr(), envp.as_ptr());
+            }
+            error = io::Error::last_os_error();
+            if !matches!(error.raw_os_error(), Some(libc::ENOENT | libc::ENOTDIR)) {
+                break;
+            }
+        }
+        io::Error::new(error.kind(), format!("{program}: {error}"))
+    }
+}
+
+/// Resolves `path` inside `root`, following symlinks as if `root` was `/`.
+///
+/// # Example
+/// ```
+/// // With /my/rootfs/var/run -> ../run:
+/// assert_eq!(resolve_in_root(Path::new("/my/rootfs"), Path::new("/var/run/dbus")), PathBuf::from("/my/rootfs/run/dbus"));
+/// ```
+fn resolve_in_root(root: &Path, path: &Path) -> PathBuf {
+    fn push_components(pending: &mut Vec<OsString>, path: &Path) {
+        for c in path.components().rev() {
+            match c {
+                Component::Normal(name) => pending.push(name.to_os_string()),
+                Component::ParentDir => pending.push("..".into()),
+                _ => {}
+            }
+        }
+    }
+
+    let mut pending = Vec::new();
+    push_components(&mut pending, path);
+    let mut resolved = PathBuf::new();
+    let mut links = 0;
+
+    while let Some(name) = pending.pop() {
+        if name == ".." {
+            resolved.pop();
+            continue;
+        }
+        let candidate = resolved.join(&name);
+        match fs::read_link(root.join(&candidate)) {
+            Ok(target) if links < MAX_SYMLINKS => {
+                links += 1;
+                if target.is_absolute() {
+                    resolved = PathBuf::new();
+                }
+                push_components(&mut pending, &target);
+            }
+            _ => resolved = candidate,
+        }
+    }
+    root.join(resolved)
+}
+
+/// Remounts a bind mount read-only, keeping the flags the kernel does not let a user namespace clear.
+fn remount_read_only(target: &Path) -> io::Result<()> {
+    let path = cstring(target.as_os_str())?;
+    // SAFETY: statvfs only writes to the zeroed struct.
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    unsafe {
+        check("statvfs", libc::statvfs(path.as_ptr(), &mut stat))?;
+    }
+
+    let kept = [
+        (libc::ST_NOSUID, libc::MS_NOSUID),
+        (libc::ST_NODEV, libc::MS_NODEV),
+        (libc::ST_NOEXEC, libc::MS_NOEXEC),
+        (libc::ST_NOATIME, libc::MS_NOATIME),
+        (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+        (libc::ST_RELATIME, libc::MS_RELATIME),
+    ]
+    .iter()
+    .filter(|(st, _)| stat.f_flag & st != 0)
+    .fold(0, |flags, (_, ms)| flags | ms);
+
+    mount(None, target, None, libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY | kept)
+}
+
+/// Calls `mount(2)`.
+fn mount(source: Option<&OsStr>, target: &Path, fstype: Option<&str>, flags: libc::c_ulong) -> io::Result<()> {
+    let source = source.map(cstring).transpose()?;
+    let target = cstring(target.as_os_str())?;
+    let fstype = fstype.map(|f| cstring(f.as_ref())).transpose()?;
+    let ptr_of = |s: &Option<CString>| s.as_ref().map_or(ptr::null(), |s| s.as_ptr());
+
+    // SAFETY: every pointer is a valid C string or null.
+    let ret = unsafe { libc::mount(ptr_of(&source), target.as_ptr(), ptr_of(&fstype), flags, ptr::null()) };
+    check("mount", ret)
+}
+
+/// Waits for a child and returns its exit code, `128 + signal` if it was killed.
+fn wait(pid: libc::pid_t) -> io::Result<i32> {
+    let mut status = 0;
+    // SAFETY: waits for a child of this process.
+    while unsafe { libc::waitpid(pid, &mut status, 0) } == -1 {
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    Ok(match libc::WIFEXITED(status) {
+        true => libc::WEXITSTATUS(status),
+        false => 128 + libc::WTERMSIG(status),
+    })
+}
+
+/// Checks that the process runs a single thread, so that forking it is safe.
+fn is_single_threaded() -> bool {
+    fs::read_dir("/proc/self/task").is_ok_and(|tasks| tasks.count() == 1)
+}
+
+/// Converts a string of the OS to a C string.
+fn cstring(s: &OsStr) -> io::Result<CString> {
+    CString::new(s.as_bytes()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("{s:?} contains a NUL byte")))
+}
+
+/// Turns the `-1` returned by a failed system call into an error naming it.
+fn check(call: &str, ret: libc::c_int) -> io::Result<()> {
+    match ret {
+        -1 => context(call, Err(io::Error::last_os_error())),
+        _ => Ok(()),
+    }
+}
+
+/// Prefixes an error with what was being done.
+fn context<T>(what: &str, result: io::Result<T>) -> io::Result<T> {
+    result.map_err(|e| io::Error::new(e.kind(), format!("{what}: {e}")))
+}
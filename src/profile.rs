@@ -0,0 +1,283 @@
+use crate::registry::{Registry, RootfsEntry};
+use crate::settings::Settings;
+use crate::setup::Setup;
+use crate::snapshot::Snapshot;
+use crate::utils;
+
+use indicatif::HumanBytes;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+
+pub struct Profile {
+    name: String,
+    remaining_args: Vec<String>,
+}
+
+impl Profile {
+    pub fn new(name: String, remaining_args: Vec<String>) -> Self {
+        Profile {
+            name,
+            remaining_args,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<_> = self.remaining_args.clone().into();
+
+        match args.pop_front().as_deref() {
+            Some("list") | Some("ls") => self.list(),
+            Some("create") => {
+                let env = self.take_name(&mut args, "create")?;
+                self.create(env, args.into())
+            }
+            Some("rm") | Some("remove") => {
+                let env = self.take_name(&mut args, "rm")?;
+                let (mut yes, mut keep_files, mut force) = (false, false, false);
+                while let Some(arg) = args.pop_front() {
+                    match arg.as_str() {
+                        "-y" | "--yes" => yes = true,
+                        "--keep-files" => keep_files = true,
+                        "--force" => force = true,
+                        _ => {
+                            return Err(format!("{c}: env: invalid argument '{arg}'\nUse '{c} --help' to see available options.", c = self.name).into())
+                        }
+                    }
+                }
+                self.remove(env, yes, keep_files, force)
+            }
+            Some("clone") => {
+                let src = self.take_name(&mut args, "clone")?;
+                let dst = self.take_name(&mut args, "clone")?;
+                self.clone_env(src, dst)
+            }
+            Some(other) => Err(format!(
+                "{c}: env: invalid argument '{other}'\nUse '{c} --help' to see available options.",
+                c = self.name
+            )
+            .into()),
+            None => Err(format!(
+                "{c}: env: no parameter specified\nUse '{c} --help' to see available options.",
+                c = self.name
+            )
+            .into()),
+        }
+    }
+
+    /// Prints the registered environments with their size and creation date.
+    fn list(&self) -> Result<(), Box<dyn Error>> {
+        let registry = Registry::load();
+        if registry.rootfs.is_empty() {
+            println!("No environment registered.\nUse '{} env create <NAME>' to create one.", self.name);
+            return Ok(());
+        }
+
+        let default_backend = Settings::load_or_create().cmd_rootfs;
+        let mut rows = vec![[
+            "NAME".to_string(),
+            "RELEASE".to_string(),
+            "VERSION".to_string(),
+            "ARCH".to_string(),
+            "BACKEND".to_string(),
+            "SIZE".to_string(),
+            "CREATED".to_string(),
+            "PATH".to_string(),
+        ]];
+
+        for e in &registry.rootfs {
+            let size = match Path::new(&e.path).is_dir() {
+                true => HumanBytes(utils::dir_size(Path::new(&e.path))).to_string(),
+                false => "missing".to_string(),
+            };
+            let backend = match e.backend.is_empty() {
+                true => format!("({default_backend})"),
+                false => e.backend.clone(),
+            };
+            rows.push([
+                e.name.clone(),
+                e.release.clone(),
+                e.version.clone(),
+                e.arch.clone(),
+                backend,
+                size,
+                utils::format_date(e.created),
+                e.path.clone(),
+            ]);
+        }
+
+        let widths: Vec<usize> = (0..8)
+            .map(|i| rows.iter().map(|r| r[i].chars().count()).max().unwrap_or(0))
+            .collect();
+
+        println!("{}", utils::separator_line());
+        for (n, row) in rows.iter().enumerate() {
+            let line: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(v, w)| format!("{v:<w$}"))
+                .collect();
+            println!("{}", line.join("  ").trim_end());
+            if n == 0 {
+                println!("{}", utils::separator_line());
+            }
+        }
+        println!("{}", utils::separator_line());
+        Ok(())
+    }
+
+    /// Creates a new environment by running `setup` on its directory.
+    fn create(&self, env: String, setup_args: Vec<String>) -> Result<(), Box<dyn Error>> {
+        if Registry::load().find(&env).is_some() {
+            return Err(format!("{}: env: environment '{env}' already exists", self.name).into());
+        }
+
+        let mut args = vec!["--env".to_string(), env];
+        args.extend(setup_args);
+        Setup::new(self.name.clone(), args).run()
+    }
+
+    /// Removes an environment and its rootfs directory.
+    ///
+    /// The directory is only deleted after a confirmation, and only when it
+    /// is inside `envs_dir` or `rootfs_dir` of the settings. The snapshots of
+    /// the environment are deleted with it.
+    ///
+    /// # Arguments
+    /// * `env` - The name of the environment.
+    /// * `yes` - Delete the directory without asking for a confirmation.
+    /// * `keep_files` - Only unregister the environment, keeping its directory.
+    /// * `force` - Delete the directory even if it is outside `envs_dir` and `rootfs_dir`.
+    fn remove(&self, env: String, yes: bool, keep_files: bool, force: bool) -> Result<(), Box<dyn Error>> {
+        let mut registry = Registry::load();
+        let entry = registry
+            .find(&env)
+            .cloned()
+            .ok_or_else(|| format!("{}: env: environment '{env}' not found", self.name))?;
+        let path = Path::new(&entry.path);
+        let snapshots = Snapshot::snapshot_dir(&entry.path);
+        let targets: Vec<&Path> = [path, snapshots.as_path()].into_iter().filter(|p| p.exists()).collect();
+
+        if !keep_files && !targets.is_empty() {
+            if path.exists() && !force && !Self::is_managed(path) {
+                return Err(format!(
+                    "{}: env: refusing to delete '{}', it is not inside 'envs_dir' or 'rootfs_dir'.\nUse '--keep-files' to only unregister it, or '--force' to delete it anyway.",
+                    self.name, entry.path
+                )
+                .into());
+            }
+            if !yes && !self.confirm(&env, &targets)? {
+                println!("Aborted.");
+                return Ok(());
+            }
+            for target in &targets {
+                println!("Removing {}...", target.display());
+                fs::remove_dir_all(target)?;
+            }
+        }
+
+        registry.remove(&env);
+        registry.save()?;
+        match keep_files && path.exists() {
+            true => println!("Environment '{env}' removed, its files were kept in {}.", entry.path),
+            false => println!("Environment '{env}' removed."),
+        }
+        Ok(())
+    }
+
+    /// Checks whether a directory is inside the `envs_dir` or `rootfs_dir` of the settings.
+    fn is_managed(path: &Path) -> bool {
+        let sett = Settings::load_or_create();
+        let Ok(path) = path.canonicalize() else {
+            return false;
+        };
+        [sett.envs_dir.clone(), sett.set_rootfs()]
+            .iter()
+            .filter_map(|dir| Path::new(dir).canonicalize().ok())
+            .any(|dir| path.starts_with(dir))
+    }
+
+    /// Asks the user to confirm the deletion of the directories of an environment.
+    ///
+    /// # Returns
+    /// * `Ok(true)` if the user answered yes.
+    /// * `Err` if the standard input is not a terminal.
+    fn confirm(&self, env: &str, dirs: &[&Path]) -> Result<bool, Box<dyn Error>> {
+        let dirs: Vec<String> = dirs.iter().map(|d| d.display().to_string()).collect();
+        if !io::stdin().is_terminal() {
+            return Err(format!(
+                "{}: env: removing '{env}' deletes {}, use '--yes' to confirm or '--keep-files' to keep it.",
+                self.name,
+                dirs.join(" and ")
+            )
+            .into());
+        }
+
+        let size: u64 = dirs.iter().map(|d| utils::dir_size(Path::new(d))).sum();
+        print!(
+            "Remove environment '{env}' and delete {} ({})? [y/N] ",
+            dirs.join(" and "),
+            HumanBytes(size)
+        );
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+    }
+
+    /// Copies an environment into a new one.
+    fn clone_env(&self, src: String, dst: String) -> Result<(), Box<dyn Error>> {
+        let mut registry = Registry::load();
+        let source = registry
+            .find(&src)
+            .cloned()
+            .ok_or_else(|| format!("{}: env: environment '{src}' not found", self.name))?;
+
+        if registry.find(&dst).is_some() {
+            return Err(format!("{}: env: environment '{dst}' already exists", self.name).into());
+        }
+
+        let dest_path = format!("{}/{dst}", Settings::load_or_create().envs_dir);
+        if Path::new(&dest_path).exists() {
+            return Err(format!("{}: env: directory '{dest_path}' already exists", self.name).into());
+        }
+
+        println!("Cloning {} to {dest_path}...", source.path);
+        if let Some(parent) = Path::new(&dest_path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        utils::copy_tree(Path::new(&source.path), Path::new(&dest_path))?;
+
+        let mut entry = RootfsEntry::new(dst.clone(), dest_path);
+        entry.arch = source.arch;
+        entry.release = source.release;
+        entry.version = source.version;
+        entry.backend = source.backend;
+        entry.repositories = source.repositories;
+        entry.binds = source.binds;
+        entry.env = source.env;
+        entry.packages = source.packages;
+        entry.provisioned = source.provisioned;
+        registry.upsert(entry);
+        registry.save()?;
+
+        println!("Environment '{src}' cloned to '{dst}'.");
+        Ok(())
+    }
+
+    /// Takes the next environment name from the arguments.
+    fn take_name(&self, args: &mut VecDeque<String>, sub: &str) -> Result<String, Box<dyn Error>> {
+        match args.pop_front() {
+            Some(n) if !n.starts_with('-') => match Registry::check_name(&n) {
+                Ok(()) => Ok(n),
+                Err(e) => Err(format!("{}: env: {e}", self.name).into()),
+            },
+            _ => Err(format!(
+                "{c}: env: {sub} requires a <name> as argument.\nUsage: {c} env {sub} <name>",
+                c = self.name
+            )
+            .into()),
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use crate::command::Command;
+use crate::sandbox::Mount;
+use crate::utils;
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+/// Directory of the rootfs where the provisioning scripts are mounted.
+const PROVISION_DIR: &str = "var/lib/alpack/provision";
+
+/// A provisioning step run inside a new rootfs after setup.
+///
+/// Steps are listed as `[[provision]]` entries in `config.toml`:
+///
+/// ```toml
+/// [[provision]]
+/// script = "/home/user/provision/ca-certs.sh"
+///
+/// [[provision]]
+/// command = "mkdir -p ~/.config"
+/// user = true
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ProvisionStep {
+    /// A script on the host, run with its directory mounted in the rootfs.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub script: String,
+    /// An inline shell command.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub command: String,
+    /// Run as the user instead of root.
+    pub user: bool,
+}
+
+impl ProvisionStep {
+    /// Returns the name of the step shown in the reports.
+    fn label(&self) -> String {
+        match self.script.is_empty() {
+            true => self.command.clone(),
+            false => self.script.clone(),
+        }
+    }
+}
+
+/// Runs the provisioning steps inside a rootfs, in order.
+///
+/// Scripts are checked before anything runs. Every step runs through
+/// `Command::run`; the first step exiting with a non-zero code stops the
+/// provisioning and a report of the executed, failed and skipped steps is
+/// printed.
+///
+/// # Arguments
+/// * `rootfs` - The rootfs directory.
+/// * `steps` - The steps to run.
+///
+/// # Returns
+/// * `Ok(())` if every step succeeded.
+/// * `Err` if a script is missing or a step failed.
+///
+/// # Example
+/// ```
+/// provision::run("/home/user/.ALPack", &settings.provision)?;
+/// ```
+pub fn run(rootfs: &str, steps: &[ProvisionStep]) -> Result<(), Box<dyn Error>> {
+    if steps.is_empty() {
+        return Ok(());
+    }
+
+    for step in steps {
+        if step.script.is_empty() == step.command.is_empty() {
+            return Err(format!("Invalid provisioning step '{}': set either 'script' or 'command'", step.label()).into());
+        }
+        if !step.script.is_empty() && !Path::new(&step.script).is_file() {
+            return Err(format!("Provisioning script '{}' not found", step.script).into());
+        }
+    }
+
+    let total = steps.len();
+    let mut failure = None;
+
+    for (i, step) in steps.iter().enumerate() {
+        let who = if step.user { "user" } else { "root" };
+        println!("\x1b[1;32m==>\x1b[0m Provisioning [{}/{total}] {} ({who})", i + 1, step.label());
+
+        let (bind, cmd) = match step.script.is_empty() {
+            true => (Vec::new(), step.command.clone()),
+            false => {
+                let script = fs::canonicalize(&step.script)?;
+                let dir = script.parent().unwrap().to_path_buf();
+                let file = script.file_name().unwrap().to_string_lossy().to_string();
+                let mount = format!("/{PROVISION_DIR}/{:02}", i + 1);
+
+                let executable = fs::metadata(&script)?.permissions().mode() & 0o111 != 0;
+                let file = utils::shell_quote(&format!("./{file}"));
+                let cmd = match executable {
+                    true => format!("cd {mount} && {file}"),
+                    false => format!("cd {mount} && sh {file}"),
+                };
+                (vec![Mount::new(dir, mount, true).into()], cmd)
+            }
+        };
+
+        let code = Command::run(rootfs.to_string(), bind, Some(cmd), !step.user, true, false)?;
+        if code != 0 {
+            failure = Some((i, code));
+            break;
+        }
+    }
+
+    cleanup(rootfs, total);
+
+    let Some((failed, code)) = failure else {
+        println!("Provisioning completed: {total} step(s) succeeded.");
+        return Ok(());
+    };
+
+    eprintln!("Provisioning report:");
+    for (i, step) in steps.iter().enumerate() {
+        let status = match i.cmp(&failed) {
+            std::cmp::Ordering::Less => "\x1b[1;32mok\x1b[0m     ".to_string(),
+            std::cmp::Ordering::Equal => "\x1b[1;31mfailed\x1b[0m ".to_string(),
+            std::cmp::Ordering::Greater => "skipped".to_string(),
+        };
+        eprintln!("  {status} [{}/{total}] {}", i + 1, step.label());
+    }
+
+    Err(format!(
+        "Provisioning step {} failed with exit code {code}: {}",
+        failed + 1,
+        steps[failed].label()
+    )
+    .into())
+}
+
+/// Removes the mount points left in the rootfs by the provisioning scripts.
+fn cleanup(rootfs: &str, total: usize) {
+    let dir = Path::new(rootfs).join(PROVISION_DIR);
+    for i in 1..=total {
+        let _ = fs::remove_dir(dir.join(format!("{i:02}")));
+    }
+    let _ = fs::remove_dir(&dir);
+    if let Some(parent) = dir.parent() {
+        let _ = fs::remove_dir(parent);
+    }
+}
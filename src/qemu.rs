@@ -0,0 +1,146 @@
+use crate::registry::Registry;
+use crate::utils;
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+use which::which;
+
+/// Architectures for which Alpine Linux publishes a minirootfs.
+pub const ALPINE_ARCHES: [&str; 9] = [
+    "x86_64", "x86", "aarch64", "armhf", "armv7", "ppc64le", "s390x", "riscv64", "loongarch64",
+];
+
+/// Returns the qemu-user name of an Alpine architecture.
+///
+/// # Example
+/// ```
+/// assert_eq!(qemu_arch("armv7"), Some("arm"));
+/// ```
+pub fn qemu_arch(arch: &str) -> Option<&'static str> {
+    match arch {
+        "x86_64" => Some("x86_64"),
+        "x86" => Some("i386"),
+        "aarch64" => Some("aarch64"),
+        "armhf" | "armv7" => Some("arm"),
+        "ppc64le" => Some("ppc64le"),
+        "s390x" => Some("s390x"),
+        "riscv64" => Some("riscv64"),
+        "loongarch64" => Some("loongarch64"),
+        _ => None,
+    }
+}
+
+/// Returns the Alpine name of the host architecture.
+fn host_arch() -> &'static str {
+    match env::consts::ARCH {
+        "arm" => "armv7",
+        "powerpc64" => "ppc64le",
+        other => other,
+    }
+}
+
+/// Checks whether binaries of `arch` need qemu-user to run on this host.
+///
+/// x86 binaries run natively on x86_64 hosts.
+pub fn needs_emulation(arch: &str) -> bool {
+    let host = host_arch();
+    !arch.is_empty() && arch != host && !(host == "x86_64" && arch == "x86")
+}
+
+/// Returns the architecture of a rootfs.
+///
+/// The architecture recorded in the registry is used first, then the
+/// `etc/apk/arch` file of the rootfs, then the architecture in use.
+///
+/// # Example
+/// ```
+/// let arch = rootfs_arch("/home/user/.ALPack");
+/// ```
+pub fn rootfs_arch(rootfs: &str) -> String {
+    Registry::load()
+        .find_by_path(rootfs)
+        .map(|e| e.arch.clone())
+        .filter(|a| !a.is_empty())
+        .or_else(|| {
+            fs::read_to_string(Path::new(rootfs).join("etc/apk/arch"))
+                .ok()
+                .map(|a| a.trim().to_string())
+                .filter(|a| !a.is_empty())
+        })
+        .unwrap_or_else(utils::get_arch)
+}
+
+/// Finds a qemu-user binary able to run `arch`.
+///
+/// `ALPACK_QEMU` overrides the lookup; otherwise `qemu-<arch>-static` and
+/// `qemu-<arch>` are searched in the `PATH` and in `~/.local/bin`. No binary
+/// is downloaded, qemu-user-static must be installed on the host.
+///
+/// # Returns
+/// * `Ok(PathBuf)` with the path to the qemu-user binary.
+/// * `Err` if the architecture is unknown or no binary could be found.
+///
+/// # Example
+/// ```
+/// let qemu = find_qemu("aarch64")?; // e.g. /usr/bin/qemu-aarch64-static
+/// ```
+pub fn find_qemu(arch: &str) -> Result<PathBuf, Box<dyn Error>> {
+    if let Ok(path) = env::var("ALPACK_QEMU") {
+        return match Path::new(&path).is_file() {
+            true => Ok(PathBuf::from(path)),
+            false => Err(format!("ALPACK_QEMU: '{path}' not found").into()),
+        };
+    }
+
+    let qemu = qemu_arch(arch).ok_or_else(|| format!("Unsupported architecture '{arch}'"))?;
+    if let Ok(path) = which(format!("qemu-{qemu}-static")) {
+        return Ok(path);
+    }
+
+    utils::find_rootfs_command(&format!("qemu-{qemu}")).ok_or_else(|| {
+        format!(
+            "qemu-{qemu} is required to run {arch} binaries.\n\
+             Install qemu-user-static on the host (e.g. 'apt install qemu-user-static'), or set ALPACK_QEMU."
+        )
+        .into()
+    })
+}
+
+/// Returns the interpreter that must be visible inside a bwrap sandbox.
+///
+/// bwrap relies on the binfmt_misc handler registered for the architecture.
+/// Handlers registered with the `F` (fix binary) flag keep the interpreter
+/// open and need nothing, otherwise the interpreter is looked up inside the
+/// sandbox and the qemu binary must be bound at its path.
+///
+/// # Returns
+/// * `Ok(None)` if nothing needs to be bound.
+/// * `Ok(Some(String))` with the interpreter path expected by the kernel.
+/// * `Err` if no binfmt_misc handler is registered for the architecture.
+pub fn binfmt_interpreter(arch: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let qemu = qemu_arch(arch).ok_or_else(|| format!("Unsupported architecture '{arch}'"))?;
+    let entry = format!("/proc/sys/fs/binfmt_misc/qemu-{qemu}");
+
+    let content = fs::read_to_string(&entry).unwrap_or_default();
+    if !content.lines().any(|l| l.trim() == "enabled") {
+        return Err(format!(
+            "No binfmt_misc handler registered for {arch} ({entry}).\n\
+             Install qemu-user-static/binfmt support on the host, or use proot to run this rootfs."
+        )
+        .into());
+    }
+
+    let field = |name: &str| {
+        content
+            .lines()
+            .find_map(|l| l.strip_prefix(name))
+            .map(|v| v.trim().to_string())
+            .unwrap_or_default()
+    };
+
+    match field("flags:").contains('F') {
+        true => Ok(None),
+        false => Ok(Some(field("interpreter"))).map(|i| i.filter(|i| !i.is_empty())),
+    }
+}
@@ -0,0 +1,431 @@
+use crate::backend;
+use crate::command::Command;
+use crate::mirror::Mirror;
+use crate::provision::{self, ProvisionStep};
+use crate::registry::{Registry, Repository, RootfsEntry};
+use crate::repo::Repo;
+use crate::settings::Settings;
+use crate::setup::Setup;
+use crate::upgrade::Upgrade;
+use crate::utils::{self, _parse_key_value};
+use crate::{parse_key_value, qemu, verify};
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Recipe read by `build-env` when no file is given.
+const DEFAULT_RECIPE: &str = "ALPack.toml";
+
+pub struct Recipe {
+    name: String,
+    remaining_args: Vec<String>,
+}
+
+/// An environment described in a recipe file, usually `ALPack.toml`.
+///
+/// ```toml
+/// name = "web"
+/// release = "v3.20"
+/// packages = ["build-base", "git"]
+/// binds = ["./src:/src"]
+///
+/// [env]
+/// CC = "gcc"
+///
+/// [[repositories]]
+/// url = "https://pkgs.example.org/alpine"
+/// tag = "internal"
+/// key = "./keys/internal.rsa.pub"
+///
+/// [[provision]]
+/// command = "adduser -D builder"
+/// ```
+///
+/// Relative paths are resolved from the directory of the recipe.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RecipeFile {
+    name: String,
+    release: String,
+    arch: String,
+    mirror: String,
+    backend: String,
+    packages: Vec<String>,
+    repositories: Vec<Repository>,
+    provision: Vec<ProvisionStep>,
+    binds: Vec<String>,
+    env: BTreeMap<String, String>,
+}
+
+impl Recipe {
+    pub fn new(name: String, remaining_args: Vec<String>) -> Self {
+        Recipe {
+            name,
+            remaining_args,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<_> = self.remaining_args.clone().into();
+        let (mut file, mut rootfs_dir, mut env_name): (Option<String>, Option<String>, Option<String>) = (None, None, None);
+
+        while let Some(arg) = args.pop_front() {
+            match arg.as_str() {
+                a if a.starts_with("--file=") => {
+                    file = parse_key_value!("build-env", "file", arg)?;
+                }
+                "-f" | "--file" => {
+                    file = parse_key_value!("build-env", "file", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with("--env=") => {
+                    env_name = parse_key_value!("build-env", "name", arg)?;
+                }
+                "--env" => {
+                    env_name = parse_key_value!("build-env", "name", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with("--rootfs=") => {
+                    rootfs_dir = parse_key_value!("build-env", "directory", arg)?;
+                }
+                "-R" | "--rootfs" => {
+                    rootfs_dir = parse_key_value!("build-env", "directory", arg, args.pop_front().unwrap_or_default())?;
+                }
+                _ => {
+                    return Err(format!("{c}: build-env: invalid argument '{arg}'\nUse '{c} --help' to see available options.", c = self.name).into())
+                }
+            }
+        }
+
+        let file = file.unwrap_or_else(|| DEFAULT_RECIPE.to_string());
+        let recipe = self.load(&file)?;
+
+        let env_name = env_name.or(Some(recipe.name.clone()).filter(|n| !n.is_empty()));
+        if let Some(Err(e)) = env_name.as_deref().map(Registry::check_name) {
+            return Err(format!("{}: build-env: {e}", self.name).into());
+        }
+        let rootfs = match (rootfs_dir, &env_name) {
+            (Some(dir), _) => Registry::normalize(&dir),
+            (None, Some(env)) => match Registry::load().find(env) {
+                Some(entry) => entry.path.clone(),
+                None => format!("{}/{env}", Settings::load_or_create().envs_dir),
+            },
+            (None, None) => Registry::normalize(&Settings::load_or_create().set_rootfs()),
+        };
+
+        let mut changes = 0;
+        match Path::new(&rootfs).is_dir() {
+            true => changes += self.converge_release(&rootfs, &recipe)?,
+            false => {
+                self.create(&rootfs, env_name.as_deref(), &recipe)?;
+                changes += 1;
+            }
+        }
+
+        let registry = Registry::load();
+        let mut entry = registry.find_by_path(&rootfs).cloned().unwrap_or_else(|| {
+            let name = env_name.clone().unwrap_or_else(|| registry.name_for_path(&rootfs));
+            RootfsEntry::new(name, rootfs.clone())
+        });
+
+        if !recipe.backend.is_empty() && entry.backend != recipe.backend {
+            Self::step(&format!("Using the {} backend", recipe.backend));
+            entry.backend = recipe.backend.clone();
+            changes += 1;
+        }
+        if entry.binds != recipe.binds {
+            Self::step(&format!("Setting {} bind(s)", recipe.binds.len()));
+            entry.binds = recipe.binds.clone();
+            changes += 1;
+        }
+        if entry.env != recipe.env {
+            Self::step(&format!("Setting {} environment variable(s)", recipe.env.len()));
+            entry.env = recipe.env.clone();
+            changes += 1;
+        }
+        Self::save(&entry)?;
+
+        if self.converge_repositories(&rootfs, &recipe, &mut entry)? {
+            Self::save(&entry)?;
+            self.apk(&rootfs, "apk update".to_string())?;
+            changes += 1;
+        }
+
+        changes += self.converge_packages(&rootfs, &recipe, &mut entry)?;
+        Self::save(&entry)?;
+
+        let digest = Self::provision_digest(&recipe.provision)?;
+        if entry.provisioned != digest {
+            provision::run(&rootfs, &recipe.provision)?;
+            entry.provisioned = digest;
+            Self::save(&entry)?;
+            changes += usize::from(!recipe.provision.is_empty());
+        }
+
+        match changes {
+            0 => println!("Environment '{}' is up to date.", entry.name),
+            n => println!("Environment '{}' converged to {file} with {n} change(s): {rootfs}", entry.name),
+        }
+        Ok(())
+    }
+
+    /// Reads and validates a recipe, resolving its relative paths.
+    ///
+    /// # Returns
+    /// * `Ok(RecipeFile)` with the host paths made absolute.
+    /// * `Err` if the file cannot be read or holds an invalid value.
+    fn load(&self, path: &str) -> Result<RecipeFile, Box<dyn Error>> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("{}: build-env: cannot read recipe '{path}': {e}", self.name))?;
+        let mut recipe: RecipeFile = toml::from_str(&content)
+            .map_err(|e| format!("{}: build-env: invalid recipe '{path}': {e}", self.name))?;
+        let base = fs::canonicalize(path)?.parent().unwrap().to_path_buf();
+        let invalid = |what: String| format!("{}: build-env: {path}: {what}", self.name);
+
+        if !recipe.name.is_empty() {
+            Registry::check_name(&recipe.name).map_err(invalid)?;
+        }
+        if !recipe.release.is_empty() && !Mirror::is_valid_release(&recipe.release) {
+            return Err(invalid(format!("invalid release '{}', expected 'latest-stable', 'edge' or 'vX.Y'", recipe.release)).into());
+        }
+        if !recipe.arch.is_empty() && !qemu::ALPINE_ARCHES.contains(&recipe.arch.as_str()) {
+            return Err(invalid(format!("invalid architecture '{}', expected one of: {}", recipe.arch, qemu::ALPINE_ARCHES.join(", "))).into());
+        }
+        if !recipe.backend.is_empty() && !backend::BACKENDS.contains(&recipe.backend.as_str()) {
+            return Err(invalid(format!("invalid backend '{}', expected one of: {}", recipe.backend, backend::BACKENDS.join(", "))).into());
+        }
+        if let Some(p) = recipe.packages.iter().find(|p| p.is_empty() || p.contains(char::is_whitespace)) {
+            return Err(invalid(format!("invalid package '{p}', list one package per entry")).into());
+        }
+        if let Some(k) = recipe.env.keys().find(|k| {
+            k.is_empty() || k.starts_with(|c: char| c.is_ascii_digit()) || !k.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }) {
+            return Err(invalid(format!("invalid variable name '{k}'")).into());
+        }
+
+        for bind in recipe.binds.iter_mut() {
+            let (host, guest) = bind.split_once(':').unwrap_or((bind.as_str(), bind.as_str()));
+            let host = fs::canonicalize(Self::resolve(&base, host))
+                .map_err(|e| invalid(format!("bind source '{host}': {e}")))?
+                .display()
+                .to_string();
+            if !guest.starts_with('/') || host.contains(':') {
+                return Err(invalid(format!("invalid bind '{bind}', expected 'HOST:GUEST' with an absolute GUEST and no ':' in HOST")).into());
+            }
+            *bind = format!("{host}:{guest}");
+        }
+
+        for step in recipe.provision.iter_mut().filter(|s| !s.script.is_empty()) {
+            step.script = Self::resolve(&base, &step.script);
+        }
+
+        for repo in recipe.repositories.iter_mut() {
+            let url = utils::local_path(&repo.url).unwrap_or(&repo.url).to_string();
+            repo.url = Self::resolve(&base, &url).trim_end_matches('/').to_string();
+            repo.tag = repo.tag.trim_start_matches('@').to_string();
+            if !repo.key.is_empty() {
+                repo.key = Self::resolve(&base, &repo.key);
+            }
+        }
+        Ok(recipe)
+    }
+
+    /// Creates the rootfs by running `setup`, with provisioning and packages left to the recipe.
+    fn create(&self, rootfs: &str, env_name: Option<&str>, recipe: &RecipeFile) -> Result<(), Box<dyn Error>> {
+        Self::step(&format!("Creating the rootfs in {rootfs}"));
+
+        let mut args = vec!["--rootfs".to_string(), rootfs.to_string(), "--minimal".to_string(), "--no-provision".to_string()];
+        if let Some(env) = env_name {
+            args.extend(["--env".to_string(), env.to_string()]);
+        }
+        for (option, value) in [
+            ("--release", &recipe.release),
+            ("--arch", &recipe.arch),
+            ("--mirror", &recipe.mirror),
+            ("--backend", &recipe.backend),
+        ] {
+            if !value.is_empty() {
+                args.extend([option.to_string(), value.clone()]);
+            }
+        }
+        Setup::new(self.name.clone(), args).run()
+    }
+
+    /// Checks the architecture of an existing rootfs and upgrades it to the release of the recipe.
+    ///
+    /// # Returns
+    /// * `Ok(usize)` with the number of changes made (0 or 1).
+    /// * `Err` if the architecture differs or the upgrade failed.
+    fn converge_release(&self, rootfs: &str, recipe: &RecipeFile) -> Result<usize, Box<dyn Error>> {
+        let arch = qemu::rootfs_arch(rootfs);
+        if !recipe.arch.is_empty() && recipe.arch != arch {
+            return Err(format!(
+                "{}: build-env: the rootfs {rootfs} is {arch}, not {}\nRemove it to create it again with the recipe.",
+                self.name, recipe.arch
+            )
+            .into());
+        }
+
+        let release = Registry::load()
+            .find_by_path(rootfs)
+            .map(|e| e.release.clone())
+            .unwrap_or_default();
+        if recipe.release.is_empty() || recipe.release == release {
+            return Ok(0);
+        }
+
+        Self::step(&format!("Upgrading the rootfs to {}", recipe.release));
+        let mut args = vec!["--to".to_string(), recipe.release.clone(), "--rootfs".to_string(), rootfs.to_string()];
+        if !recipe.mirror.is_empty() {
+            args.extend(["--mirror".to_string(), recipe.mirror.clone()]);
+        }
+        Upgrade::new(self.name.clone(), args).run()?;
+        Ok(1)
+    }
+
+    /// Writes the mirror and repositories of the recipe to `/etc/apk/repositories`.
+    ///
+    /// Signing keys are installed when missing, and the keys of removed
+    /// repositories are deleted when no other repository uses them.
+    ///
+    /// # Returns
+    /// * `Ok(true)` if the repositories changed.
+    fn converge_repositories(&self, rootfs: &str, recipe: &RecipeFile, entry: &mut RootfsEntry) -> Result<bool, Box<dyn Error>> {
+        let keys_dir = Path::new(rootfs).join("etc/apk/keys");
+        let mut repos = Vec::new();
+        for r in &recipe.repositories {
+            let mut repo = Repository { url: r.url.clone(), tag: r.tag.clone(), key: String::new() };
+            if !r.key.is_empty() {
+                repo.key = r.key.trim_end_matches('/').rsplit('/').next().unwrap_or_default().to_string();
+                if !keys_dir.join(&repo.key).is_file() {
+                    repo.key = Repo::store_key(&r.key)?;
+                    Repo::install_keys(rootfs, std::slice::from_ref(&repo))?;
+                }
+            }
+            repos.push(repo);
+        }
+
+        let release = Some(recipe.release.clone())
+            .filter(|r| !r.is_empty())
+            .or(Some(entry.release.clone()).filter(|r| !r.is_empty()));
+        let mut mirror = Mirror::new(Some(recipe.mirror.clone()), release);
+        mirror.set_arch(Some(qemu::rootfs_arch(rootfs)));
+        mirror.run()?;
+        mirror.set_version(Some(entry.version.clone()).filter(|v| !v.is_empty()));
+        mirror.set_extra_repositories(repos.iter().map(|r| r.line()).collect());
+        let content = mirror.get_repository();
+
+        let repo_file = Path::new(rootfs).join("etc/apk/repositories");
+        let current = fs::read_to_string(&repo_file).unwrap_or_default();
+        let lines = |s: &str| -> Vec<String> {
+            s.lines()
+                .map(|l| l.trim().to_string())
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .collect()
+        };
+        if lines(&current) == lines(&content) && entry.repositories == repos {
+            return Ok(false);
+        }
+
+        Self::step("Updating the repositories");
+        fs::write(&repo_file, content)?;
+        for old in entry.repositories.iter().filter(|o| !o.key.is_empty()) {
+            if !repos.iter().any(|r| r.key == old.key) {
+                let _ = fs::remove_file(keys_dir.join(&old.key));
+            }
+        }
+        entry.repositories = repos;
+        Ok(true)
+    }
+
+    /// Installs the packages of the recipe missing from `/etc/apk/world`, and
+    /// removes the ones a previous recipe installed that are no longer listed.
+    ///
+    /// # Returns
+    /// * `Ok(usize)` with the number of apk runs (0 to 2).
+    fn converge_packages(&self, rootfs: &str, recipe: &RecipeFile, entry: &mut RootfsEntry) -> Result<usize, Box<dyn Error>> {
+        let world = fs::read_to_string(Path::new(rootfs).join("etc/apk/world")).unwrap_or_default();
+        let world: Vec<&str> = world.split_whitespace().collect();
+        let mut changes = 0;
+
+        let remove: Vec<&str> = entry
+            .packages
+            .iter()
+            .filter(|p| !recipe.packages.contains(p) && world.contains(&p.as_str()))
+            .map(String::as_str)
+            .collect();
+        if !remove.is_empty() {
+            Self::step(&format!("Removing {}", remove.join(" ")));
+            self.apk(rootfs, format!("apk del {}", remove.join(" ")))?;
+            changes += 1;
+        }
+
+        let install: Vec<&str> = recipe
+            .packages
+            .iter()
+            .filter(|p| !world.contains(&p.as_str()))
+            .map(String::as_str)
+            .collect();
+        if !install.is_empty() {
+            Self::step(&format!("Installing {}", install.join(" ")));
+            self.apk(rootfs, format!("apk add {}", install.join(" ")))?;
+            changes += 1;
+        }
+
+        entry.packages = recipe.packages.clone();
+        Ok(changes)
+    }
+
+    /// Runs an apk command as root inside the rootfs.
+    fn apk(&self, rootfs: &str, cmd: String) -> Result<(), Box<dyn Error>> {
+        let code = Command::run(rootfs.to_string(), Vec::new(), Some(cmd.clone()), true, true, false)?;
+        if code != 0 {
+            return Err(format!("{}: build-env: '{cmd}' exited with code {code}", self.name).into());
+        }
+        Ok(())
+    }
+
+    /// Returns the digest of the provisioning steps, including the content of their scripts.
+    ///
+    /// The steps run again only when this digest changes, so editing a
+    /// script or a command of the recipe is enough to provision again.
+    fn provision_digest(steps: &[ProvisionStep]) -> Result<String, Box<dyn Error>> {
+        if steps.is_empty() {
+            return Ok(String::new());
+        }
+
+        let mut hasher = Sha256::new();
+        for step in steps {
+            hasher.update(format!("{}\0{}\0{}\0", step.script, step.command, step.user));
+            if !step.script.is_empty() {
+                let script = fs::read(&step.script)
+                    .map_err(|e| format!("Cannot read provisioning script '{}': {e}", step.script))?;
+                hasher.update(script);
+            }
+        }
+        Ok(verify::hex(&hasher.finalize()))
+    }
+
+    /// Resolves a path of the recipe from its directory; URLs and absolute paths are kept.
+    fn resolve(base: &Path, path: &str) -> String {
+        match path.contains("://") || path.starts_with('/') {
+            true => path.to_string(),
+            false => base.join(path.trim_start_matches("./")).display().to_string(),
+        }
+    }
+
+    /// Records the entry in the registry.
+    fn save(entry: &RootfsEntry) -> Result<(), Box<dyn Error>> {
+        let mut registry = Registry::load();
+        registry.upsert(entry.clone());
+        registry.save()?;
+        Ok(())
+    }
+
+    /// Prints a convergence step.
+    fn step(msg: &str) {
+        println!("\x1b[1;32m==>\x1b[0m {msg}...");
+    }
+}
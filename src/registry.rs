@@ -0,0 +1,273 @@
+use crate::settings::Settings;
+use crate::utils;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{self, Path, PathBuf};
+use std::{fs, io};
+
+/// Metadata recorded for every rootfs created by ALPack.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RootfsEntry {
+    pub name: String,
+    pub path: String,
+    pub arch: String,
+    pub release: String,
+    pub version: String,
+    pub backend: String,
+    pub created: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub repositories: Vec<Repository>,
+    /// Host directories bound in every session, as `HOST:GUEST`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub binds: Vec<String>,
+    /// Variables set in every session.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Packages installed from the recipe by `build-env`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<String>,
+    /// Digest of the provisioning steps last run by `build-env`.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub provisioned: String,
+}
+
+/// An apk repository added to a rootfs with `repo add`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Repository {
+    pub url: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub tag: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub key: String,
+}
+
+impl Repository {
+    /// Returns the line written to `/etc/apk/repositories`.
+    ///
+    /// # Examples
+    /// ```
+    /// let repo = Repository { url: "https://dl-cdn.alpinelinux.org/alpine/edge/testing".into(), tag: "testing".into(), ..Default::default() };
+    /// assert_eq!(repo.line(), "@testing https://dl-cdn.alpinelinux.org/alpine/edge/testing");
+    /// ```
+    pub fn line(&self) -> String {
+        match self.tag.is_empty() {
+            true => self.url.clone(),
+            false => format!("@{} {}", self.tag, self.url),
+        }
+    }
+}
+
+/// Registry of the known rootfs trees, stored in `~/.config/ALPack/rootfs.toml`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Registry {
+    pub rootfs: Vec<RootfsEntry>,
+}
+
+impl RootfsEntry {
+    /// Creates a new entry for the rootfs at `path`, stamped with the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// let entry = RootfsEntry::new("web".to_string(), "/home/user/.ALPack-envs/web".to_string());
+    /// ```
+    pub fn new(name: String, path: String) -> Self {
+        RootfsEntry {
+            name,
+            path: Registry::normalize(&path),
+            created: utils::now(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Registry {
+    /// Loads the registry from disk, or returns an empty one if it doesn't exist or is invalid.
+    ///
+    /// # Examples
+    /// ```
+    /// let registry = Registry::load();
+    /// ```
+    pub fn load() -> Self {
+        let path = Self::path();
+        match fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content).unwrap_or_else(|_| {
+                eprintln!("\x1b[1;33mWarning\x1b[0m: Failed to parse rootfs registry '{}'.", path.display());
+                Registry::default()
+            }),
+            Err(_) => Registry::default(),
+        }
+    }
+
+    /// Saves the registry to `~/.config/ALPack/rootfs.toml`.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the file was successfully written.
+    /// - `Err` if the registry could not be serialized or written.
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let toml_data = toml::to_string_pretty(self).map_err(|e| io::Error::other(e.to_string()))?;
+        fs::write(path, toml_data)
+    }
+
+    /// Returns the entry registered under `name`.
+    pub fn find(&self, name: &str) -> Option<&RootfsEntry> {
+        self.rootfs.iter().find(|e| e.name == name)
+    }
+
+    /// Returns the entry registered for the rootfs at `path`.
+    pub fn find_by_path(&self, path: &str) -> Option<&RootfsEntry> {
+        let path = Self::normalize(path);
+        self.rootfs.iter().find(|e| e.path == path)
+    }
+
+    /// Adds an entry, replacing the one registered for the same path.
+    ///
+    /// The name and creation date of a replaced entry are kept.
+    ///
+    /// # Examples
+    /// ```
+    /// registry.upsert(RootfsEntry::new("web".into(), "/srv/web".into()));
+    /// registry.save()?;
+    /// ```
+    pub fn upsert(&mut self, mut entry: RootfsEntry) {
+        match self.rootfs.iter_mut().find(|e| e.path == entry.path) {
+            Some(old) => {
+                entry.name = old.name.clone();
+                entry.created = old.created;
+                *old = entry;
+            }
+            None => self.rootfs.push(entry),
+        }
+    }
+
+    /// Removes the entry registered under `name`.
+    pub fn remove(&mut self, name: &str) -> Option<RootfsEntry> {
+        let pos = self.rootfs.iter().position(|e| e.name == name)?;
+        Some(self.rootfs.remove(pos))
+    }
+
+    /// Returns a name not used by any entry, derived from `base`.
+    ///
+    /// # Examples
+    /// ```
+    /// let name = registry.unique_name("alpine"); // "alpine", "alpine-2", ...
+    /// ```
+    pub fn unique_name(&self, base: &str) -> String {
+        let mut name = base.to_string();
+        let mut n = 2;
+        while self.find(&name).is_some() {
+            name = format!("{base}-{n}");
+            n += 1;
+        }
+        name
+    }
+
+    /// Returns the name to record for a new rootfs at `path`.
+    ///
+    /// The configured rootfs directory is named `default`, any other path is
+    /// named after its last component, with the characters not allowed in a
+    /// name replaced by `-`.
+    pub fn name_for_path(&self, path: &str) -> String {
+        let sett = Settings::load_or_create();
+        if Self::normalize(path) == Self::normalize(&sett.set_rootfs()) {
+            return "default".to_string();
+        }
+        let base = Path::new(path)
+            .file_name()
+            .map(|n| {
+                n.to_string_lossy()
+                    .chars()
+                    .map(|c| if Self::is_name_char(c) { c } else { '-' })
+                    .collect::<String>()
+                    .trim_start_matches(['.', '-'])
+                    .to_string()
+            })
+            .filter(|n| !n.is_empty())
+            .unwrap_or_else(|| "rootfs".to_string());
+        self.unique_name(&base)
+    }
+
+    /// Checks whether `name` can be used as an environment name.
+    ///
+    /// Names are also directory names under `envs_dir`, so only ASCII letters,
+    /// digits, `.`, `_` and `-` are allowed, and a name cannot start with `.`
+    /// or `-`.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the name is valid.
+    /// - `Err(String)` explaining why it is not.
+    ///
+    /// # Examples
+    /// ```
+    /// assert!(Registry::check_name("web-1.0").is_ok());
+    /// assert!(Registry::check_name("..").is_err());
+    /// ```
+    pub fn check_name(name: &str) -> Result<(), String> {
+        match !name.is_empty() && !name.starts_with(['.', '-']) && name.chars().all(Self::is_name_char) {
+            true => Ok(()),
+            false => Err(format!(
+                "invalid name '{name}', only letters, digits, '.', '_' and '-' are allowed, and it cannot start with '.' or '-'"
+            )),
+        }
+    }
+
+    /// Checks whether `c` is allowed in an environment name.
+    fn is_name_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')
+    }
+
+    /// Resolves the rootfs path of a named environment.
+    ///
+    /// # Returns
+    /// - `Ok(String)` with the rootfs path.
+    /// - `Err` if no environment with this name is registered.
+    ///
+    /// # Examples
+    /// ```
+    /// let rootfs = Registry::resolve("web")?;
+    /// ```
+    pub fn resolve(name: &str) -> Result<String, Box<dyn Error>> {
+        match Self::load().find(name) {
+            Some(entry) => Ok(entry.path.clone()),
+            None => Err(format!("Environment '{name}' not found.\nUse 'env list' to see the available environments.").into()),
+        }
+    }
+
+    /// Returns an absolute form of `path`, used as the registry key.
+    pub fn normalize(path: &str) -> String {
+        path::absolute(path)
+            .unwrap_or_else(|_| PathBuf::from(path))
+            .display()
+            .to_string()
+            .trim_end_matches('/')
+            .to_string()
+    }
+
+    /// Returns the path of the registry file.
+    pub fn path() -> PathBuf {
+        Settings::config_dir().join("rootfs.toml")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_limited_to_safe_directory_names() {
+        for name in ["web", "web-1.0", "my_env", "A1"] {
+            assert!(Registry::check_name(name).is_ok(), "{name}");
+        }
+        for name in ["", ".", "..", ".hidden", "-x", "a/b", "a b", "é"] {
+            assert!(Registry::check_name(name).is_err(), "{name}");
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use crate::registry::{Registry, Repository, RootfsEntry};
+use crate::settings::Settings;
+use crate::utils::{self, _parse_key_value};
+use crate::{download, parse_key_value};
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub struct Repo {
+    name: String,
+    remaining_args: Vec<String>,
+}
+
+impl Repo {
+    pub fn new(name: String, remaining_args: Vec<String>) -> Self {
+        Repo {
+            name,
+            remaining_args,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<_> = self.remaining_args.clone().into();
+        let sett = Settings::load_or_create();
+        let mut rootfs_dir: String = sett.set_rootfs();
+        let (mut tag, mut key): (Option<String>, Option<String>) = (None, None);
+        let mut positional: Vec<String> = Vec::new();
+
+        while let Some(arg) = args.pop_front() {
+            match arg.as_str() {
+                a if a.starts_with("--tag=") => {
+                    tag = parse_key_value!("repo", "name", arg)?;
+                }
+                "--tag" => {
+                    tag = parse_key_value!("repo", "name", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with("--key=") => {
+                    key = parse_key_value!("repo", "file", arg)?;
+                }
+                "--key" => {
+                    key = parse_key_value!("repo", "file", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with("--env=") => {
+                    let env = parse_key_value!("repo", "name", arg)?.unwrap();
+                    rootfs_dir = Registry::resolve(&env)?;
+                }
+                "--env" => {
+                    let env = parse_key_value!("repo", "name", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                    rootfs_dir = Registry::resolve(&env)?;
+                }
+                a if a.starts_with("--rootfs=") => {
+                    rootfs_dir = parse_key_value!("repo", "directory", arg)?.unwrap();
+                }
+                "-R" | "--rootfs" => {
+                    rootfs_dir = parse_key_value!("repo", "directory", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                }
+                a if a.starts_with('-') => {
+                    return Err(format!("{c}: repo: invalid argument '{arg}'\nUse '{c} --help' to see available options.", c = self.name).into())
+                }
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        let action = positional.next();
+        let target = positional.next();
+        if let Some(extra) = positional.next() {
+            return Err(format!("{c}: repo: invalid argument '{extra}'\nUse '{c} --help' to see available options.", c = self.name).into());
+        }
+
+        utils::check_rootfs_exists(self.name.clone(), rootfs_dir.clone())?;
+
+        match action.as_deref() {
+            Some("list") | Some("ls") => self.list(&rootfs_dir),
+            Some("add") => {
+                let url = self.require_target(target, "add", "URL")?;
+                self.add(&rootfs_dir, url, tag, key)
+            }
+            Some("rm") | Some("remove") => {
+                let url = self.require_target(target, "rm", "URL|@TAG")?;
+                self.remove(&rootfs_dir, url)
+            }
+            Some(other) => Err(format!(
+                "{c}: repo: invalid argument '{other}'\nUse '{c} --help' to see available options.",
+                c = self.name
+            )
+            .into()),
+            None => Err(format!(
+                "{c}: repo: no parameter specified\nUse '{c} --help' to see available options.",
+                c = self.name
+            )
+            .into()),
+        }
+    }
+
+    /// Adds a repository to the rootfs and records it in the registry.
+    fn add(&self, rootfs: &str, url: String, tag: Option<String>, key: Option<String>) -> Result<(), Box<dyn Error>> {
+        let url = match utils::local_path(&url) {
+            Some(path) => path.trim_end_matches('/').to_string(),
+            None => url.trim_end_matches('/').to_string(),
+        };
+
+        let remote = ["http://", "https://", "ftp://"].iter().any(|s| url.starts_with(s));
+        if !remote && !url.starts_with('/') {
+            return Err(format!("{}: repo: invalid repository '{url}', expected an URL or an absolute path", self.name).into());
+        }
+        if url.starts_with('/') && !Path::new(&url).is_dir() {
+            eprintln!("\x1b[1;33mWarning\x1b[0m: Local repository '{url}' does not exist yet.");
+        }
+
+        let tag = tag.unwrap_or_default().trim_start_matches('@').to_string();
+        if !tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(format!("{}: repo: invalid tag '{tag}'", self.name).into());
+        }
+
+        let mut registry = Registry::load();
+        let mut entry = Self::entry(&registry, rootfs);
+        if entry.repositories.iter().any(|r| r.url == url) {
+            return Err(format!("{}: repo: repository '{url}' is already added", self.name).into());
+        }
+
+        let key = match key {
+            Some(k) => Self::store_key(&k)?,
+            None => String::new(),
+        };
+        let repo = Repository { url, tag, key };
+
+        Self::install_keys(rootfs, std::slice::from_ref(&repo))?;
+
+        let repo_file = Path::new(rootfs).join("etc/apk/repositories");
+        let mut content = fs::read_to_string(&repo_file).unwrap_or_default();
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str(&repo.line());
+        content.push('\n');
+        fs::write(&repo_file, content)?;
+
+        println!("Repository added: {}", repo.line());
+        if !repo.key.is_empty() {
+            println!("Signing key installed: /etc/apk/keys/{}", repo.key);
+        }
+        println!("Run '{} update' to refresh the package index.", self.name);
+
+        entry.repositories.push(repo);
+        registry.upsert(entry);
+        registry.save()?;
+        Ok(())
+    }
+
+    /// Removes a repository, given by URL or `@tag`, from the rootfs.
+    ///
+    /// Its signing key is removed from the rootfs when no other added
+    /// repository uses it.
+    fn remove(&self, rootfs: &str, target: String) -> Result<(), Box<dyn Error>> {
+        let target = utils::local_path(&target).unwrap_or(&target).trim_end_matches('/').to_string();
+
+        let mut registry = Registry::load();
+        let mut entry = Self::entry(&registry, rootfs);
+        let pos = entry
+            .repositories
+            .iter()
+            .position(|r| r.url == target || (!r.tag.is_empty() && format!("@{}", r.tag) == target))
+            .ok_or_else(|| format!("{}: repo: repository '{target}' was not added with 'repo add'", self.name))?;
+        let repo = entry.repositories.remove(pos);
+
+        let repo_file = Path::new(rootfs).join("etc/apk/repositories");
+        let content = fs::read_to_string(&repo_file).unwrap_or_default();
+        let lines: String = content
+            .lines()
+            .filter(|l| l.trim() != repo.line())
+            .map(|l| format!("{l}\n"))
+            .collect();
+        fs::write(&repo_file, lines)?;
+
+        if !repo.key.is_empty() && !entry.repositories.iter().any(|r| r.key == repo.key) {
+            let _ = fs::remove_file(Path::new(rootfs).join("etc/apk/keys").join(&repo.key));
+        }
+
+        registry.upsert(entry);
+        registry.save()?;
+        println!("Repository removed: {}", repo.line());
+        Ok(())
+    }
+
+    /// Prints the repositories of the rootfs, marking the added ones.
+    fn list(&self, rootfs: &str) -> Result<(), Box<dyn Error>> {
+        let content = fs::read_to_string(Path::new(rootfs).join("etc/apk/repositories")).unwrap_or_default();
+        let entry = Self::entry(&Registry::load(), rootfs);
+
+        for line in content.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            match entry.repositories.iter().find(|r| r.line() == line) {
+                Some(r) if !r.key.is_empty() => println!("+ {line}  (key: {})", r.key),
+                Some(_) => println!("+ {line}"),
+                None => println!("  {line}"),
+            }
+        }
+        println!("\n(+) added with 'repo add', kept on 'setup --reinstall'");
+        Ok(())
+    }
+
+    /// Returns the registry entry of the rootfs, or a new one if it is not registered.
+    fn entry(registry: &Registry, rootfs: &str) -> RootfsEntry {
+        registry
+            .find_by_path(rootfs)
+            .cloned()
+            .unwrap_or_else(|| RootfsEntry::new(registry.name_for_path(rootfs), rootfs.to_string()))
+    }
+
+    /// Copies a signing key, from a file or an URL, to the key store.
+    ///
+    /// A stored key is never replaced: storing a key again with the same
+    /// content is a no-op, while a different key with the same name is refused.
+    ///
+    /// # Returns
+    /// * `Ok(String)` with the file name of the key.
+    /// * `Err` if the key cannot be read, or another key with the same name is stored.
+    ///
+    /// # Example
+    /// ```
+    /// let key = Repo::store_key("https://pkgs.example.org/alpine/builder.rsa.pub")?; // "builder.rsa.pub"
+    /// ```
+    pub fn store_key(key: &str) -> Result<String, Box<dyn Error>> {
+        let key_name = key
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .filter(|n| !n.is_empty())
+            .ok_or_else(|| format!("Invalid key '{key}'"))?
+            .to_string();
+        let store = Self::key_store();
+        fs::create_dir_all(&store)?;
+
+        let local = utils::local_path(key).or(Some(key).filter(|k| !k.contains("://")));
+        let content = match local {
+            Some(path) => fs::read(path).map_err(|e| format!("Cannot read key '{path}': {e}"))?,
+            None => download::fetch_text(key.to_string())?.into_bytes(),
+        };
+
+        let dest = store.join(&key_name);
+        match fs::read(&dest) {
+            Ok(stored) if stored == content => {}
+            Ok(_) => {
+                return Err(format!(
+                    "A different key named '{key_name}' is already stored in {}.\nRemove it or rename the new key before adding it.",
+                    store.display()
+                )
+                .into())
+            }
+            Err(_) => fs::write(&dest, content)?,
+        }
+        Ok(key_name)
+    }
+
+    /// Installs the signing keys of the repositories into `/etc/apk/keys`.
+    ///
+    /// # Arguments
+    /// * `rootfs` - The rootfs directory.
+    /// * `repos` - The repositories whose keys are installed.
+    ///
+    /// # Returns
+    /// * `Ok(())` if every key was installed.
+    /// * `Err` if a key is missing from the key store.
+    ///
+    /// # Example
+    /// ```
+    /// Repo::install_keys("/home/user/.ALPack", &entry.repositories)?;
+    /// ```
+    pub fn install_keys(rootfs: &str, repos: &[Repository]) -> Result<(), Box<dyn Error>> {
+        let keys_dir = Path::new(rootfs).join("etc/apk/keys");
+        for repo in repos.iter().filter(|r| !r.key.is_empty()) {
+            let source = Self::key_store().join(&repo.key);
+            fs::create_dir_all(&keys_dir)?;
+            fs::copy(&source, keys_dir.join(&repo.key))
+                .map_err(|e| format!("Cannot install key '{}': {e}", source.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the directory where the signing keys of added repositories are kept.
+    fn key_store() -> PathBuf {
+        Settings::config_dir().join("apk-keys")
+    }
+
+    /// Returns the target of an action or an error if it was not given.
+    fn require_target(&self, target: Option<String>, sub: &str, what: &str) -> Result<String, Box<dyn Error>> {
+        target.ok_or_else(|| {
+            format!(
+                "{c}: repo: {sub} requires a <{what}> as argument.\nUsage: {c} repo {sub} <{what}>",
+                c = self.name
+            )
+            .into()
+        })
+    }
+}
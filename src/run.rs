@@ -1,9 +1,13 @@
 use crate::command::Command;
 use crate::parse_key_value;
+use crate::registry::Registry;
+use crate::sandbox::{Bind, MountEntry};
 use crate::settings::Settings;
+use crate::utils;
 use crate::utils::_parse_key_value;
 use std::collections::VecDeque;
 use std::error::Error;
+use std::ffi::OsString;
 
 pub struct Run {
     name: String,
@@ -24,8 +28,8 @@ impl Run {
         let mut args: VecDeque<_> = self.remaining_args.clone().into();
 
         let mut cmd_args = Vec::new();
-        let mut bind_args: Option<String> = None;
-        let (mut use_root, mut ignore_extra_bind) = (false, false);
+        let mut binds = Vec::new();
+        let (mut use_root, mut ignore_extra_bind, mut ephemeral) = (false, false, false);
 
         while let Some(arg) = args.pop_front() {
             match arg.as_str() {
@@ -35,11 +39,24 @@ impl Run {
                 "-i" | "--ignore-extra-binds" => {
                     ignore_extra_bind = true;
                 },
+                "-e" | "--ephemeral" => {
+                    ephemeral = true;
+                },
                 a if a.starts_with("--bind-args=") => {
-                    bind_args = parse_key_value!("run", "parameters", arg)?;
+                    let params = parse_key_value!("run", "parameters", arg)?.unwrap();
+                    binds.push(Bind::Args(utils::split_args(&params)?.into_iter().map(OsString::from).collect()));
+                }
+                a if a.starts_with("--mount=") => {
+                    let mount = parse_key_value!("run", "mount", arg)?.unwrap();
+                    binds.extend(MountEntry::parse(&mount)?.to_mount()?.map(Bind::from));
                 }
+                "-m" | "--mount" => {
+                    let mount = parse_key_value!("run", "mount", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                    binds.extend(MountEntry::parse(&mount)?.to_mount()?.map(Bind::from));
+                },
                 "-b" | "--bind-args" => {
-                    bind_args = parse_key_value!("run", "parameters", arg, args.pop_front().unwrap_or_default())?;
+                    let params = parse_key_value!("run", "parameters", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                    binds.push(Bind::Args(utils::split_args(&params)?.into_iter().map(OsString::from).collect()));
                 },
                 a if a.starts_with("--command=") => {
                     let cmd = parse_key_value!("run", "command", arg)?;
@@ -49,6 +66,14 @@ impl Run {
                     let cmd = parse_key_value!("run", "command", arg, args.pop_front().unwrap_or_default())?;
                     cmd_args.push(cmd.unwrap());
                 },
+                a if a.starts_with("--env=") => {
+                    let env = parse_key_value!("run", "name", arg)?.unwrap();
+                    rootfs_dir = Registry::resolve(&env)?;
+                }
+                "--env" => {
+                    let env = parse_key_value!("run", "name", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                    rootfs_dir = Registry::resolve(&env)?;
+                }
                 a if a.starts_with("--rootfs=") => {
                     rootfs_dir = parse_key_value!("run", "directory", arg)?.unwrap();
                 }
@@ -56,23 +81,27 @@ impl Run {
                     rootfs_dir = parse_key_value!("run", "directory", arg, args.pop_front().unwrap_or_default())?.unwrap();
                 }
                 "--" => {
-                    cmd_args.extend(args.drain(..));
+                    cmd_args.extend(args.drain(..).map(|a| utils::shell_quote(&a)));
                     break;
                 }
                 a if a.starts_with('-') => {
                     return Err(format!("{c}: run: invalid argument '{arg}'\nUse '{c} --help' to see available options.", c = self.name).into())
                 }
                 _ => {
-                    cmd_args.push(arg);
-                    cmd_args.extend(args.drain(..));
+                    cmd_args.push(utils::shell_quote(&arg));
+                    cmd_args.extend(args.drain(..).map(|a| utils::shell_quote(&a)));
                     break;
                 }
             }
         }
 
-        Command::run(
+        let run = match ephemeral {
+            true => Command::run_ephemeral,
+            false => Command::run,
+        };
+        run(
             rootfs_dir,
-            bind_args,
+            binds,
             Some(cmd_args.join(" ")),
             use_root,
             ignore_extra_bind,
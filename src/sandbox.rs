@@ -0,0 +1,187 @@
+use crate::registry::Registry;
+
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::error::Error;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// `PATH` of the sandboxed processes.
+pub const SANDBOX_PATH: &str = "/bin:/sbin:/usr/bin:/usr/sbin:/usr/libexec";
+
+/// A host path mounted in the sandbox.
+#[derive(Debug, Clone)]
+pub struct Mount {
+    pub source: PathBuf,
+    pub dest: PathBuf,
+    /// Mount it read-only; PRoot cannot enforce it and binds it read-write.
+    pub read_only: bool,
+}
+
+impl Mount {
+    /// Creates a mount of `source` at `dest`.
+    ///
+    /// # Example
+    /// ```
+    /// let mount = Mount::new("/home/user/src", "/src", false);
+    /// ```
+    pub fn new(source: impl Into<PathBuf>, dest: impl Into<PathBuf>, read_only: bool) -> Self {
+        Mount {
+            source: source.into(),
+            dest: dest.into(),
+            read_only,
+        }
+    }
+}
+
+/// A mount of the settings, or given to `run --mount`.
+///
+/// Mounts of the settings apply to every rootfs, or only to the one named
+/// by `rootfs`:
+///
+/// ```toml
+/// [[mounts]]
+/// source = "~/src"
+/// target = "/src"
+///
+/// [[mounts]]
+/// source = "/opt/sdk"
+/// read_only = true
+/// optional = true
+/// rootfs = "arm"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct MountEntry {
+    /// Host path, `~` is the home directory and a relative path starts in the current directory.
+    pub source: String,
+    /// Absolute path inside the rootfs, the source path if empty.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub target: String,
+    pub read_only: bool,
+    /// Skip the mount when the source is missing, instead of failing.
+    pub optional: bool,
+    /// Name of the environment or path of the rootfs using the mount, every rootfs if empty.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub rootfs: String,
+}
+
+impl MountEntry {
+    /// Parses a `SOURCE[:TARGET[:OPTIONS]]` mount, with `ro`, `rw` and
+    /// `optional` as comma-separated options.
+    ///
+    /// # Example
+    /// ```
+    /// let entry = MountEntry::parse("./src:/src:ro")?;
+    /// ```
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.splitn(3, ':');
+        let mut entry = MountEntry {
+            source: parts.next().unwrap_or_default().to_string(),
+            target: parts.next().unwrap_or_default().to_string(),
+            ..Default::default()
+        };
+        for option in parts.next().unwrap_or_default().split(',').filter(|o| !o.is_empty()) {
+            match option {
+                "ro" => entry.read_only = true,
+                "rw" => entry.read_only = false,
+                "optional" => entry.optional = true,
+                other => return Err(format!("invalid mount option '{other}' in '{spec}', expected 'ro', 'rw' or 'optional'")),
+            }
+        }
+        if entry.source.is_empty() {
+            return Err(format!("invalid mount '{spec}', expected 'SOURCE[:TARGET[:ro|rw]]'"));
+        }
+        Ok(entry)
+    }
+
+    /// Checks whether the mount is used by a rootfs.
+    pub fn applies_to(&self, rootfs: &str) -> bool {
+        let rootfs = rootfs.trim_end_matches('/');
+        self.rootfs.is_empty()
+            || self.rootfs.trim_end_matches('/') == rootfs
+            || Registry::load().find(&self.rootfs).is_some_and(|e| e.path.trim_end_matches('/') == rootfs)
+    }
+
+    /// Resolves the mount to the host and rootfs paths.
+    ///
+    /// # Returns
+    /// * `Ok(Some(Mount))` when the source exists.
+    /// * `Ok(None)` when an optional source is missing.
+    /// * `Err` when a required source is missing or the target is not absolute.
+    pub fn to_mount(&self) -> Result<Option<Mount>, Box<dyn Error>> {
+        let source = match self.source.strip_prefix('~') {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+                PathBuf::from(format!("{}{rest}", env::var("HOME").unwrap_or_default()))
+            }
+            _ => env::current_dir()?.join(&self.source),
+        };
+        let target = match self.target.is_empty() {
+            true => source.clone(),
+            false => PathBuf::from(&self.target),
+        };
+
+        if !target.is_absolute() {
+            return Err(format!("mount target '{}' is not an absolute path", self.target).into());
+        }
+        if !source.exists() {
+            return match self.optional {
+                true => Ok(None),
+                false => Err(format!("mount source '{}' does not exist", source.display()).into()),
+            };
+        }
+        Ok(Some(Mount::new(source, target, self.read_only)))
+    }
+}
+
+/// An extra bind of a session, given to `Command::run`.
+#[derive(Debug, Clone)]
+pub enum Bind {
+    /// A host path mounted in the rootfs.
+    Mount(Mount),
+    /// Raw options of the backend, as given to `run --bind-args`.
+    Args(Vec<OsString>),
+}
+
+impl From<Mount> for Bind {
+    fn from(mount: Mount) -> Self {
+        Bind::Mount(mount)
+    }
+}
+
+/// A sandboxed session, independent of the backend running it.
+///
+/// Every path and argument is kept as a separate `OsString`, so paths
+/// with spaces or that are not valid UTF-8 reach the backend unchanged.
+#[derive(Debug, Default)]
+pub struct SandboxSpec {
+    /// The directory mounted as `/`.
+    pub root: PathBuf,
+    /// Mount `root` as the lower layer of a temporary overlay, see `Backend::supports_tmp_overlay`.
+    pub overlay: bool,
+    pub mounts: Vec<Mount>,
+    /// Raw options passed to the backend after the mounts.
+    pub backend_args: Vec<OsString>,
+    /// `NAME=value` variables set with `env` before the command.
+    pub env: Vec<OsString>,
+    /// Run the command as root (uid 0).
+    pub root_user: bool,
+    /// Use the users and groups of the rootfs instead of the ones of the host.
+    pub rootfs_users: bool,
+    /// Working directory inside the sandbox, the default one of the backend if `None`.
+    pub cwd: Option<PathBuf>,
+    /// qemu-user binary running the programs of a foreign rootfs (PRoot only).
+    pub emulator: Option<PathBuf>,
+    /// The command run inside the sandbox.
+    pub argv: Vec<OsString>,
+}
+
+impl SandboxSpec {
+    /// Returns the command line run inside the sandbox: `env`, the variables and the command.
+    pub fn command_args(&self) -> Vec<OsString> {
+        let mut args = vec![OsString::from("env")];
+        args.extend(self.env.iter().cloned());
+        args.extend(self.argv.iter().cloned());
+        args
+    }
+}
@@ -1,26 +1,55 @@
+use crate::provision::ProvisionStep;
+use crate::sandbox::MountEntry;
+
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::Path;
 use std::{env, fs, io, path::PathBuf};
 
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
 pub struct Settings {
     pub default_mirror: String,
+    pub fallback_mirrors: Vec<String>,
     pub cache_dir: String,
     pub rootfs_dir: String,
+    pub envs_dir: String,
     pub cmd_rootfs: String,
     pub release: String,
+    pub version: String,
     pub output_dir: String,
+    pub apk_cache: bool,
+    pub proxy: String,
+    pub no_proxy: String,
+    pub ca_bundle: String,
+    pub default_preset: String,
+    pub presets: BTreeMap<String, Vec<String>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub provision: Vec<ProvisionStep>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mounts: Vec<MountEntry>,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Self {
             default_mirror: "https://dl-cdn.alpinelinux.org/alpine/".to_string(),
+            fallback_mirrors: Vec::new(),
             cache_dir: format!("{}/.cache/ALPack", env!("HOME")),
             rootfs_dir: format!("{}/.ALPack", env!("HOME")),
+            envs_dir: format!("{}/.ALPack-envs", env!("HOME")),
             cmd_rootfs: "proot".to_string(),
             release: "latest-stable".to_string(),
+            version: String::new(),
             output_dir: String::new(),
+            apk_cache: false,
+            proxy: String::new(),
+            no_proxy: String::new(),
+            ca_bundle: String::new(),
+            default_preset: "sdk".to_string(),
+            presets: Self::builtin_presets(),
+            provision: Vec::new(),
+            mounts: Vec::new(),
         }
     }
 }
@@ -33,7 +62,7 @@ impl Settings {
     /// let settings = Settings::load_or_create();
     /// ```
     pub fn load_or_create() -> Self {
-        let path = PathBuf::from(format!("{}/.config/ALPack/config.toml", env!("HOME")));
+        let path = Self::config_dir().join("config.toml");
 
         if path.exists() {
             match fs::read_to_string(&path) {
@@ -100,12 +129,12 @@ impl Settings {
     /// settings.save().unwrap();
     /// ```
     pub fn save(&self) -> io::Result<()> {
-        let path = PathBuf::from(format!("{}/.config/ALPack/config.toml", env!("HOME")));
+        let path = Self::config_dir().join("config.toml");
         if let Some(dir) = path.parent() {
             fs::create_dir_all(dir)?;
         }
         let toml_data = toml::to_string_pretty(self)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+            .map_err(|e| io::Error::other(e.to_string()))?;
         fs::write(path, toml_data)
     }
 
@@ -121,7 +150,7 @@ impl Settings {
     /// settings.show_config_changes();
     /// ```
     pub fn show_config_changes(&self) {
-        let path = format!("{}/.config/ALPack/config.toml", env!("HOME"));
+        let path = Self::config_dir().join("config.toml");
         let _current_disk_config = fs::read_to_string(&path)
             .ok()
             .and_then(|s| toml::from_str::<Settings>(&s).ok());
@@ -129,18 +158,26 @@ impl Settings {
 
         macro_rules! show_field {
             ($field:ident) => {
+                show_field!($field, |s: &Settings| s.$field.to_string());
+            };
+            ($field:ident, $to_string:expr) => {
+                let to_string = $to_string;
                 let field_name = stringify!($field).to_string();
-                let mut new_val = self.$field.to_string();
+                let mut new_val = to_string(self);
                 let value_str = if let Some(old) = &_current_disk_config {
-                    let mut old_val = old.$field.to_string();
-                    if field_name == "output_dir" {
-                        let val = "Current Directory or Home FallBack".to_string();
-                        if new_val == String::new() {
-                            new_val = val.clone();
-                        }
-                        if old_val == String::new() {
-                            old_val = val.clone();
-                        }
+                    let mut old_val = to_string(old);
+                    let placeholder = match field_name.as_str() {
+                        "version" => "latest",
+                        "output_dir" => "Current Directory or Home FallBack",
+                        "proxy" => "from environment",
+                        "ca_bundle" => "built-in",
+                        _ => "",
+                    };
+                    if new_val == String::new() {
+                        new_val = placeholder.to_string();
+                    }
+                    if old_val == String::new() {
+                        old_val = placeholder.to_string();
                     }
                     if old_val != new_val {
                         format!("\x1b[1;31m{old_val}\x1b[0m -> \x1b[1;32m{new_val}\x1b[0m")
@@ -155,11 +192,19 @@ impl Settings {
         }
 
         show_field!(default_mirror);
+        show_field!(fallback_mirrors, |s: &Settings| s.fallback_mirrors.join(" "));
         show_field!(cache_dir);
         show_field!(rootfs_dir);
+        show_field!(envs_dir);
         show_field!(cmd_rootfs);
         show_field!(release);
+        show_field!(version);
         show_field!(output_dir);
+        show_field!(apk_cache);
+        show_field!(proxy);
+        show_field!(no_proxy);
+        show_field!(ca_bundle);
+        show_field!(default_preset);
 
         let key_width = rows.iter().map(|(k, _)| k.len()).max().unwrap_or(0);
         let val_width = rows.iter().map(|(_, v)| v.len()).max().unwrap_or(0);
@@ -192,6 +237,62 @@ impl Settings {
         print!("{output}");
     }
 
+    /// Returns the package presets shipped with ALPack.
+    ///
+    /// The `sdk` preset is the package set installed by earlier versions.
+    pub fn builtin_presets() -> BTreeMap<String, Vec<String>> {
+        let presets: [(&str, &[&str]); 6] = [
+            ("base", &["bash", "curl", "git", "xz"]),
+            ("c", &["build-base", "autoconf", "automake", "cmake", "pkgconf"]),
+            ("go", &["go", "git"]),
+            ("rust", &["rust", "cargo", "git"]),
+            ("gui", &["font-dejavu", "mesa-dri-gallium", "mesa-egl", "libx11"]),
+            ("sdk", &["alpine-sdk", "autoconf", "automake", "cmake", "go", "xz"]),
+        ];
+
+        presets
+            .iter()
+            .map(|(name, pkgs)| (name.to_string(), pkgs.iter().map(|p| p.to_string()).collect()))
+            .collect()
+    }
+
+    /// Returns the packages of a preset.
+    ///
+    /// Presets defined in `config.toml` take precedence over the built-in ones.
+    ///
+    /// # Examples
+    /// ```
+    /// let pkgs = settings.preset("rust"); // Some(["rust", "cargo", "git"])
+    /// ```
+    pub fn preset(&self, name: &str) -> Option<Vec<String>> {
+        self.presets
+            .get(name)
+            .cloned()
+            .or_else(|| Self::builtin_presets().remove(name))
+    }
+
+    /// Returns the names of every available preset.
+    pub fn preset_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.presets.keys().cloned().collect();
+        names.extend(Self::builtin_presets().into_keys());
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Returns the directory holding the ALPack configuration files.
+    ///
+    /// # Returns
+    /// * `PathBuf` - The path to `~/.config/ALPack`.
+    ///
+    /// # Examples
+    /// ```
+    /// let keys = Settings::config_dir().join("keys");
+    /// ```
+    pub fn config_dir() -> PathBuf {
+        PathBuf::from(format!("{}/.config/ALPack", env!("HOME")))
+    }
+
     /// Determines the output directory for the application.
     ///
     /// # Returns
@@ -1,20 +1,22 @@
+use crate::archive;
+use crate::backend;
 use crate::command::Command;
 use crate::mirror::Mirror;
+use crate::provision::{self, ProvisionStep};
+use crate::registry::{Registry, RootfsEntry};
+use crate::repo::Repo;
 use crate::settings::Settings;
 use crate::utils::{_parse_key_value, finish_msg_setup};
-use crate::{parse_key_value, utils};
+use crate::{download, parse_key_value, qemu, utils, verify};
 
-use flate2::read::GzDecoder;
-use indicatif::{ProgressBar, ProgressStyle};
 use regex::Regex;
-use scraper::{Html, Selector};
-use std::collections::VecDeque;
+use serde::Deserialize;
+use std::collections::{BTreeMap, VecDeque};
 use std::error::Error;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::Write;
 use std::path::Path;
-use std::{fs, io};
-use tar::Archive;
+use std::{env, fs, io, process};
 
 pub struct Setup {
     name: String,
@@ -22,6 +24,28 @@ pub struct Setup {
     def_rootfs: Option<String>,
 }
 
+/// Origin of a minirootfs tarball given on the command line.
+enum Source {
+    Local(String),
+    Remote(String),
+}
+
+impl Source {
+    fn path(&self) -> &str {
+        match self {
+            Source::Local(p) | Source::Remote(p) => p,
+        }
+    }
+}
+
+/// Package presets shipped in a file, given with `--preset-file`.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct PresetFile {
+    default_preset: Option<String>,
+    presets: BTreeMap<String, Vec<String>>,
+}
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 struct VersionKey {
     major: u32,
@@ -41,8 +65,15 @@ impl Setup {
 
     pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
         let mut args: VecDeque<_> = self.remaining_args.clone().into();
-        let mut use_mirror: Option<String> = None;
+        let (mut use_mirror, mut release, mut version): (Option<String>, Option<String>, Option<String>) = (None, None, None);
         let (mut no_cache, mut reinstall, mut edge, mut minimal) = (false, false, false, false);
+        let (mut from_tarball, mut from_url): (Option<String>, Option<String>) = (None, None);
+        let (mut env_name, mut backend): (Option<String>, Option<String>) = (None, None);
+        let mut arch: Option<String> = None;
+        let (mut provision, mut provision_user, mut no_provision): (Vec<ProvisionStep>, bool, bool) = (Vec::new(), false, false);
+        let (mut preset, mut preset_file, mut packages): (Option<String>, Option<String>, Option<String>) = (None, None, None);
+        let mut rootfs_set = false;
+        let mut skip_verify = false;
 
         let sett = Settings::load_or_create();
         let (mut cache_dir, mut rootfs_dir) = (sett.set_cache_dir(), sett.set_rootfs());
@@ -62,6 +93,33 @@ impl Setup {
                 "--minimal" => {
                     minimal = true;
                 },
+                "--skip-verify" => {
+                    skip_verify = true;
+                },
+                a if a.starts_with("--release=") => {
+                    release = parse_key_value!("setup", "release", arg)?;
+                }
+                "--release" => {
+                    release = parse_key_value!("setup", "release", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with("--version=") => {
+                    version = parse_key_value!("setup", "version", arg)?;
+                }
+                "--version" => {
+                    version = parse_key_value!("setup", "version", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with("--from-tarball=") => {
+                    from_tarball = parse_key_value!("setup", "file", arg)?;
+                }
+                "--from-tarball" => {
+                    from_tarball = parse_key_value!("setup", "file", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with("--from-url=") => {
+                    from_url = parse_key_value!("setup", "url", arg)?;
+                }
+                "--from-url" => {
+                    from_url = parse_key_value!("setup", "url", arg, args.pop_front().unwrap_or_default())?;
+                }
                 a if a.starts_with("--mirror=") => {
                     use_mirror = parse_key_value!("setup", "url", arg)?;
                 }
@@ -76,9 +134,69 @@ impl Setup {
                 }
                 a if a.starts_with("--rootfs=") => {
                     rootfs_dir = parse_key_value!("setup", "directory", arg)?.unwrap_or_default();
+                    rootfs_set = true;
                 }
                 "-R" | "--rootfs" => {
                     rootfs_dir = parse_key_value!("setup", "directory", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                    rootfs_set = true;
+                }
+                a if a.starts_with("--env=") => {
+                    env_name = parse_key_value!("setup", "name", arg)?;
+                }
+                "--env" => {
+                    env_name = parse_key_value!("setup", "name", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with("--provision=") => {
+                    let script = parse_key_value!("setup", "file", arg)?.unwrap();
+                    provision.push(ProvisionStep { script, ..Default::default() });
+                }
+                "--provision" => {
+                    let script = parse_key_value!("setup", "file", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                    provision.push(ProvisionStep { script, ..Default::default() });
+                }
+                a if a.starts_with("--provision-cmd=") => {
+                    let command = parse_key_value!("setup", "command", arg)?.unwrap();
+                    provision.push(ProvisionStep { command, ..Default::default() });
+                }
+                "--provision-cmd" => {
+                    let command = parse_key_value!("setup", "command", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                    provision.push(ProvisionStep { command, ..Default::default() });
+                }
+                "--provision-user" => {
+                    provision_user = true;
+                },
+                "--no-provision" => {
+                    no_provision = true;
+                },
+                a if a.starts_with("--preset=") => {
+                    preset = parse_key_value!("setup", "name", arg)?;
+                }
+                "--preset" => {
+                    preset = parse_key_value!("setup", "name", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with("--preset-file=") => {
+                    preset_file = parse_key_value!("setup", "file", arg)?;
+                }
+                "--preset-file" => {
+                    preset_file = parse_key_value!("setup", "file", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with("--packages=") => {
+                    packages = parse_key_value!("setup", "packages", arg)?;
+                }
+                "--packages" => {
+                    packages = parse_key_value!("setup", "packages", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with("--arch=") => {
+                    arch = parse_key_value!("setup", "arch", arg)?;
+                }
+                "--arch" => {
+                    arch = parse_key_value!("setup", "arch", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with("--backend=") => {
+                    backend = parse_key_value!("setup", "backend", arg)?;
+                }
+                "--backend" => {
+                    backend = parse_key_value!("setup", "backend", arg, args.pop_front().unwrap_or_default())?;
                 }
                 _ => {
                     return Err(format!("{c}: setup: invalid argument '{arg}'\nUse '{c} --help' to see available options.", c = self.name).into())
@@ -86,60 +204,172 @@ impl Setup {
             }
         }
 
+        if let Some(b) = &backend
+            && !backend::BACKENDS.contains(&b.as_str())
+        {
+            return Err(format!("{}: setup: invalid backend '{b}', expected one of: {}", self.name, backend::BACKENDS.join(", ")).into());
+        }
+
+        if minimal && (preset.is_some() || packages.is_some()) {
+            return Err(format!("{}: setup: --minimal cannot be used with --preset or --packages", self.name).into());
+        }
+        provision.iter_mut().for_each(|step| step.user = provision_user);
+        if !no_provision {
+            provision.splice(0..0, sett.provision.iter().cloned());
+        }
+
+        let install = match minimal {
+            true => Vec::new(),
+            false => self.resolve_packages(&sett, preset, preset_file, packages)?,
+        };
+
+        if let Some(a) = &arch
+            && !qemu::ALPINE_ARCHES.contains(&a.as_str())
+        {
+            return Err(format!(
+                "{}: setup: invalid architecture '{a}', expected one of: {}",
+                self.name,
+                qemu::ALPINE_ARCHES.join(", ")
+            )
+            .into());
+        }
+
+        if let Some(name) = &env_name {
+            match Registry::load().find(name) {
+                Some(entry) => rootfs_dir = entry.path.clone(),
+                None => {
+                    Registry::check_name(name).map_err(|e| format!("{}: setup: {e}", self.name))?;
+                    if !rootfs_set {
+                        rootfs_dir = format!("{}/{name}", sett.envs_dir);
+                    }
+                }
+            }
+        }
+
         if !reinstall {
             self.test_valid_directory(&rootfs_dir)?;
         }
 
         if no_cache {
-            cache_dir = String::from("/tmp/ALPack_cache");
+            cache_dir = env::temp_dir().join(format!("ALPack-cache-{}", process::id())).display().to_string();
         }
 
-        let mut mirror = Mirror::new(use_mirror, edge.then_some("edge".to_string()));
-        mirror.run()?;
-
-        let url = mirror.get_mirror();
-        let res = ureq::get(url.as_str())
-            .call()?
-            .body_mut()
-            .read_to_string()?;
+        if edge {
+            if release.as_ref().is_some_and(|r| r != "edge") {
+                return Err(format!("{}: setup: --edge cannot be used with --release", self.name).into());
+            }
+            release = Some("edge".to_string());
+        }
 
-        let document = Html::parse_document(res.as_str());
-        let selector = Selector::parse("a").unwrap();
+        if let Some(r) = &release
+            && !Mirror::is_valid_release(r)
+        {
+            return Err(format!("{}: setup: invalid release '{r}', expected 'latest-stable', 'edge' or 'vX.Y'", self.name).into());
+        }
 
-        let pattern = format!(
-            r"^alpine-minirootfs-([\w.\-]+)-{}\.tar\.gz$",
-            utils::get_arch()
-        );
-        let re = Regex::new(&pattern).unwrap();
+        let source = match (from_tarball, from_url) {
+            (Some(_), Some(_)) => {
+                return Err(format!("{}: setup: --from-tarball cannot be used with --from-url", self.name).into());
+            }
+            (Some(path), None) => Some(Source::Local(path)),
+            (None, Some(url)) => match utils::local_path(&url) {
+                Some(path) => Some(Source::Local(path.to_string())),
+                None => Some(Source::Remote(url)),
+            },
+            (None, None) => None,
+        };
 
-        let mut matches = vec![];
-        for element in document.select(&selector) {
-            if let Some(href) = element.value().attr("href") {
-                if let Some(caps) = re.captures(href) {
-                    let version_str = &caps[1];
-                    if let Some(key) = self.parse_version_key(version_str) {
-                        matches.push((key, version_str.to_string(), href.to_string()));
-                    }
+        if let Some(src) = &source {
+            let found = Self::tarball_version(src.path());
+            match (&version, &found) {
+                (Some(v), Some(f)) if v != f => {
+                    return Err(format!("{}: setup: the tarball contains version {f}, not {v}", self.name).into());
                 }
+                (None, Some(_)) => version = found,
+                _ => {}
+            }
+            if arch.is_none() {
+                arch = Self::tarball_arch(src.path());
             }
         }
 
-        matches.sort_by(|a, b| a.0.cmp(&b.0));
-        let mut dest_rootfs = rootfs_dir.clone();
-
-        if let Some((_, version, link)) = matches.last() {
-            println!("Latest version found: {version}");
-            println!("Link: {url}{link}");
-            let dest_dir =
-                utils::download_file(format!("{url}{link}"), cache_dir.clone(), link.to_string())?;
-            dest_rootfs = self.extract_tar_gz(format!("{dest_dir}/{link}"), rootfs_dir)?;
-
-            if no_cache {
-                let path = Path::new(cache_dir.as_str());
-                fs::remove_dir_all(path)?;
+        if version.is_none() && release.is_none() && source.is_none() && !sett.version.is_empty() {
+            version = Some(sett.version.clone());
+        }
+
+        if let Some(v) = &version {
+            let branch = Mirror::release_from_version(v).ok_or_else(|| {
+                format!("{}: setup: invalid version '{v}', expected 'X.Y.Z'", self.name)
+            })?;
+            match &release {
+                Some(r) if *r != branch => {
+                    return Err(format!("{}: setup: version {v} does not belong to release {r}", self.name).into());
+                }
+                _ => release = Some(branch),
             }
-        } else {
-            Err("No alpine-minirootfs files found")?;
+        }
+
+        let mut mirror = Mirror::new(use_mirror, release);
+        mirror.set_arch(arch);
+        mirror.run()?;
+
+        if source.is_none() {
+            Mirror::check_release(&mirror.get_base(), &mirror.get_release())
+                .map_err(|e| format!("{}: setup: {e}", self.name))?;
+        }
+
+        let tarball = match source {
+            Some(Source::Local(path)) => self.local_rootfs(path, skip_verify)?,
+            Some(Source::Remote(url)) => self.download_rootfs(url, &cache_dir, skip_verify)?,
+            None => loop {
+                let attempt = self
+                    .find_rootfs(&mirror, version.clone())
+                    .and_then(|(url, found)| Ok((self.download_rootfs(url, &cache_dir, skip_verify)?, found)));
+                match attempt {
+                    Ok((tarball, found)) => {
+                        version = Some(found);
+                        break tarball;
+                    }
+                    Err(e) if e.downcast_ref::<io::Error>().is_some_and(download::is_network_error) => {
+                        let failed = mirror.get_base();
+                        match mirror.next_mirror() {
+                            Some(next) => eprintln!("\x1b[1;33mWarning\x1b[0m: Mirror '{failed}' failed: {e}\nTrying mirror '{next}'..."),
+                            None => return Err(e),
+                        }
+                    }
+                    Err(e) => return Err(e),
+                }
+            },
+        };
+
+        mirror.set_version(version.clone());
+        let dest_rootfs = self.extract_tar_gz(tarball, rootfs_dir)?;
+
+        let mut registry = Registry::load();
+        let existing = registry.find_by_path(&dest_rootfs).cloned();
+        let name = env_name
+            .or(existing.as_ref().map(|e| e.name.clone()))
+            .unwrap_or_else(|| registry.name_for_path(&dest_rootfs));
+
+        let mut entry = RootfsEntry::new(name, dest_rootfs.clone());
+        entry.arch = mirror.get_arch();
+        entry.release = mirror.get_release();
+        entry.version = version.unwrap_or_default();
+        if let Some(old) = &existing {
+            entry.repositories = old.repositories.clone();
+            entry.binds = old.binds.clone();
+            entry.env = old.env.clone();
+        }
+        entry.backend = backend
+            .or(existing.map(|e| e.backend))
+            .unwrap_or_default();
+        mirror.set_extra_repositories(entry.repositories.iter().map(|r| r.line()).collect());
+        Repo::install_keys(&dest_rootfs, &entry.repositories)?;
+        registry.upsert(entry);
+        registry.save()?;
+
+        if no_cache {
+            let _ = fs::remove_dir_all(Path::new(cache_dir.as_str()));
         }
 
         let new_content = mirror.get_repository();
@@ -149,29 +379,231 @@ impl Setup {
 
         Command::run(
             dest_rootfs.clone(),
-            None,
+            Vec::new(),
             Some("apk update".to_string()),
             true,
             true,
             false,
         )?;
 
-        if !minimal {
+        if !install.is_empty() {
             Command::run(
-                dest_rootfs,
-                None,
-                Some("apk add alpine-sdk autoconf automake cmake go xz".to_string()),
+                dest_rootfs.clone(),
+                Vec::new(),
+                Some(format!("apk add {}", install.join(" "))),
                 true,
                 true,
                 false,
             )?;
         }
 
+        provision::run(&dest_rootfs, &provision)?;
+
         finish_msg_setup(self.name.clone());
         Ok(())
     }
 
-    /// Extracts a `.tar.gz` archive to the specified destination directory.
+    /// Resolves the packages installed after the rootfs is extracted.
+    ///
+    /// `--packages` alone installs exactly the given packages; combined with
+    /// `--preset` they are added to the preset. Without either option, the
+    /// default preset is installed. Presets are looked up in the preset file
+    /// first, then in `config.toml` and in the built-in presets.
+    ///
+    /// # Arguments
+    /// * `sett` - The settings holding the configured presets.
+    /// * `preset` - The preset given with `--preset`.
+    /// * `preset_file` - A TOML file with a `[presets]` table and an optional `default_preset`.
+    /// * `packages` - The space-separated packages given with `--packages`.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<String>)` with the packages to install.
+    /// * `Err` if the preset file is invalid or the preset does not exist.
+    fn resolve_packages(
+        &self,
+        sett: &Settings,
+        preset: Option<String>,
+        preset_file: Option<String>,
+        packages: Option<String>,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let file = match &preset_file {
+            Some(path) => {
+                let content = fs::read_to_string(path)
+                    .map_err(|e| format!("{}: setup: cannot read preset file '{path}': {e}", self.name))?;
+                toml::from_str::<PresetFile>(&content)
+                    .map_err(|e| format!("{}: setup: invalid preset file '{path}': {e}", self.name))?
+            }
+            None => PresetFile::default(),
+        };
+
+        let extra: Vec<String> = packages
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(|p| p.to_string())
+            .collect();
+
+        let name = match (preset, &file.default_preset) {
+            (Some(p), _) => p,
+            (None, Some(p)) => p.clone(),
+            (None, None) if !extra.is_empty() => return Ok(extra),
+            (None, None) => sett.default_preset.clone(),
+        };
+
+        let mut install = file.presets.get(&name).cloned().or_else(|| sett.preset(&name)).ok_or_else(|| {
+            let mut names = sett.preset_names();
+            names.extend(file.presets.keys().cloned());
+            names.sort();
+            names.dedup();
+            format!("{}: setup: unknown preset '{name}'\nAvailable presets: {}", self.name, names.join(", "))
+        })?;
+
+        for pkg in extra {
+            if !install.contains(&pkg) {
+                install.push(pkg);
+            }
+        }
+        Ok(install)
+    }
+
+    /// Finds the minirootfs tarball to install in the mirror index.
+    ///
+    /// # Arguments
+    /// * `mirror` - The mirror to search.
+    /// * `version` - The exact version to look for, or `None` for the latest one.
+    ///
+    /// # Returns
+    /// * `Ok((String, String))` with the tarball URL and its version.
+    /// * `Err` if no matching tarball is found.
+    fn find_rootfs(&self, mirror: &Mirror, version: Option<String>) -> Result<(String, String), Box<dyn Error>> {
+        let url = mirror.get_mirror();
+        let pattern = format!(
+            r"^alpine-minirootfs-([\w.\-]+)-{}\.tar\.gz$",
+            mirror.get_arch()
+        );
+        let re = Regex::new(&pattern).unwrap();
+
+        let mut matches = vec![];
+        for href in Mirror::list_index(&url)? {
+            if let Some(caps) = re.captures(&href) {
+                let version_str = &caps[1];
+                if let Some(key) = self.parse_version_key(version_str) {
+                    matches.push((key, version_str.to_string(), href.to_string()));
+                }
+            }
+        }
+
+        matches.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let selected = match &version {
+            Some(v) => {
+                let found = matches.iter().find(|(_, ver, _)| ver == v);
+                if found.is_none() {
+                    let available: Vec<&str> = matches.iter().map(|(_, ver, _)| ver.as_str()).collect();
+                    return Err(format!(
+                        "Alpine version {v} not found in {url}\nAvailable versions: {}",
+                        available.join(", ")
+                    )
+                    .into());
+                }
+                found
+            }
+            None => matches.last(),
+        };
+
+        match selected {
+            Some((_, version, link)) => {
+                println!("Selected version: {version}");
+                Ok((format!("{url}{link}"), version.clone()))
+            }
+            None => Err("No alpine-minirootfs files found".into()),
+        }
+    }
+
+    /// Downloads a minirootfs tarball into the cache and verifies it.
+    ///
+    /// A downloaded tarball that fails the verification is removed from the
+    /// cache, so it is downloaded again on the next run.
+    ///
+    /// # Arguments
+    /// * `url` - The URL of the tarball.
+    /// * `cache_dir` - The cache directory.
+    /// * `skip_verify` - If true, skip the checksum and signature verification.
+    ///
+    /// # Returns
+    /// * `Ok(String)` with the path of the downloaded tarball.
+    fn download_rootfs(&self, url: String, cache_dir: &str, skip_verify: bool) -> Result<String, Box<dyn Error>> {
+        let link = url.rsplit('/').next().unwrap_or_default().to_string();
+        println!("Link: {url}");
+
+        let dest_dir = download::download_file(url.clone(), cache_dir.to_string(), link.clone())?;
+        let tarball = format!("{dest_dir}/{link}");
+
+        if skip_verify {
+            eprintln!("\x1b[1;33mWarning\x1b[0m: Skipping checksum and signature verification of '{link}'.");
+            return Ok(tarball);
+        }
+
+        verify::fetch_sidecars(&url, Path::new(&tarball))?;
+        if let Err(e) = verify::verify_tarball(Path::new(&tarball)) {
+            let _ = fs::remove_file(&tarball);
+            return Err(format!("{e}\nThe cached file was removed, please run the command again.").into());
+        }
+        Ok(tarball)
+    }
+
+    /// Checks a minirootfs tarball available on the local filesystem.
+    ///
+    /// The checksum and signature files are expected next to the tarball.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the tarball.
+    /// * `skip_verify` - If true, skip the checksum and signature verification.
+    ///
+    /// # Returns
+    /// * `Ok(String)` with the path of the tarball.
+    fn local_rootfs(&self, path: String, skip_verify: bool) -> Result<String, Box<dyn Error>> {
+        if !Path::new(&path).is_file() {
+            return Err(format!("{}: setup: tarball '{path}' not found", self.name).into());
+        }
+        println!("Using local tarball: {path}");
+
+        if skip_verify {
+            eprintln!("\x1b[1;33mWarning\x1b[0m: Skipping checksum and signature verification of '{path}'.");
+        } else {
+            verify::verify_tarball(Path::new(&path))?;
+        }
+        Ok(path)
+    }
+
+    /// Extracts the Alpine version from a minirootfs tarball name.
+    ///
+    /// # Example
+    /// ```
+    /// let v = Setup::tarball_version("alpine-minirootfs-3.19.4-x86_64.tar.gz");
+    /// assert_eq!(v, Some("3.19.4".to_string()));
+    /// ```
+    fn tarball_version(path: &str) -> Option<String> {
+        let name = path.rsplit('/').next()?;
+        let re = Regex::new(r"^alpine-minirootfs-(\d+\.\d+\.\d+[\w.\-]*?)-[a-z0-9_]+\.tar\.gz$").unwrap();
+        re.captures(name).map(|caps| caps[1].to_string())
+    }
+
+    /// Extracts the architecture from a minirootfs tarball name.
+    ///
+    /// # Example
+    /// ```
+    /// let a = Setup::tarball_arch("alpine-minirootfs-3.19.4-aarch64.tar.gz");
+    /// assert_eq!(a, Some("aarch64".to_string()));
+    /// ```
+    fn tarball_arch(path: &str) -> Option<String> {
+        let name = path.rsplit('/').next()?.strip_suffix(".tar.gz")?;
+        qemu::ALPINE_ARCHES
+            .iter()
+            .find(|a| name.starts_with("alpine-minirootfs-") && name.ends_with(&format!("-{a}")))
+            .map(|a| a.to_string())
+    }
+
+    /// Extracts a minirootfs `.tar.gz` archive into the rootfs directory.
     ///
     /// # Arguments
     /// * `file_path` - The path to the `.tar.gz` file to extract.
@@ -189,24 +621,9 @@ impl Setup {
     fn extract_tar_gz(&self, file_path: String, destination: String) -> io::Result<String> {
         let dest_ok = utils::create_dir_with_fallback(destination);
         let save_dest = dest_ok?.to_str().unwrap().to_string();
-        let mut decoder = GzDecoder::new(File::open(file_path)?);
-
-        let mut temp = Vec::new();
-        decoder.read_to_end(&mut temp)?;
-
-        let bar = ProgressBar::new(temp.len() as u64);
-        bar.set_message("Extracting...");
-        bar.set_style(
-            ProgressStyle::with_template(utils::DOWNLOAD_TEMPLATE)
-                .unwrap()
-                .progress_chars("##-"),
-        );
-
-        let reader = bar.wrap_read(io::Cursor::new(temp));
-        let mut archive = Archive::new(reader);
-        archive.unpack(Path::new(save_dest.as_str()))?;
 
-        bar.finish_with_message("Extracted! ");
+        let report = archive::extract_tar_gz(Path::new(&file_path), Path::new(&save_dest))?;
+        report.print();
         Ok(save_dest)
     }
 
@@ -256,20 +673,21 @@ impl Setup {
             return Err(format!("Rootfs directory {target} is already available.\nUse [-r|--reinstall] to reinstall it.").into());
         }
 
-        if let Some(parent) = target_path.parent() {
-            if parent.exists() && fs::metadata(parent).unwrap().permissions().readonly() == false {
-                let test_path = parent.join(".permission_test");
-                match File::create(&test_path) {
-                    Ok(_) => {
-                        fs::remove_file(&test_path)?;
-                        return Ok(());
-                    }
-                    Err(_) => {
-                        eprintln!(
-                            "\x1b[1;33mWarning\x1b[0m: Write access denied for '{}'. Falling back to the default location...",
-                            target
-                        );
-                    }
+        if let Some(parent) = target_path.parent()
+            && parent.exists()
+            && !fs::metadata(parent).unwrap().permissions().readonly()
+        {
+            let test_path = parent.join(".permission_test");
+            match File::create(&test_path) {
+                Ok(_) => {
+                    fs::remove_file(&test_path)?;
+                    return Ok(());
+                }
+                Err(_) => {
+                    eprintln!(
+                        "\x1b[1;33mWarning\x1b[0m: Write access denied for '{}'. Falling back to the default location...",
+                        target
+                    );
                 }
             }
         }
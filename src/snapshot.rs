@@ -0,0 +1,413 @@
+use crate::archive;
+use crate::{parse_key_value, verify};
+use crate::registry::Registry;
+use crate::settings::Settings;
+use crate::utils::{self, _parse_key_value};
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use std::{fs, io, process};
+use tar::{Builder, EntryType, Header, HeaderMode};
+
+/// Extension of the snapshot archives.
+const SNAPSHOT_EXT: &str = "tar.gz";
+
+pub struct Snapshot {
+    name: String,
+    remaining_args: Vec<String>,
+}
+
+impl Snapshot {
+    pub fn new(name: String, remaining_args: Vec<String>) -> Self {
+        Snapshot {
+            name,
+            remaining_args,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<_> = self.remaining_args.clone().into();
+        let sett = Settings::load_or_create();
+        let mut rootfs_dir: String = sett.set_rootfs();
+        let (mut action, mut snap_name): (Option<String>, Option<String>) = (None, None);
+
+        while let Some(arg) = args.pop_front() {
+            match arg.as_str() {
+                a if a.starts_with("--env=") => {
+                    let env = parse_key_value!("snapshot", "name", arg)?.unwrap();
+                    rootfs_dir = Registry::resolve(&env)?;
+                }
+                "--env" => {
+                    let env = parse_key_value!("snapshot", "name", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                    rootfs_dir = Registry::resolve(&env)?;
+                }
+                a if a.starts_with("--rootfs=") => {
+                    rootfs_dir = parse_key_value!("snapshot", "directory", arg)?.unwrap();
+                }
+                "-R" | "--rootfs" => {
+                    rootfs_dir = parse_key_value!("snapshot", "directory", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                }
+                a if a.starts_with('-') => {
+                    return Err(format!("{c}: snapshot: invalid argument '{arg}'\nUse '{c} --help' to see available options.", c = self.name).into())
+                }
+                _ if action.is_none() => action = Some(arg),
+                _ if snap_name.is_none() => snap_name = Some(arg),
+                _ => {
+                    return Err(format!("{c}: snapshot: invalid argument '{arg}'\nUse '{c} --help' to see available options.", c = self.name).into())
+                }
+            }
+        }
+
+        if let Some(n) = &snap_name
+            && (n.contains('/') || n.starts_with('.'))
+        {
+            return Err(format!("{}: snapshot: invalid snapshot name '{n}'", self.name).into());
+        }
+
+        match action.as_deref() {
+            Some("create") => {
+                utils::check_rootfs_exists(self.name.clone(), rootfs_dir.clone())?;
+                let snap_name = snap_name.unwrap_or_else(|| {
+                    utils::format_date(utils::now()).replace([' ', ':'], "-")
+                });
+                Self::create(&rootfs_dir, &snap_name)?;
+                Ok(())
+            }
+            Some("list") | Some("ls") => self.list(&rootfs_dir),
+            Some("restore") => {
+                let snap_name = self.require_name(snap_name, "restore")?;
+                Self::restore(&rootfs_dir, &snap_name)
+            }
+            Some("rm") | Some("remove") => {
+                let snap_name = self.require_name(snap_name, "rm")?;
+                let path = Self::snapshot_path(&rootfs_dir, &snap_name);
+                if !path.is_file() {
+                    return Err(format!("{}: snapshot: snapshot '{snap_name}' not found", self.name).into());
+                }
+                fs::remove_file(&path)?;
+                println!("Snapshot '{snap_name}' removed.");
+                Ok(())
+            }
+            Some(other) => Err(format!(
+                "{c}: snapshot: invalid argument '{other}'\nUse '{c} --help' to see available options.",
+                c = self.name
+            )
+            .into()),
+            None => Err(format!(
+                "{c}: snapshot: no parameter specified\nUse '{c} --help' to see available options.",
+                c = self.name
+            )
+            .into()),
+        }
+    }
+
+    /// Creates a compressed snapshot of a rootfs directory.
+    ///
+    /// The archive keeps permissions, ownership, symlinks, special files and
+    /// extended attributes. It is written to a `.part` file and only renamed
+    /// once complete, so an interrupted snapshot is never listed or restored.
+    ///
+    /// # Arguments
+    /// * `rootfs` - The rootfs directory.
+    /// * `snap_name` - The name of the snapshot.
+    ///
+    /// # Returns
+    /// * `Ok(PathBuf)` with the path of the snapshot archive.
+    /// * `Err` if the snapshot already exists or the archive cannot be written.
+    ///
+    /// # Example
+    /// ```
+    /// Snapshot::create("/home/user/.ALPack", "before-upgrade")?;
+    /// ```
+    pub fn create(rootfs: &str, snap_name: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let dest = Self::snapshot_path(rootfs, snap_name);
+        if dest.exists() {
+            return Err(format!("Snapshot '{snap_name}' already exists").into());
+        }
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let part = PathBuf::from(format!("{}.part", dest.display()));
+        let bar = ProgressBar::new(utils::dir_size(Path::new(rootfs)));
+        bar.set_message("Creating snapshot...");
+        bar.set_style(
+            ProgressStyle::with_template(utils::DOWNLOAD_TEMPLATE)
+                .unwrap()
+                .progress_chars("##-"),
+        );
+
+        let result = (|| -> io::Result<()> {
+            let encoder = GzEncoder::new(BufWriter::new(File::create(&part)?), Compression::default());
+            let mut builder = Builder::new(encoder);
+            builder.follow_symlinks(false);
+            builder.mode(HeaderMode::Complete);
+
+            Self::append_tree(&mut builder, Path::new(rootfs), Path::new("."), &bar)?;
+
+            builder.into_inner()?.finish()?.into_inner()?.sync_all()?;
+            fs::rename(&part, &dest)
+        })();
+
+        if let Err(e) = result {
+            bar.abandon_with_message("Failed!");
+            let _ = fs::remove_file(&part);
+            return Err(format!("Failed to create snapshot '{snap_name}': {e}").into());
+        }
+
+        bar.finish_with_message("Snapshot created!");
+        println!("Snapshot saved to: {}", dest.display());
+        Ok(dest)
+    }
+
+    /// Restores a rootfs directory from a snapshot.
+    ///
+    /// The snapshot is first extracted next to the rootfs; the current rootfs
+    /// is only replaced once every entry of the archive was restored, so a
+    /// truncated or corrupted snapshot leaves it untouched.
+    ///
+    /// # Arguments
+    /// * `rootfs` - The rootfs directory.
+    /// * `snap_name` - The name of the snapshot.
+    ///
+    /// # Returns
+    /// * `Ok(())` if the rootfs was restored.
+    /// * `Err` if the snapshot is missing or invalid.
+    ///
+    /// # Example
+    /// ```
+    /// Snapshot::restore("/home/user/.ALPack", "before-upgrade")?;
+    /// ```
+    pub fn restore(rootfs: &str, snap_name: &str) -> Result<(), Box<dyn Error>> {
+        let source = Self::snapshot_path(rootfs, snap_name);
+        if !source.is_file() {
+            return Err(format!("Snapshot '{snap_name}' not found").into());
+        }
+
+        let rootfs = Registry::normalize(rootfs);
+        let staging = PathBuf::from(format!("{rootfs}.restore-{}", process::id()));
+        let backup = PathBuf::from(format!("{rootfs}.old-{}", process::id()));
+        fs::create_dir_all(&staging)?;
+
+        println!("Restoring snapshot '{snap_name}'...");
+        // Entries restored without their metadata (ownership a non-root user
+        // cannot set, unsupported xattrs...) are only reported as warnings.
+        let result = archive::extract_tar_gz(&source, &staging).and_then(|report| {
+            report.print();
+            if !report.failures.is_empty() {
+                return Err(io::Error::other(format!("{} of {} entries could not be restored", report.failures.len(), report.entries)));
+            }
+            Self::check_stream_end(&source)
+        });
+
+        if let Err(e) = result {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(format!("Snapshot '{snap_name}' is damaged, the rootfs was not modified: {e}").into());
+        }
+
+        let had_rootfs = Path::new(&rootfs).exists();
+        if had_rootfs {
+            fs::rename(&rootfs, &backup)?;
+        }
+        if let Err(e) = fs::rename(&staging, &rootfs) {
+            if had_rootfs {
+                fs::rename(&backup, &rootfs)?;
+            }
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e.into());
+        }
+        if had_rootfs {
+            fs::remove_dir_all(&backup)?;
+        }
+
+        println!("Snapshot '{snap_name}' restored to {rootfs}.");
+        Ok(())
+    }
+
+    /// Lists the snapshots of a rootfs.
+    fn list(&self, rootfs: &str) -> Result<(), Box<dyn Error>> {
+        let dir = Self::snapshot_dir(rootfs);
+        let mut snapshots: Vec<(String, u64, u64)> = fs::read_dir(&dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter_map(|e| {
+                        let name = e.file_name().to_string_lossy().to_string();
+                        let name = name.strip_suffix(&format!(".{SNAPSHOT_EXT}"))?.to_string();
+                        let meta = e.metadata().ok()?;
+                        let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs();
+                        Some((name, meta.len(), mtime))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if snapshots.is_empty() {
+            println!("No snapshot found for {rootfs}.");
+            return Ok(());
+        }
+
+        snapshots.sort_by_key(|s| s.2);
+        let width = snapshots.iter().map(|s| s.0.len()).max().unwrap_or(0).max(4);
+
+        println!("{}", utils::separator_line());
+        println!("{:<width$}  {:>10}  CREATED", "NAME", "SIZE");
+        println!("{}", utils::separator_line());
+        for (name, size, mtime) in snapshots {
+            println!(
+                "{name:<width$}  {:>10}  {}",
+                HumanBytes(size).to_string(),
+                utils::format_date(mtime)
+            );
+        }
+        println!("{}", utils::separator_line());
+        Ok(())
+    }
+
+    /// Returns the directory holding the snapshots of a rootfs.
+    ///
+    /// Snapshots are grouped by the canonical path of the rootfs, as
+    /// `<directory name>-<hash of the path>`, so two rootfs trees never share
+    /// their snapshots.
+    pub fn snapshot_dir(rootfs: &str) -> PathBuf {
+        let path = fs::canonicalize(rootfs)
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|_| Registry::normalize(rootfs));
+        let name = Path::new(&path)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| "rootfs".to_string());
+        let hash = verify::hex(&Sha256::digest(path.as_bytes()));
+
+        PathBuf::from(Settings::load_or_create().set_cache_dir())
+            .join("snapshots")
+            .join(format!("{name}-{}", &hash[..16]))
+    }
+
+    /// Returns the path of a snapshot archive.
+    pub fn snapshot_path(rootfs: &str, snap_name: &str) -> PathBuf {
+        Self::snapshot_dir(rootfs).join(format!("{snap_name}.{SNAPSHOT_EXT}"))
+    }
+
+    /// Recursively appends a directory tree to the archive.
+    ///
+    /// Directories are appended before their contents; extended attributes
+    /// are stored as PAX `SCHILY.xattr` records and sockets are skipped.
+    fn append_tree<W: io::Write>(
+        builder: &mut Builder<W>,
+        path: &Path,
+        name: &Path,
+        bar: &ProgressBar,
+    ) -> io::Result<()> {
+        let meta = fs::symlink_metadata(path)?;
+        let file_type = meta.file_type();
+
+        if file_type.is_socket() {
+            return Ok(());
+        }
+
+        Self::append_xattrs(builder, path)?;
+
+        if file_type.is_fifo() || file_type.is_char_device() || file_type.is_block_device() {
+            let mut header = Header::new_gnu();
+            header.set_metadata_in_mode(&meta, HeaderMode::Complete);
+            header.set_entry_type(match () {
+                _ if file_type.is_fifo() => EntryType::Fifo,
+                _ if file_type.is_char_device() => EntryType::Char,
+                _ => EntryType::Block,
+            });
+            let dev = meta.rdev();
+            header.set_device_major(libc::major(dev))?;
+            header.set_device_minor(libc::minor(dev))?;
+            header.set_size(0);
+            builder.append_data(&mut header, name, io::empty())?;
+        } else {
+            builder.append_path_with_name(path, name)?;
+        }
+
+        if file_type.is_file() {
+            bar.inc(meta.len());
+        }
+
+        if file_type.is_dir() {
+            let mut entries: Vec<_> = fs::read_dir(path)?.flatten().collect();
+            entries.sort_by_key(|e| e.file_name());
+            for entry in entries {
+                Self::append_tree(builder, &entry.path(), &name.join(entry.file_name()), bar)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends a PAX header holding the extended attributes of `path`, if any.
+    pub fn append_xattrs<W: io::Write>(builder: &mut Builder<W>, path: &Path) -> io::Result<()> {
+        let Ok(names) = xattr::list(path) else {
+            return Ok(());
+        };
+
+        let mut data = Vec::new();
+        for attr in names {
+            if let Ok(Some(value)) = xattr::get(path, &attr) {
+                let mut key = b"SCHILY.xattr.".to_vec();
+                key.extend_from_slice(attr.as_encoded_bytes());
+                data.extend(Self::pax_record(&key, &value));
+            }
+        }
+
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let mut header = Header::new_ustar();
+        header.set_entry_type(EntryType::XHeader);
+        header.set_mode(0o644);
+        header.set_size(data.len() as u64);
+        builder.append_data(&mut header, "././@PaxHeader", data.as_slice())
+    }
+
+    /// Encodes a PAX extended header record (`<len> <key>=<value>\n`).
+    fn pax_record(key: &[u8], value: &[u8]) -> Vec<u8> {
+        let body = key.len() + value.len() + 3;
+        let mut len = body + body.to_string().len();
+        if len.to_string().len() != body.to_string().len() {
+            len += 1;
+        }
+
+        let mut record = format!("{len} ").into_bytes();
+        record.extend_from_slice(key);
+        record.push(b'=');
+        record.extend_from_slice(value);
+        record.push(b'\n');
+        record
+    }
+
+    /// Checks that a gzip stream was read up to its end.
+    ///
+    /// `tar` stops at the end-of-archive marker, so this also reads the
+    /// trailing data to make sure the gzip checksum is verified.
+    fn check_stream_end(source: &Path) -> io::Result<()> {
+        io::copy(&mut GzDecoder::new(File::open(source)?), &mut io::sink())?;
+        Ok(())
+    }
+
+    /// Returns the snapshot name or an error if it was not given.
+    fn require_name(&self, snap_name: Option<String>, sub: &str) -> Result<String, Box<dyn Error>> {
+        snap_name.ok_or_else(|| {
+            format!(
+                "{c}: snapshot: {sub} requires a <name> as argument.\nUsage: {c} snapshot {sub} <name>",
+                c = self.name
+            )
+            .into()
+        })
+    }
+}
@@ -0,0 +1,152 @@
+use crate::command::Command;
+use crate::mirror::Mirror;
+use crate::registry::{Registry, RootfsEntry};
+use crate::settings::Settings;
+use crate::snapshot::Snapshot;
+use crate::utils::{self, _parse_key_value};
+use crate::{parse_key_value, qemu};
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+pub struct Upgrade {
+    name: String,
+    remaining_args: Vec<String>,
+}
+
+impl Upgrade {
+    pub fn new(name: String, remaining_args: Vec<String>) -> Self {
+        Upgrade {
+            name,
+            remaining_args,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<_> = self.remaining_args.clone().into();
+        let sett = Settings::load_or_create();
+        let mut rootfs_dir: String = sett.set_rootfs();
+        let (mut to, mut use_mirror): (Option<String>, Option<String>) = (None, None);
+
+        while let Some(arg) = args.pop_front() {
+            match arg.as_str() {
+                a if a.starts_with("--to=") => {
+                    to = parse_key_value!("upgrade-release", "release", arg)?;
+                }
+                "--to" => {
+                    to = parse_key_value!("upgrade-release", "release", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with("--mirror=") => {
+                    use_mirror = parse_key_value!("upgrade-release", "url", arg)?;
+                }
+                "--mirror" => {
+                    use_mirror = parse_key_value!("upgrade-release", "url", arg, args.pop_front().unwrap_or_default())?;
+                }
+                a if a.starts_with("--env=") => {
+                    let env = parse_key_value!("upgrade-release", "name", arg)?.unwrap();
+                    rootfs_dir = Registry::resolve(&env)?;
+                }
+                "--env" => {
+                    let env = parse_key_value!("upgrade-release", "name", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                    rootfs_dir = Registry::resolve(&env)?;
+                }
+                a if a.starts_with("--rootfs=") => {
+                    rootfs_dir = parse_key_value!("upgrade-release", "directory", arg)?.unwrap();
+                }
+                "-R" | "--rootfs" => {
+                    rootfs_dir = parse_key_value!("upgrade-release", "directory", arg, args.pop_front().unwrap_or_default())?.unwrap();
+                }
+                _ => {
+                    return Err(format!("{c}: upgrade-release: invalid argument '{arg}'\nUse '{c} --help' to see available options.", c = self.name).into())
+                }
+            }
+        }
+
+        let to = to.ok_or_else(|| {
+            format!(
+                "{c}: upgrade-release: --to requires a <BRANCH> as argument.\nUsage: {c} upgrade-release --to <BRANCH>",
+                c = self.name
+            )
+        })?;
+        if !Mirror::is_valid_release(&to) {
+            return Err(format!("{}: upgrade-release: invalid release '{to}', expected 'latest-stable', 'edge' or 'vX.Y'", self.name).into());
+        }
+
+        utils::check_rootfs_exists(self.name.clone(), rootfs_dir.clone())?;
+        let registry = Registry::load();
+        let entry = registry
+            .find_by_path(&rootfs_dir)
+            .cloned()
+            .unwrap_or_else(|| RootfsEntry::new(registry.name_for_path(&rootfs_dir), rootfs_dir.clone()));
+
+        let from = match entry.release.is_empty() {
+            true => "unknown".to_string(),
+            false => entry.release.clone(),
+        };
+        if from == to {
+            return Err(format!("{}: upgrade-release: the rootfs already tracks {to}", self.name).into());
+        }
+
+        let mut mirror = Mirror::new(use_mirror, Some(to.clone()));
+        mirror.set_arch(Some(qemu::rootfs_arch(&rootfs_dir)));
+        mirror.run()?;
+        Mirror::check_release(&mirror.get_base(), &to)
+            .map_err(|e| format!("{}: upgrade-release: {e}", self.name))?;
+
+        let snap_name = format!(
+            "pre-upgrade-{from}-to-{to}-{}",
+            utils::format_date(utils::now()).replace([' ', ':'], "-")
+        );
+        println!("Saving snapshot '{snap_name}' before the upgrade...");
+        Snapshot::create(&rootfs_dir, &snap_name)?;
+
+        println!("Upgrading {rootfs_dir} from {from} to {to}...");
+        if let Err(e) = self.upgrade(&rootfs_dir, &mut mirror, &entry) {
+            eprintln!("\x1b[1;31mUpgrade failed\x1b[0m: {e}\nRolling back to snapshot '{snap_name}'...");
+            Snapshot::restore(&rootfs_dir, &snap_name)?;
+            return Err(format!("{}: upgrade-release: upgrade to {to} failed, the rootfs was restored", self.name).into());
+        }
+
+        let version = fs::read_to_string(Path::new(&rootfs_dir).join("etc/alpine-release"))
+            .map(|v| v.trim().to_string())
+            .unwrap_or_default();
+
+        let mut registry = Registry::load();
+        let mut entry = entry;
+        entry.release = to.clone();
+        entry.version = version.clone();
+        registry.upsert(entry);
+        registry.save()?;
+
+        println!(
+            "Rootfs upgraded to Alpine Linux {version} ({to}).\nThe snapshot '{snap_name}' was kept, remove it with '{} snapshot rm {snap_name}'.",
+            self.name
+        );
+        Ok(())
+    }
+
+    /// Rewrites the repositories of the rootfs and upgrades every package.
+    ///
+    /// # Returns
+    /// * `Ok(())` if `apk upgrade --available` succeeded.
+    /// * `Err` if the repositories cannot be written or apk failed.
+    fn upgrade(&self, rootfs: &str, mirror: &mut Mirror, entry: &RootfsEntry) -> Result<(), Box<dyn Error>> {
+        mirror.set_extra_repositories(entry.repositories.iter().map(|r| r.line()).collect());
+        fs::write(Path::new(rootfs).join("etc/apk/repositories"), mirror.get_repository())?;
+
+        let code = Command::run(
+            rootfs.to_string(),
+            Vec::new(),
+            Some("apk update && apk upgrade --available".to_string()),
+            true,
+            true,
+            false,
+        )?;
+        if code != 0 {
+            return Err(format!("apk exited with code {code}").into());
+        }
+        Ok(())
+    }
+}
@@ -1,15 +1,15 @@
+use crate::download;
 use crate::settings::Settings;
-use indicatif::{ProgressBar, ProgressStyle};
 use std::error::Error;
-use std::fs::File;
 use std::ops::Add;
-use std::os::unix::fs::PermissionsExt;
+use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
 use std::path::{Path, PathBuf};
 use std::{env, fs, io};
 use walkdir_minimal::WalkDir;
 use which::which;
 
 pub const DOWNLOAD_TEMPLATE: &str = "{msg} {spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})";
+pub const SPINNER_TEMPLATE: &str = "{msg} {spinner:.green} [{elapsed_precise}] {bytes} ({bytes_per_sec})";
 
 #[macro_export]
 macro_rules! parse_key_value {
@@ -144,6 +144,7 @@ pub fn check_rootfs_exists(cmd: String, path: String) -> Result<(), Box<dyn Erro
 /// let box_str = get_cmd_box("$ ALPack setup");
 /// println!("{}", box_str);
 /// ```
+#[allow(clippy::unnecessary_lazy_evaluations)]
 pub fn get_cmd_box(
     name: String,
     repeat: Option<usize>,
@@ -199,6 +200,7 @@ pub fn separator_line() -> String {
 /// let dst = std::path::Path::new("/home/user/output");
 /// copy_dir_recursive(src, dst).expect("Failed to copy directory");
 /// ```
+#[allow(clippy::io_other_error)]
 pub fn copy_dir_recursive(src: &Path, dst: &Path) -> io::Result<()> {
     println!("copy {} to {}", src.display(), dst.display());
     let dir_name = src
@@ -223,6 +225,99 @@ pub fn copy_dir_recursive(src: &Path, dst: &Path) -> io::Result<()> {
     Ok(())
 }
 
+/// Copies a rootfs tree, preserving symlinks and permissions.
+///
+/// Unlike `copy_dir_recursive`, symlinks are recreated instead of followed,
+/// so absolute links inside the rootfs keep pointing inside it. Special files
+/// (device nodes, sockets, fifos) are skipped.
+///
+/// # Arguments
+/// * `src` - The source path.
+/// * `dst` - The destination path, created by the copy.
+///
+/// # Returns
+/// * `io::Result<()>` - Ok on success, or an error if the operation fails.
+///
+/// # Example
+/// ```
+/// copy_tree(Path::new("/home/user/.ALPack"), Path::new("/home/user/.ALPack-copy"))?;
+/// ```
+pub fn copy_tree(src: &Path, dst: &Path) -> io::Result<()> {
+    let meta = fs::symlink_metadata(src)?;
+    let file_type = meta.file_type();
+
+    if file_type.is_symlink() {
+        symlink(fs::read_link(src)?, dst)?;
+    } else if file_type.is_dir() {
+        fs::create_dir_all(dst)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &dst.join(entry.file_name()))?;
+        }
+        fs::set_permissions(dst, meta.permissions())?;
+    } else if file_type.is_file() {
+        fs::copy(src, dst)?;
+    } else {
+        eprintln!("\x1b[1;33mWarning\x1b[0m: Skipping special file '{}'", src.display());
+    }
+    Ok(())
+}
+
+/// Computes the disk usage of a directory tree without following symlinks.
+///
+/// # Arguments
+/// * `path` - The directory to measure.
+///
+/// # Returns
+/// * `u64` - The total size in bytes of the files found (unreadable entries are ignored).
+///
+/// # Example
+/// ```
+/// println!("{}", indicatif::HumanBytes(dir_size(Path::new("/home/user/.ALPack"))));
+/// ```
+pub fn dir_size(path: &Path) -> u64 {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    fs::read_dir(path)
+        .map(|entries| entries.flatten().map(|e| dir_size(&e.path())).sum())
+        .unwrap_or(0)
+}
+
+/// Returns the current Unix timestamp, in seconds.
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Formats a Unix timestamp as a `YYYY-MM-DD HH:MM` UTC date.
+///
+/// # Example
+/// ```
+/// assert_eq!(format_date(0), "1970-01-01 00:00");
+/// ```
+pub fn format_date(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let (hour, min) = ((secs % 86400) / 3600, (secs % 3600) / 60);
+
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02} {hour:02}:{min:02}")
+}
+
 /// Attempts to create the target directory, falling back to a default path if permission is denied.
 ///
 /// # Parameters
@@ -237,6 +332,7 @@ pub fn copy_dir_recursive(src: &Path, dst: &Path) -> io::Result<()> {
 /// let dir = create_dir_with_fallback("/opt/some_dir".to_string())?;
 /// println!("Directory created or reused: {}", dir.display());
 /// ```
+#[allow(clippy::needless_borrows_for_generic_args)]
 pub fn create_dir_with_fallback(target: String) -> io::Result<PathBuf> {
     let target_path = Path::new(target.as_str());
 
@@ -257,60 +353,101 @@ pub fn create_dir_with_fallback(target: String) -> io::Result<PathBuf> {
     Ok(PathBuf::from(fallback_path))
 }
 
-/// Downloads a file from the specified URL and saves it to the destination folder.
+/// Returns the local filesystem path referenced by a `file://` URL or an absolute path.
 ///
-/// # Arguments
-/// * `url` - The URL of the file to be downloaded.
-/// * `dest` - The directory where the file will be saved.
-/// * `filename` - The name of the file to save.
+/// # Returns
+/// * `Some(&str)` with the path if the URL points to the local filesystem.
+/// * `None` for remote URLs.
+///
+/// # Examples
+/// ```
+/// assert_eq!(local_path("file:///srv/alpine/"), Some("/srv/alpine/"));
+/// assert_eq!(local_path("https://dl-cdn.alpinelinux.org/alpine/"), None);
+/// ```
+pub fn local_path(url: &str) -> Option<&str> {
+    match url.strip_prefix("file://") {
+        Some(path) => Some(path),
+        None if url.starts_with('/') => Some(url),
+        None => None,
+    }
+}
+
+/// Quotes a word for `/bin/sh`, so it is passed as a single argument.
+///
+/// # Examples
+/// ```
+/// assert_eq!(shell_quote("file.txt"), "file.txt");
+/// assert_eq!(shell_quote("my file's.txt"), "'my file'\\''s.txt'");
+/// ```
+pub fn shell_quote(word: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-./=:,@%+".contains(c);
+    match !word.is_empty() && word.chars().all(safe) {
+        true => word.to_string(),
+        false => format!("'{}'", word.replace('\'', "'\\''")),
+    }
+}
+
+/// Splits a line into arguments like `/bin/sh` does, without expansions.
+///
+/// Words are separated by whitespace; single quotes, double quotes and
+/// backslashes keep spaces inside a word.
 ///
 /// # Returns
-/// * `Ok(String)` - The full path of the saved file.
-/// * `Err`: An `io::Error` if the download or save fails.
+/// * `Ok(Vec<String>)` with the arguments.
+/// * `Err` if a quote is not closed.
 ///
 /// # Examples
 /// ```
-/// let saved_path = download_file("https://url.com/file.tar.gz".to_string(),
-///     "/tmp".to_string(), "file.tar.gz".to_string())?;
-/// println!("File saved to: {}", saved_path);
+/// assert_eq!(split_args("--bind '/my dir' /dir")?, ["--bind", "/my dir", "/dir"]);
 /// ```
-pub fn download_file(url: String, dest: String, filename: String) -> io::Result<String> {
-    let dest_ok = create_dir_with_fallback(dest);
-    let save_dest = dest_ok?.to_str().unwrap().to_string();
-    let save_file = format!("{save_dest}/{filename}");
-
-    if Path::new(&save_file).exists() {
-        println!("File '{}' already exists, skipping download.", filename);
-        return Ok(save_dest);
+pub fn split_args(line: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let (mut args, mut word, mut in_word) = (Vec::new(), String::new(), false);
+    let mut quote = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') => match chars.next() {
+                Some(n @ ('"' | '\\' | '$' | '`')) => word.push(n),
+                Some(n) => word.extend(['\\', n]),
+                None => word.push('\\'),
+            },
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => (quote, in_word) = (Some(c), true),
+            (None, '\\') => {
+                word.extend(chars.next());
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    args.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
     }
 
-    println!("Saving file to: {save_file}");
-    let resp = ureq::get(url)
-        .call()
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-    let length = resp
-        .headers()
-        .get("Content-Length")
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .parse()
-        .unwrap();
-
-    let bar = ProgressBar::new(length);
-    bar.set_message("Downloading...");
-    bar.set_style(
-        ProgressStyle::with_template(DOWNLOAD_TEMPLATE)
-            .unwrap()
-            .progress_chars("##-"),
-    );
+    if quote.is_some() {
+        return Err(format!("unterminated quote in '{line}'").into());
+    }
+    if in_word {
+        args.push(word);
+    }
+    Ok(args)
+}
 
-    io::copy(
-        &mut bar.wrap_read(resp.into_body().into_reader()),
-        &mut File::create(save_file)?,
-    )?;
-    bar.finish_with_message("Downloaded!");
-    Ok(save_dest)
+/// Checks whether the current process runs with root privileges.
+///
+/// # Returns
+/// * `true` if the effective user is root.
+/// * `false` otherwise.
+pub fn is_root() -> bool {
+    fs::metadata("/proc/self").map(|m| m.uid() == 0).unwrap_or(false)
 }
 
 /// Returns the path to the user's local binary directory (`~/.local/bin`).
@@ -360,10 +497,30 @@ fn binary_url(cmd: &str) -> Option<&'static str> {
 /// # Returns
 /// * `true` if the architecture is `x86_64`.
 /// * `false` otherwise.
-fn is_x86_64() -> bool {
+pub fn is_x86_64() -> bool {
     env::consts::ARCH == "x86_64"
 }
 
+/// Looks up a rootfs command in `PATH` and then in `~/.local/bin`, without downloading it.
+///
+/// # Arguments
+/// * `cmd_rootfs` - The name of the rootfs command (`"proot"` or `"bwrap"`).
+///
+/// # Returns
+/// * `Some(PathBuf)` with the path of the executable if it was found.
+/// * `None` otherwise.
+///
+/// # Example
+/// ```
+/// let bwrap = find_rootfs_command("bwrap"); // Some("/usr/bin/bwrap")
+/// ```
+pub fn find_rootfs_command(cmd_rootfs: &str) -> Option<PathBuf> {
+    if let Ok(path) = which(cmd_rootfs) {
+        return Some(path);
+    }
+    Some(local_bin_dir().join(cmd_rootfs)).filter(|p| p.exists())
+}
+
 /// Verifies the availability of the specified rootfs command and downloads it if necessary.
 ///
 /// Only x86_64 architecture is supported for automatic downloads. On other
@@ -384,16 +541,11 @@ fn is_x86_64() -> bool {
 /// Returns `io::ErrorKind::Unsupported` if the command is not found and
 /// no binary is available for the current architecture.
 pub fn verify_and_download_rootfs_command(cmd_rootfs: &str) -> io::Result<PathBuf> {
-    if let Some(path) = which(cmd_rootfs).ok() {
+    if let Some(path) = find_rootfs_command(cmd_rootfs) {
         return Ok(path);
     }
 
     let local_dir = local_bin_dir();
-    let local_path = local_dir.join(cmd_rootfs);
-
-    if local_path.exists() {
-        return Ok(local_path);
-    }
 
     if !is_x86_64() {
         return Err(io::Error::new(
@@ -410,13 +562,13 @@ pub fn verify_and_download_rootfs_command(cmd_rootfs: &str) -> io::Result<PathBu
 
     fs::create_dir_all(&local_dir)?;
 
-    let downloaded = download_file(
+    let downloaded = download::download_file(
         url.to_string(),
         local_dir.to_string_lossy().to_string(),
         cmd_rootfs.to_string(),
     )?;
 
-    let downloaded_path = PathBuf::from(downloaded);
+    let downloaded_path = PathBuf::from(downloaded).join(cmd_rootfs);
 
     make_executable(&downloaded_path)?;
 
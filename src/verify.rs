@@ -0,0 +1,326 @@
+use crate::settings::Settings;
+use crate::download;
+
+use sha2::{Digest, Sha256, Sha512};
+use std::error::Error;
+use std::fs::File;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command as StdCommand, Stdio};
+use std::{env, fs, io, process};
+use which::which;
+
+/// Fingerprints of the keys used by Alpine Linux to sign release images.
+///
+/// Only signatures made by one of these keys (or by a key the user placed
+/// in `~/.config/ALPack/keys`) are accepted.
+pub const ALPINE_KEY_FINGERPRINTS: &[&str] = &["0482D84022F52DF1C4E7CD43293ACD0907D9495A"];
+
+/// Public key matching `ALPINE_KEY_FINGERPRINTS`, from <https://alpinelinux.org/keys/ncopa.asc>.
+///
+/// It is bundled in the binary, so the key is never fetched from the network.
+const ALPINE_KEY: &str = include_str!("../keys/ncopa.asc");
+
+/// Sidecar files published by Alpine next to every release tarball.
+pub const SIDECAR_EXTENSIONS: &[&str] = &["sha256", "sha512", "asc"];
+
+/// Downloads the checksum and signature files published next to a tarball.
+///
+/// Existing sidecar files are always replaced, so a cached tarball is
+/// checked against what the mirror currently publishes.
+///
+/// # Arguments
+/// * `url` - The URL of the tarball on the mirror.
+/// * `tarball` - The local path of the tarball.
+///
+/// # Returns
+/// * `Ok(())` if at least one checksum file was retrieved.
+/// * `Err` if no checksum could be downloaded, with the download error when
+///   the mirror could not be reached.
+///
+/// # Example
+/// ```
+/// fetch_sidecars("https://mirror/alpine.tar.gz", Path::new("/tmp/alpine.tar.gz"))?;
+/// ```
+pub fn fetch_sidecars(url: &str, tarball: &Path) -> Result<(), Box<dyn Error>> {
+    let mut found = false;
+    let mut failure = None;
+
+    for ext in SIDECAR_EXTENSIONS {
+        let dest = sidecar_path(tarball, ext);
+        match download::fetch_text(format!("{url}.{ext}")) {
+            Ok(content) => {
+                fs::write(&dest, content)?;
+                found |= *ext != "asc";
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&dest);
+                eprintln!("\x1b[1;33mWarning\x1b[0m: Failed to download '{url}.{ext}': {e}");
+                failure = Some(e);
+            }
+        }
+    }
+
+    if !found {
+        return match failure {
+            Some(e) if download::is_network_error(&e) => Err(e.into()),
+            _ => Err(format!("No checksum file found for '{url}'").into()),
+        };
+    }
+    Ok(())
+}
+
+/// Verifies a tarball against its checksum and signature sidecar files.
+///
+/// Every `.sha256` and `.sha512` file found next to the tarball must match,
+/// and the `.asc` signature must be valid and made by a trusted key.
+///
+/// # Arguments
+/// * `tarball` - Path to the tarball to verify.
+///
+/// # Returns
+/// * `Ok(())` if the tarball is authentic.
+/// * `Err` describing the first failed check.
+///
+/// # Example
+/// ```
+/// verify_tarball(Path::new("/tmp/alpine-minirootfs-3.22.1-x86_64.tar.gz"))?;
+/// ```
+pub fn verify_tarball(tarball: &Path) -> Result<(), Box<dyn Error>> {
+    let name = tarball.file_name().unwrap_or_default().to_string_lossy().to_string();
+    println!("Verifying {name}...");
+
+    let mut checked = 0;
+    for ext in ["sha256", "sha512"] {
+        let sidecar = sidecar_path(tarball, ext);
+        if !sidecar.is_file() {
+            continue;
+        }
+
+        let content = fs::read_to_string(&sidecar)?;
+        let expected = content
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        let actual = match ext {
+            "sha256" => file_digest::<Sha256>(tarball)?,
+            _ => file_digest::<Sha512>(tarball)?,
+        };
+
+        if expected != actual {
+            return Err(format!(
+                "{ext} checksum mismatch for '{name}'\n  expected: {expected}\n  found:    {actual}"
+            )
+            .into());
+        }
+        println!("  {ext}: OK");
+        checked += 1;
+    }
+
+    if checked == 0 {
+        return Err(format!("No checksum file found for '{name}', refusing to continue.\nUse '--skip-verify' to bypass the verification.").into());
+    }
+
+    let signature = sidecar_path(tarball, "asc");
+    if !signature.is_file() {
+        return Err(format!("No signature file found for '{name}', refusing to continue.\nUse '--skip-verify' to bypass the verification.").into());
+    }
+
+    let signer = verify_signature(tarball, &signature)?;
+    println!("  signature: OK ({signer})");
+    Ok(())
+}
+
+/// Returns the path of a sidecar file for the given tarball.
+///
+/// # Example
+/// ```
+/// let sum = sidecar_path(Path::new("/tmp/a.tar.gz"), "sha256");
+/// assert_eq!(sum, PathBuf::from("/tmp/a.tar.gz.sha256"));
+/// ```
+pub fn sidecar_path(tarball: &Path, ext: &str) -> PathBuf {
+    let mut path = tarball.as_os_str().to_owned();
+    path.push(format!(".{ext}"));
+    PathBuf::from(path)
+}
+
+/// Computes the hexadecimal digest of a file.
+///
+/// # Arguments
+/// * `path` - Path to the file to hash.
+///
+/// # Returns
+/// * `Ok(String)` with the lowercase hexadecimal digest.
+/// * `Err(io::Error)` if the file cannot be read.
+pub fn file_digest<D: Digest + io::Write>(path: &Path) -> io::Result<String> {
+    let mut hasher = D::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex(&hasher.finalize()))
+}
+
+/// Formats bytes as a lowercase hexadecimal string.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Checks a detached OpenPGP signature with `gpg` using a throwaway keyring.
+///
+/// The keyring holds the bundled Alpine release key and any key placed in
+/// `~/.config/ALPack/keys`. A signature is
+/// only accepted when its primary key fingerprint is one of
+/// `ALPINE_KEY_FINGERPRINTS` or belongs to a user-provided key.
+///
+/// # Returns
+/// * `Ok(String)` with the fingerprint of the signing key.
+/// * `Err` if `gpg` is missing, the signature is invalid or the key is not trusted.
+fn verify_signature(tarball: &Path, signature: &Path) -> Result<String, Box<dyn Error>> {
+    let gpg = which("gpg").map_err(|_| {
+        "gpg not found in the system, it is required on the host to verify the rootfs signature.\nInstall gnupg (e.g. 'apt install gnupg' or 'apk add gnupg') or use '--skip-verify' to bypass the verification."
+    })?;
+
+    let home = env::temp_dir().join(format!("ALPack-gpg-{}", process::id()));
+    fs::create_dir_all(&home)?;
+    fs::set_permissions(&home, fs::Permissions::from_mode(0o700))?;
+
+    let result = (|| -> Result<String, Box<dyn Error>> {
+        let mut trusted: Vec<String> = ALPINE_KEY_FINGERPRINTS.iter().map(|f| f.to_string()).collect();
+
+        let user_keys = list_keys(&Settings::config_dir().join("keys"));
+        for key in &user_keys {
+            trusted.extend(key_fingerprints(&gpg, &home, key)?);
+        }
+
+        let mut keys = vec![bundled_key(&gpg, &home)?];
+        keys.extend(user_keys);
+
+        for key in &keys {
+            gpg_command(&gpg, &home)
+                .arg("--import")
+                .arg(key)
+                .status()?;
+        }
+
+        let output = gpg_command(&gpg, &home)
+            .args(["--status-fd", "1", "--verify"])
+            .arg(signature)
+            .arg(tarball)
+            .stdout(Stdio::piped())
+            .output()?;
+
+        let status = String::from_utf8_lossy(&output.stdout);
+        let signer = status
+            .lines()
+            .find_map(|l| l.strip_prefix("[GNUPG:] VALIDSIG "))
+            .and_then(|l| l.split_whitespace().last())
+            .map(|f| f.to_uppercase());
+
+        match signer {
+            Some(fpr) if output.status.success() && trusted.contains(&fpr) => Ok(fpr),
+            Some(fpr) if output.status.success() => {
+                Err(format!("Signature made by untrusted key {fpr}, refusing to continue.").into())
+            }
+            _ => Err(format!(
+                "Invalid signature for '{}', refusing to continue.",
+                tarball.display()
+            )
+            .into()),
+        }
+    })();
+
+    let _ = fs::remove_dir_all(&home);
+    result
+}
+
+/// Writes the bundled Alpine key to the gpg home directory.
+///
+/// # Returns
+/// * `Ok(PathBuf)` with the path of the key file.
+/// * `Err` if the key does not hold every fingerprint of `ALPINE_KEY_FINGERPRINTS`.
+fn bundled_key(gpg: &Path, home: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let key = home.join("ncopa.asc");
+    fs::write(&key, ALPINE_KEY)?;
+
+    let fingerprints = key_fingerprints(gpg, home, &key)?;
+    if !ALPINE_KEY_FINGERPRINTS.iter().all(|f| fingerprints.iter().any(|k| k == f)) {
+        return Err("The bundled Alpine signing key does not match its pinned fingerprint, refusing to continue.".into());
+    }
+    Ok(key)
+}
+
+/// Builds a quiet, non-interactive `gpg` command bound to the given home directory.
+fn gpg_command(gpg: &Path, home: &Path) -> StdCommand {
+    let mut cmd = StdCommand::new(gpg);
+    cmd.arg("--homedir")
+        .arg(home)
+        .args(["--batch", "--no-tty", "--quiet"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    cmd
+}
+
+/// Reads the primary key fingerprints contained in an armored key file.
+fn key_fingerprints(gpg: &Path, home: &Path, key: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let output = gpg_command(gpg, home)
+        .args(["--with-colons", "--import-options", "show-only", "--import"])
+        .arg(key)
+        .stdout(Stdio::piped())
+        .output()?;
+
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let mut fingerprints = Vec::new();
+    let mut primary = false;
+
+    for line in listing.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        match fields.first() {
+            Some(&"pub") => primary = true,
+            Some(&"sub") => primary = false,
+            Some(&"fpr") if primary => {
+                fingerprints.push(fields.get(9).unwrap_or(&"").to_uppercase());
+                primary = false;
+            }
+            _ => {}
+        }
+    }
+    Ok(fingerprints)
+}
+
+/// Lists the key files (`.asc`, `.gpg`, `.pub`) found in a directory.
+fn list_keys(dir: &Path) -> Vec<PathBuf> {
+    let mut keys: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|e| e.path())
+                .filter(|p| {
+                    matches!(
+                        p.extension().and_then(|e| e.to_str()),
+                        Some("asc") | Some("gpg") | Some("pub")
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    keys.sort();
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_key_matches_the_pinned_fingerprint() {
+        let gpg = which("gpg").expect("gpg is required to check the bundled Alpine key");
+        let home = env::temp_dir().join(format!("ALPack-key-test-{}", process::id()));
+        fs::create_dir_all(&home).unwrap();
+        fs::set_permissions(&home, fs::Permissions::from_mode(0o700)).unwrap();
+
+        let result = bundled_key(&gpg, &home).map_err(|e| e.to_string());
+        let _ = fs::remove_dir_all(&home);
+
+        assert!(!ALPINE_KEY.trim().is_empty(), "keys/ncopa.asc is empty");
+        assert!(result.is_ok(), "{}", result.unwrap_err());
+    }
+}